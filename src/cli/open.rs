@@ -24,7 +24,7 @@ pub fn run(opts: Opts) {
 
     init_menu(&mut siv);
 
-    let mut b = BrowserView::named(Rc::new(siv.cb_sink().clone()));
+    let mut b = BrowserView::named(Rc::new(siv.cb_sink().clone()), siv.current_theme());
     b.get_mut().navigate_to(start_url);
     siv.add_fullscreen_layer(b);
 
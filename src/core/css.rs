@@ -1,40 +1,230 @@
-use super::dom::node::{Node, NodeType};
+use super::dom::node::{NodeRef, NodeType};
+use super::source::{Diagnostic, SourcePosition, SourceSpan};
+use super::url::Url;
 use combine::{
-    choice,
+    attempt, between, choice, eof,
     error::StreamError,
-    error::StringStreamError,
-    many, many1, optional,
-    parser::char::{self, letter, newline, space},
-    sep_by, sep_end_by, ParseError, Parser, Stream,
+    look_ahead, many, many1, optional, parser,
+    parser::{
+        char::{self, alpha_num, letter, newline, space},
+        repeat::{skip_many, skip_many1, skip_until},
+    },
+    position, satisfy, sep_by, sep_by1,
+    stream::{easy, position as stream_position, StreamErrorFor},
+    ParseError, Parser, Stream,
 };
+use std::collections::HashSet;
 use thiserror::Error;
 
 // style
 #[derive(Debug, PartialEq)]
 pub struct Stylesheet {
+    // @import的url，按出现顺序
+    pub imports: Vec<String>,
     pub rules: Vec<Rule>,
+    // 被跳过的规则和声明
+    pub errors: Vec<Diagnostic<CSSParseError>>,
 }
 
 impl Stylesheet {
     pub fn new(rules: Vec<Rule>) -> Self {
-        Stylesheet { rules: rules }
+        Stylesheet {
+            imports: vec![],
+            rules: rules,
+            errors: vec![],
+        }
     }
 }
 
 // 规则
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    // 外层@media的条件，全部满足时规则才有效
+    pub media: Vec<MediaQueryList>,
+    // 从选择器到'}'
+    pub span: SourceSpan,
+}
+
+// 比较时忽略位置
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.selectors == other.selectors
+            && self.declarations == other.declarations
+            && self.media == other.media
+    }
 }
 
 impl Rule {
-    pub fn matches(&self, n: &Box<Node>) -> bool {
-        self.selectors.iter().any(|s| s.matches(n))
+    pub fn new(selectors: Vec<Selector>, declarations: Vec<Declaration>) -> Self {
+        Rule {
+            selectors,
+            declarations,
+            media: vec![],
+            span: SourceSpan::default(),
+        }
+    }
+
+    pub fn matches(&self, n: NodeRef, context: &SelectorContext) -> bool {
+        self.specificity(n, None, context).is_some()
+    }
+
+    // 匹配的选择器中最高的specificity，不匹配时返回None
+    // pseudo_element为Some时只看以::before等结尾的选择器
+    pub fn specificity(
+        &self,
+        n: NodeRef,
+        pseudo_element: Option<PseudoElement>,
+        context: &SelectorContext,
+    ) -> Option<Specificity> {
+        self.selectors
+            .iter()
+            .filter(|s| s.pseudo_element() == pseudo_element && s.matches(n, context))
+            .map(|s| s.specificity())
+            .max()
+    }
+}
+
+// 伪类匹配需要的页面状态
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SelectorContext {
+    // 获得焦点的元素，即NodeRef::path()
+    pub focused: Option<Vec<usize>>,
+    // 访问过的url
+    pub visited: HashSet<String>,
+    // 解析href用
+    pub base_url: Option<Url>,
+}
+
+impl SelectorContext {
+    fn is_visited(&self, href: &str) -> bool {
+        let url = match self.base_url {
+            Some(ref base) => base.join(href),
+            None => Url::parse(href),
+        };
+        url.is_ok_and(|url| self.visited.contains(url.as_str()))
     }
 }
 
-pub type Selector = SimpleSelector;
+// (id选择器数, class和属性选择器数, type选择器数)
+pub type Specificity = (usize, usize, usize);
+
+// 逗号分隔的media query，任意一个满足即可
+pub type MediaQueryList = Vec<MediaQuery>;
+
+// screen and (max-width: 600px)
+#[derive(Debug, PartialEq, Clone)]
+pub struct MediaQuery {
+    // not screen
+    pub negated: bool,
+    // 省略时为all
+    pub media_type: Option<String>,
+    pub features: Vec<MediaFeature>,
+}
+
+// (max-width: 600px) 或 (color)
+#[derive(Debug, PartialEq, Clone)]
+pub struct MediaFeature {
+    pub name: String,
+    pub value: Option<CSSValue>,
+}
+
+// 由combinator连接的选择器，如"ul > li"
+#[derive(Debug, PartialEq)]
+pub struct Selector {
+    // 最右边的部分，即匹配的元素本身
+    pub compound: CompoundSelector,
+    // 左边的部分及连接方式
+    pub combinator: Option<(Combinator, Box<Selector>)>,
+}
+
+// 不含combinator的一组选择器，如"div#main.note"，全部满足时匹配
+pub type CompoundSelector = Vec<SimpleSelector>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Combinator {
+    Descendant,        // 空白
+    Child,             // >
+    NextSibling,       // +
+    SubsequentSibling, // ~
+}
+
+impl Selector {
+    pub fn new(compound: CompoundSelector) -> Self {
+        Selector {
+            compound,
+            combinator: None,
+        }
+    }
+
+    pub fn specificity(&self) -> Specificity {
+        let (mut a, mut b, mut c) = match self.combinator {
+            Some((_, ref left)) => left.specificity(),
+            None => (0, 0, 0),
+        };
+        for s in &self.compound {
+            match s {
+                SimpleSelector::UniversalSelector => {}
+                SimpleSelector::TypeSelector { .. } => c += 1,
+                SimpleSelector::AttributeSelector { .. } => b += 1,
+                SimpleSelector::ClassSelector { .. } => b += 1,
+                SimpleSelector::IdSelector { .. } => a += 1,
+                // :is()和:not()取参数中最高的specificity
+                SimpleSelector::PseudoClassSelector {
+                    pseudo_class: PseudoClass::Is(selectors) | PseudoClass::Not(selectors),
+                } => {
+                    let (x, y, z) = selectors
+                        .iter()
+                        .map(|s| s.specificity())
+                        .max()
+                        .unwrap_or((0, 0, 0));
+                    a += x;
+                    b += y;
+                    c += z;
+                }
+                SimpleSelector::PseudoClassSelector { .. } => b += 1,
+                SimpleSelector::PseudoElementSelector { .. } => c += 1,
+            }
+        }
+        (a, b, c)
+    }
+
+    // 只能出现在最右边的部分
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        self.compound.iter().find_map(|s| match s {
+            SimpleSelector::PseudoElementSelector { pseudo_element } => Some(*pseudo_element),
+            _ => None,
+        })
+    }
+
+    // 从右向左匹配，伪元素在这里不参与判断
+    pub fn matches(&self, n: NodeRef, context: &SelectorContext) -> bool {
+        if !self.compound.iter().all(|s| s.matches(n, context)) {
+            return false;
+        }
+        let (combinator, left) = match self.combinator {
+            Some((combinator, ref left)) => (combinator, left),
+            None => return true,
+        };
+        match combinator {
+            Combinator::Descendant => {
+                std::iter::successors(n.parent_element(), |p| p.parent_element())
+                    .any(|p| left.matches(p, context))
+            }
+            Combinator::Child => n.parent_element().is_some_and(|p| left.matches(p, context)),
+            Combinator::NextSibling => n
+                .previous_element_sibling()
+                .is_some_and(|s| left.matches(s, context)),
+            Combinator::SubsequentSibling => {
+                std::iter::successors(n.previous_element_sibling(), |s| {
+                    s.previous_element_sibling()
+                })
+                .any(|s| left.matches(s, context))
+            }
+        }
+    }
+}
 
 // 选择器类型
 #[derive(Debug, PartialEq)]
@@ -45,303 +235,1644 @@ pub enum SimpleSelector {
     TypeSelector {
         tag_name: String,
     },
-    // 方法选择器
+    // 属性选择器，[href], [type="submit" i]
     AttributeSelector {
-        tag_name: String,
         op: AttributeSelectorOp,
+        // 小写
         attribute: String,
+        // Exists时为空
         value: String,
+        // i标志，值不区分大小写
+        case_insensitive: bool,
     },
     // 类型选择器
     ClassSelector {
         class_name: String,
     },
+    // id选择器
+    IdSelector {
+        id: String,
+    },
+    // :first-child, :hover等
+    PseudoClassSelector {
+        pseudo_class: PseudoClass,
+    },
+    // ::before, ::after
+    PseudoElementSelector {
+        pseudo_element: PseudoElement,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PseudoClass {
+    FirstChild,
+    LastChild,
+    // :nth-child(an+b)，从1开始数
+    NthChild { a: i64, b: i64 },
+    Not(Vec<Selector>),
+    Is(Vec<Selector>),
+    Empty,
+    // 终端没有鼠标悬停，获得焦点的元素及其祖先算作hover
+    Hover,
+    Focus,
+    Checked,
+    Disabled,
+    Link,
+    Visited,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PseudoElement {
+    Before,
+    After,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum AttributeSelectorOp {
-    Eq,      // =
-    Contain, // ~=
+    Exists,    // 只有属性名
+    Eq,        // =
+    Contain,   // ~=
+    DashMatch, // |=
+    Prefix,    // ^=
+    Suffix,    // $=
+    Substring, // *=
+}
+
+impl AttributeSelectorOp {
+    // actual为元素的属性值
+    fn matches(&self, actual: &str, value: &str) -> bool {
+        match self {
+            AttributeSelectorOp::Exists => true,
+            AttributeSelectorOp::Eq => actual == value,
+            // 空白分隔的列表中的一项，value为空或含空白时不匹配
+            AttributeSelectorOp::Contain => {
+                !value.is_empty()
+                    && !value.contains(|c: char| c.is_ascii_whitespace())
+                    && actual.split_ascii_whitespace().any(|v| v == value)
+            }
+            // 等于value或以"value-"开头，如[lang|=en]匹配en-US
+            AttributeSelectorOp::DashMatch => {
+                actual == value
+                    || actual
+                        .strip_prefix(value)
+                        .is_some_and(|rest| rest.starts_with('-'))
+            }
+            // value为空时不匹配
+            AttributeSelectorOp::Prefix => !value.is_empty() && actual.starts_with(value),
+            AttributeSelectorOp::Suffix => !value.is_empty() && actual.ends_with(value),
+            AttributeSelectorOp::Substring => !value.is_empty() && actual.contains(value),
+        }
+    }
 }
 
 impl SimpleSelector {
-    pub fn matches(&self, n: &Box<Node>) -> bool {
+    pub fn matches(&self, n: NodeRef, context: &SelectorContext) -> bool {
         match self {
             SimpleSelector::UniversalSelector => true,
-            SimpleSelector::TypeSelector { tag_name } => match n.node_type {
-                NodeType::Element(ref e) => e.tag_name.as_str() == tag_name,
+            // html元素的tag名不区分大小写
+            SimpleSelector::TypeSelector { tag_name } => match n.node.node_type {
+                NodeType::Element(ref e) => e.tag_name.eq_ignore_ascii_case(tag_name),
                 _ => false,
             },
             SimpleSelector::AttributeSelector {
-                tag_name,
                 op,
                 attribute,
                 value,
-            } => match n.node_type {
-                NodeType::Element(ref e) => {
-                    e.tag_name.as_str() == tag_name
-                        && match op {
-                            AttributeSelectorOp::Eq => e.attributes.get(attribute) == Some(value),
-                            AttributeSelectorOp::Contain => e
-                                .attributes
-                                .get(attribute)
-                                .map(|value| {
-                                    value
-                                        .split_ascii_whitespace()
-                                        .find(|v| v == value)
-                                        .is_some()
-                                })
-                                .unwrap_or(false),
-                        }
-                }
+                case_insensitive,
+            } => match n.node.node_type {
+                NodeType::Element(ref e) => match e.attributes.get(attribute) {
+                    Some(actual) if *case_insensitive => {
+                        op.matches(&actual.to_ascii_lowercase(), &value.to_ascii_lowercase())
+                    }
+                    Some(actual) => op.matches(actual, value),
+                    None => false,
+                },
+                _ => false,
+            },
+            // class属性是空白分隔的列表
+            SimpleSelector::ClassSelector { class_name } => match n.node.node_type {
+                NodeType::Element(ref e) => e.attributes.get("class").is_some_and(|classes| {
+                    classes.split_ascii_whitespace().any(|c| c == class_name)
+                }),
                 _ => false,
             },
-            SimpleSelector::ClassSelector { class_name } => match n.node_type {
-                NodeType::Element(ref e) => e.attributes.get("class") == Some(class_name),
+            SimpleSelector::IdSelector { id } => match n.node.node_type {
+                NodeType::Element(ref e) => e.attributes.get("id") == Some(id),
                 _ => false,
             },
+            SimpleSelector::PseudoClassSelector { pseudo_class } => {
+                pseudo_class.matches(n, context)
+            }
+            SimpleSelector::PseudoElementSelector { .. } => true,
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl PseudoClass {
+    pub fn matches(&self, n: NodeRef, context: &SelectorContext) -> bool {
+        let element = match n.node.node_type {
+            NodeType::Element(ref e) => e,
+            _ => return false,
+        };
+        let tag_name = element.tag_name.to_ascii_lowercase();
+        let has_attribute = |name: &str| element.attributes.contains_key(name);
+        match self {
+            PseudoClass::FirstChild => n.previous_element_sibling().is_none(),
+            PseudoClass::LastChild => n.next_element_sibling().is_none(),
+            PseudoClass::NthChild { a, b } => {
+                let index =
+                    std::iter::successors(Some(n), |s| s.previous_element_sibling()).count() as i64;
+                // 存在k >= 0使a*k + b == index
+                match *a {
+                    0 => index == *b,
+                    a => (index - b) % a == 0 && (index - b) / a >= 0,
+                }
+            }
+            PseudoClass::Not(selectors) => !selectors.iter().any(|s| s.matches(n, context)),
+            PseudoClass::Is(selectors) => selectors.iter().any(|s| s.matches(n, context)),
+            // 注释不算内容
+            PseudoClass::Empty => n.node.children.iter().all(|c| match c.node_type {
+                NodeType::Element(_) => false,
+                NodeType::Text(ref t) => t.data.is_empty(),
+                _ => true,
+            }),
+            PseudoClass::Hover => context
+                .focused
+                .as_ref()
+                .is_some_and(|focused| focused.starts_with(&n.path())),
+            PseudoClass::Focus => context.focused.as_ref() == Some(&n.path()),
+            PseudoClass::Checked => match tag_name.as_str() {
+                "input" => {
+                    let input_type = element
+                        .attributes
+                        .get("type")
+                        .map(|t| t.to_ascii_lowercase());
+                    matches!(input_type.as_deref(), Some("checkbox" | "radio"))
+                        && has_attribute("checked")
+                }
+                "option" => has_attribute("selected"),
+                _ => false,
+            },
+            PseudoClass::Disabled => {
+                matches!(
+                    tag_name.as_str(),
+                    "button" | "input" | "select" | "textarea" | "optgroup" | "option" | "fieldset"
+                ) && has_attribute("disabled")
+            }
+            PseudoClass::Link | PseudoClass::Visited => {
+                let href = match element.attributes.get("href") {
+                    Some(href) if matches!(tag_name.as_str(), "a" | "area") => href,
+                    _ => return false,
+                };
+                context.is_visited(href) == (*self == PseudoClass::Visited)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Declaration {
     pub name: String,
     pub value: CSSValue,
+    // !important
+    pub important: bool,
+    // 从属性名到值的结尾，不包括';'
+    pub span: SourceSpan,
+}
+
+impl PartialEq for Declaration {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.value == other.value && self.important == other.important
+    }
+}
+
+impl Declaration {
+    pub fn new(name: String, value: CSSValue) -> Self {
+        Declaration {
+            name,
+            value,
+            important: false,
+            span: SourceSpan::default(),
+        }
+    }
+
+    pub fn important(mut self) -> Self {
+        self.important = true;
+        self
+    }
 }
 
 // css value可以是 ’flex‘这样的字符串，也可以是10em这样的数字
 #[derive(Debug, PartialEq, Clone)]
 pub enum CSSValue {
     Keyword(String),
-    Length((usize, Unit)),
+    Length((f64, Unit)),
+    // 没有单位的数字，如 0, 1.5
+    Number(f64),
+    Color(Color),
+    // "..."
+    String(String),
+    // url(...)
+    Url(String),
+    // 空白分隔，如 1px solid red
+    List(Vec<CSSValue>),
+    // 逗号分隔，如 Arial, sans-serif
+    CommaList(Vec<CSSValue>),
+    // var(--name, fallback)，计算样式时替换
+    Var(String, Option<Box<CSSValue>>),
+    // calc(...)，计算样式时求值
+    Calc(Box<CalcExpression>),
+    // 值之间的'/'，如 font: 12px/1.5 serif
+    Slash,
+    // 含var()的简写属性展开后各属性的值: (简写属性名, 值)，计算样式时替换后再展开
+    PendingSubstitution(String, Box<CSSValue>),
 }
 
-// 单位，仅实现em
+// calc()中的表达式
 #[derive(Debug, PartialEq, Clone)]
+pub enum CalcExpression {
+    // 数字、长度或var()
+    Value(CSSValue),
+    Add(Box<CalcExpression>, Box<CalcExpression>),
+    Sub(Box<CalcExpression>, Box<CalcExpression>),
+    Mul(Box<CalcExpression>, Box<CalcExpression>),
+    Div(Box<CalcExpression>, Box<CalcExpression>),
+}
+
+// 单位
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Unit {
+    Px,
     Em,
+    Rem,
+    Ch,
+    Percent,
+    Vw,
+    Vh,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color::rgba(r, g, b, 255)
+    }
 }
 
-#[derive(Error, Debug, PartialEq)]
+#[derive(Error, Debug, PartialEq, Clone)]
 pub enum CSSParseError {
     #[error("failed to parse; {0}")]
-    InvalidResourceError(StringStreamError),
+    InvalidResourceError(String),
+
+    #[error("invalid rule skipped")]
+    InvalidRule,
+
+    #[error("invalid declaration skipped")]
+    InvalidDeclaration,
+}
+
+// 带位置信息的输入
+type PositionedStream<'a> = stream_position::Stream<&'a str, SourcePosition>;
+
+// 得到Stylesheet，失败时返回出错的位置
+pub fn parse(raw: String) -> Result<Stylesheet, Diagnostic<CSSParseError>> {
+    parse_input(stylesheet(), &raw)
+}
+
+// style属性的值，如"color: red; display: block"
+pub fn parse_declarations(raw: &str) -> Result<Vec<Declaration>, Diagnostic<CSSParseError>> {
+    parse_input(
+        (whitespaces(), declarations(), eof()).map(|(_, (declarations, _), _)| declarations),
+        raw,
+    )
+}
+
+// 单个属性的值，如"0% 0%"
+pub fn parse_value(raw: &str) -> Result<CSSValue, Diagnostic<CSSParseError>> {
+    parse_input(
+        (whitespaces(), css_value(), whitespaces(), eof()).map(|(_, value, _, _)| value),
+        raw,
+    )
 }
-// 得到Stylesheet
-pub fn parse(raw: String) -> Result<Stylesheet, CSSParseError> {
-    rules()
-        .parse(raw.as_str())
-        .map(|(rules, _)| Stylesheet::new(rules))
-        .map_err(|e| CSSParseError::InvalidResourceError(e))
+
+fn parse_input<'a, P>(mut parser: P, raw: &'a str) -> Result<P::Output, Diagnostic<CSSParseError>>
+where
+    P: Parser<easy::Stream<PositionedStream<'a>>>,
+{
+    let input = easy::Stream(PositionedStream::with_positioner(
+        raw,
+        SourcePosition::default(),
+    ));
+    parser.parse(input).map(|(output, _)| output).map_err(|e| {
+        let message = e
+            .errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Diagnostic::new(
+            CSSParseError::InvalidResourceError(message),
+            SourceSpan::new(e.position, e.position),
+        )
+    })
 }
 
-// 空格和换行符
+// 空格和换行符，注释也当作空白跳过
 fn whitespaces<Input>() -> impl Parser<Input, Output = String>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    many::<String, _, _>(space().or(newline()))
+    many::<Vec<String>, _, _>(whitespace_or_comment()).map(|s| s.concat())
+}
+
+// 至少一个空白或注释
+fn whitespaces1<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    many1::<Vec<String>, _, _>(whitespace_or_comment()).map(|s| s.concat())
+}
+
+fn whitespace_or_comment<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        space().or(newline()).map(String::from),
+        comment().map(|_| String::new()),
+    ))
 }
-// 获取所有css规则
-fn rules<Input>() -> impl Parser<Input, Output = Vec<Rule>>
+
+// /* ... */，没有结尾时到输入结束
+fn comment<Input>() -> impl Parser<Input, Output = ()>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (whitespaces(), many(rule().skip(whitespaces()))).map(|(_, rules)| rules)
+    (
+        attempt(char::string("/*")),
+        skip_until(attempt(char::string("*/")).map(|_| ()).or(eof())),
+        optional(char::string("*/")),
+    )
+        .map(|_| ())
 }
-// 获取一个css规则
-fn rule<Input>() -> impl Parser<Input, Output = Rule>
+
+// 属性名、class名等，可以包含'-'和数字: font-weight, col-2, -webkit-box
+fn identifier<Input>() -> impl Parser<Input, Output = String>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (
-        // 获取选择器
-        selectors().skip(whitespaces()),
-        char::char('{').skip(whitespaces()),
-        // 获取属性
-        declarations().skip(whitespaces()),
-        char::char('}'),
+        optional(char::char('-')),
+        letter().or(char::char('_')),
+        many::<String, _, _>(alpha_num().or(char::char('-')).or(char::char('_'))),
     )
-        .map(|(selectors, _, declarations, _)| Rule {
-            selectors: selectors,
-            declarations,
+        .map(|(dash, first, rest)| {
+            let dash = if dash.is_some() { "-" } else { "" };
+            format!("{}{}{}", dash, first, rest)
         })
 }
 
-// 解析选择器, .class or .class_1, .class_2
-fn selectors<Input>() -> impl Parser<Input, Output = Vec<Selector>>
+parser! {
+    // 错误恢复时跳过一个component value，遇到stop中的字符时停止
+    // {...} (...) [...] 整体跳过，其中的括号可以嵌套
+    fn skip_component_value[Input](stop: &'static str)(Input) -> ()
+    where [
+        Input: Stream<Token = char>,
+        Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    ]
+    {
+        let stop: &'static str = stop;
+        let block = |open, close, stop| {
+            (
+                char::char(open),
+                skip_many(skip_component_value(stop)),
+                // 没有结尾时到输入结束
+                optional(char::char(close)),
+            )
+                .map(|_| ())
+        };
+        (
+            look_ahead(satisfy(move |c: char| !stop.contains(c))),
+            choice((
+                block('{', '}', "}"),
+                block('(', ')', ")"),
+                block('[', ']', "]"),
+                quoted_string().map(|_| ()),
+                comment(),
+                satisfy(|c: char| !"{([\"'".contains(c)).map(|_| ()),
+            )),
+        )
+            .map(|_| ())
+    }
+}
+
+// 跳过{...}
+fn skip_block<Input>() -> impl Parser<Input, Output = ()>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    sep_by(
-        selector().skip(whitespaces()),
-        char::char(',').skip(whitespaces()),
+    (
+        char::char('{'),
+        skip_many(skip_component_value("}")),
+        optional(char::char('}')),
     )
+        .map(|_| ())
 }
 
-fn selector<Input>() -> impl Parser<Input, Output = Selector>
+// tag名, h1, my-element
+fn tag_name<Input>() -> impl Parser<Input, Output = String>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    simple_selector()
+    (
+        letter(),
+        many::<String, _, _>(alpha_num().or(char::char('-'))),
+    )
+        .map(|(first, rest)| format!("{}{}", first, rest).to_ascii_lowercase())
 }
 
-// 解析选择器
-fn simple_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+// @import只能出现在规则之前
+fn stylesheet<Input>() -> impl Parser<Input, Output = Stylesheet>
+where
+    Input: Stream<Token = char, Position = SourcePosition>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        whitespaces(),
+        many(attempt(import_rule()).skip(whitespaces())),
+        rules(false),
+        eof(),
+    )
+        .map(|(_, imports, (rules, errors), _)| Stylesheet {
+            imports,
+            rules,
+            errors,
+        })
+}
+
+// @import url("a.css"); 或 @import "a.css";
+fn import_rule<Input>() -> impl Parser<Input, Output = String>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    // 通用选择器以*开头
-    let universal_selector = char::char('*').map(|_| SimpleSelector::UniversalSelector);
-    // class选择器以.开头
-    let class_selector =
-        (char::char('.'), many1(letter())).map(|(_, class_name)| SimpleSelector::ClassSelector {
-            class_name: class_name,
-        });
-    // 属性选择器需要先解析出tag,再解析出属性
-    let type_or_attribute_selector = (
-        // tag
-        many1(letter()).skip(whitespaces()),
-        // [xxx=xxx] or [xxx~=xxx]
-        optional((
-            char::char('[').skip(whitespaces()),
-            many1(letter()),
-            choice((char::string("="), char::string("~="))),
-            many1(letter()),
-            char::char(']'),
+    (
+        attempt(char::string_cmp("@import", |l, r| {
+            l.eq_ignore_ascii_case(&r)
+        })),
+        whitespaces(),
+        choice((url_function(), quoted_string())),
+        whitespaces(),
+        char::char(';'),
+    )
+        .map(|(_, _, url, _, _)| url)
+}
+
+// url(a.css) 或 url("a.css")
+fn url_function<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        attempt(char::string_cmp("url(", |l, r| l.eq_ignore_ascii_case(&r))),
+        whitespaces(),
+        choice((
+            quoted_string(),
+            many1(satisfy(|c: char| c != ')' && !c.is_whitespace())),
         )),
+        whitespaces(),
+        char::char(')'),
     )
-        .and_then(|(tag_name, opts)| match opts {
-            Some((_, attribute, op, value, _)) => {
-                let op = match op {
-                    "=" => AttributeSelectorOp::Eq,
-                    "~=" => AttributeSelectorOp::Contain,
-                    _ => {
-                        return Err(<Input::Error as combine::error::ParseError<
-                            char,
-                            Input::Range,
-                            Input::Position,
-                        >>::StreamError::message_static_message(
-                            "invalid attribute selector op",
-                        ))
-                    }
-                };
-                Ok(SimpleSelector::AttributeSelector {
-                    tag_name: tag_name,
-                    attribute: attribute,
-                    op: op,
-                    value: value,
-                })
-            }
-            None => Ok(SimpleSelector::TypeSelector { tag_name: tag_name }),
-        });
+        .map(|(_, _, url, _, _)| url)
+}
 
+// "..." 或 '...'
+fn quoted_string<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
     choice((
-        universal_selector,
-        class_selector,
-        type_or_attribute_selector,
+        between(
+            char::char('"'),
+            char::char('"'),
+            many(satisfy(|c| c != '"')),
+        ),
+        between(
+            char::char('\''),
+            char::char('\''),
+            many(satisfy(|c| c != '\'')),
+        ),
     ))
 }
 
-// 匹配css每一项和；
-fn declarations<Input>() -> impl Parser<Input, Output = Vec<Declaration>>
+parser! {
+    // 获取所有css规则，跳过无法解析的规则和不支持的at-rule
+    // nested为true时在@media的{}中，遇到'}'结束
+    fn rules[Input](nested: bool)(Input) -> (Vec<Rule>, Vec<Diagnostic<CSSParseError>>)
+    where [
+        Input: Stream<Token = char, Position = SourcePosition>,
+        Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    ]
+    {
+        let stop = if *nested { "{}" } else { "{" };
+        // 选择器无效时，丢弃到{...}为止
+        // 没有选择器时只有{...}
+        let invalid_rule = (
+            position(),
+            choice((
+                skip_many1(skip_component_value(stop)).skip(optional(skip_block())),
+                skip_block(),
+            )),
+            position(),
+        )
+            .map(|(start, _, end)| {
+                let error = Diagnostic::new(CSSParseError::InvalidRule, SourceSpan::new(start, end));
+                (vec![], vec![error])
+            });
+        many::<Vec<_>, _, _>(
+            choice((
+                attempt(media_rule()),
+                at_rule().map(|_| (vec![], vec![])),
+                attempt(rule()).map(|(rule, errors)| (vec![rule], errors)),
+                invalid_rule,
+            ))
+            .skip(whitespaces()),
+        )
+        .map(|items: Vec<(Vec<Rule>, Vec<_>)>| {
+            let (rules, errors): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+            (
+                rules.into_iter().flatten().collect(),
+                errors.into_iter().flatten().collect(),
+            )
+        })
+    }
+}
+
+// @media screen and (max-width: 600px) { ... }
+fn media_rule<Input>() -> impl Parser<Input, Output = (Vec<Rule>, Vec<Diagnostic<CSSParseError>>)>
+where
+    Input: Stream<Token = char, Position = SourcePosition>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        attempt(char::string_cmp("@media", |l, r| {
+            l.eq_ignore_ascii_case(&r)
+        })),
+        whitespaces(),
+        media_query_list().skip(whitespaces()),
+        char::char('{').skip(whitespaces()),
+        rules(true),
+        char::char('}').map(|_| ()).or(eof()),
+    )
+        .map(|(_, _, queries, _, (mut rules, errors), _)| {
+            for rule in &mut rules {
+                rule.media.insert(0, queries.clone());
+            }
+            (rules, errors)
+        })
+}
+
+fn media_query_list<Input>() -> impl Parser<Input, Output = MediaQueryList>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    sep_end_by(
-        declaration().skip(whitespaces()),
-        char::char(';').skip(whitespaces()),
+    sep_by(
+        media_query(),
+        attempt((whitespaces(), char::char(','), whitespaces())),
     )
 }
 
-// 匹配css的每一项
-fn declaration<Input>() -> impl Parser<Input, Output = Declaration>
+// [not|only] type [and (feature)]* 或 (feature) [and (feature)]*
+fn media_query<Input>() -> impl Parser<Input, Output = MediaQuery>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    // 匹配 key, :, css_value
-    (
-        many1(letter()).skip(whitespaces()),
-        char::char(':').skip(whitespaces()),
-        css_value(),
+    let and = || {
+        attempt((
+            whitespaces1(),
+            char::string_cmp("and", |l, r| l.eq_ignore_ascii_case(&r)),
+            whitespaces1(),
+        ))
+    };
+    let modifier = choice((
+        char::string_cmp("not", |l, r| l.eq_ignore_ascii_case(&r)),
+        char::string_cmp("only", |l, r| l.eq_ignore_ascii_case(&r)),
+    ));
+    let with_type = (
+        optional(attempt((modifier, whitespaces1()).map(|(m, _)| m))),
+        identifier().map(|s| s.to_ascii_lowercase()),
+        many(attempt((and(), media_feature())).map(|(_, f)| f)),
+    )
+        .map(|(modifier, media_type, features)| MediaQuery {
+            negated: modifier == Some("not"),
+            media_type: Some(media_type),
+            features,
+        });
+    let without_type = (
+        media_feature(),
+        many(attempt((and(), media_feature())).map(|(_, f)| f)),
     )
-        .map(|(k, _, v)| Declaration { name: k, value: v })
+        .map(|(first, mut rest): (_, Vec<_>)| {
+            rest.insert(0, first);
+            MediaQuery {
+                negated: false,
+                media_type: None,
+                features: rest,
+            }
+        });
+    choice((without_type, with_type))
 }
 
-// 解析css的value
-fn css_value<Input>() -> impl Parser<Input, Output = CSSValue>
+fn media_feature<Input>() -> impl Parser<Input, Output = MediaFeature>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    // 匹配字母
-    let keyword = many1(letter()).map(|s| CSSValue::Keyword(s));
-    // 匹配数字加em
-    let length = (
-        many1(char::digit()).map(|s: String| s.parse::<usize>().unwrap()),
-        char::string("em"),
+    (
+        char::char('(').skip(whitespaces()),
+        identifier()
+            .map(|s| s.to_ascii_lowercase())
+            .skip(whitespaces()),
+        optional((char::char(':').skip(whitespaces()), component_value()).map(|(_, v)| v)),
+        whitespaces(),
+        char::char(')'),
     )
-        .map(|(num, _unit)| CSSValue::Length((num, Unit::Em)));
-    choice((keyword, length))
+        .map(|(_, name, value, _, _)| MediaFeature { name, value })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// 不支持的at-rule，到';'或{...}为止
+fn at_rule<Input>() -> impl Parser<Input, Output = ()>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        char::char('@'),
+        skip_many(skip_component_value(";{")),
+        choice((char::char(';').map(|_| ()), skip_block(), eof())),
+    )
+        .map(|_| ())
+}
+// 获取一个css规则和其中被跳过的声明
+fn rule<Input>() -> impl Parser<Input, Output = (Rule, Vec<Diagnostic<CSSParseError>>)>
+where
+    Input: Stream<Token = char, Position = SourcePosition>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        position(),
+        // 获取选择器
+        selectors().skip(whitespaces()),
+        char::char('{').skip(whitespaces()),
+        // 获取属性
+        declarations().skip(whitespaces()),
+        // 没有'}'时到输入结束
+        char::char('}').map(|_| ()).or(eof()),
+        position(),
+    )
+        .map(|(start, selectors, _, (declarations, errors), _, end)| {
+            let rule = Rule {
+                selectors: selectors,
+                declarations,
+                media: vec![],
+                span: SourceSpan::new(start, end),
+            };
+            (rule, errors)
+        })
+}
 
-    #[test]
-    fn test_stylesheet() {
-        assert_eq!(
-            parse("div[display=flex] { background: red; width: 1em } .rule { backgound: none;  }".to_string()),
-            Ok(Stylesheet::new(vec![
-                Rule {
-                    selectors: vec![SimpleSelector::AttributeSelector {
-                        tag_name: "div".to_string(),
-                        attribute: "display".to_string(),
-                        op: AttributeSelectorOp::Eq,
-                        value: "flex".to_string()
-                    }],
-                    declarations: vec![
-                        Declaration {
-                            name: "background".to_string(),
-                            value: CSSValue::Keyword("red".to_string())
-                        },
-                        Declaration {
-                            name: "width".to_string(),
-                            value: CSSValue::Length((1, Unit::Em)),
-                        }
-                    ]
-                },
-                Rule {
-                    selectors: vec![SimpleSelector::ClassSelector {
-                        class_name: "rule".to_string(),
-                    }],
-                    declarations: vec![Declaration {
-                        name: "backgound".to_string(),
-                        value: CSSValue::Keyword("none".to_string())
-                    }]
-                },
-            ]))
-        );
+parser! {
+    // 解析选择器, .class or .class_1, .class_2
+    // :not()和:is()的参数也是选择器列表，所以需要parser!
+    fn selectors[Input]()(Input) -> Vec<Selector>
+    where [
+        Input: Stream<Token = char>,
+        Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    ]
+    {
+        sep_by1(
+            selector().skip(whitespaces()),
+            char::char(',').skip(whitespaces()),
+        )
     }
+}
+
+// 解析 ul > li, nav a 等
+fn selector<Input>() -> impl Parser<Input, Output = Selector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        compound_selector(),
+        // 后面不是选择器时，不消费空白
+        many::<Vec<_>, _, _>(attempt((combinator(), compound_selector()))),
+    )
+        .and_then(|(first, rest)| {
+            let selector =
+                rest.into_iter()
+                    .fold(Selector::new(first), |left, (combinator, compound)| {
+                        Selector {
+                            compound,
+                            combinator: Some((combinator, Box::new(left))),
+                        }
+                    });
+            // 伪元素只能在最右边
+            let mut left = selector.combinator.as_ref().map(|(_, left)| &**left);
+            while let Some(s) = left {
+                if s.pseudo_element().is_some() {
+                    return Err(StreamErrorFor::<Input>::message_static_message(
+                        "pseudo-element must be at the end of a selector",
+                    ));
+                }
+                left = s.combinator.as_ref().map(|(_, left)| &**left);
+            }
+            Ok(selector)
+        })
+}
+
+fn combinator<Input>() -> impl Parser<Input, Output = Combinator>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let explicit = (
+        whitespaces(),
+        choice((
+            char::char('>').map(|_| Combinator::Child),
+            char::char('+').map(|_| Combinator::NextSibling),
+            char::char('~').map(|_| Combinator::SubsequentSibling),
+        )),
+        whitespaces(),
+    )
+        .map(|(_, combinator, _)| combinator);
+    let descendant = whitespaces1().map(|_| Combinator::Descendant);
+    choice((attempt(explicit), descendant))
+}
+
+// 解析 div#main.note, .a.b 等，type选择器只能在最前面
+fn compound_selector<Input>() -> impl Parser<Input, Output = CompoundSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        (simple_selector(), many(subclass_selector())).map(
+            |(first, mut rest): (_, CompoundSelector)| {
+                rest.insert(0, first);
+                rest
+            },
+        ),
+        many1(subclass_selector()),
+    ))
+}
+
+// 解析 #id, .class, [attr] 和伪类
+fn subclass_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    // id选择器以#开头
+    let id_selector =
+        (char::char('#'), identifier()).map(|(_, id)| SimpleSelector::IdSelector { id });
+    // class选择器以.开头
+    let class_selector =
+        (char::char('.'), identifier()).map(|(_, class_name)| SimpleSelector::ClassSelector {
+            class_name: class_name,
+        });
+    choice((
+        id_selector,
+        class_selector,
+        attribute_selector(),
+        pseudo_selector(),
+    ))
+}
+
+// 解析 :hover, :nth-child(2n+1), :not(.a), ::before 等
+fn pseudo_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let name = |name: &'static str| char::string_cmp(name, |l, r| l.eq_ignore_ascii_case(&r));
+    let arguments = || {
+        between(
+            char::char('(').skip(whitespaces()),
+            char::char(')'),
+            selectors(),
+        )
+    };
+    let nth = between(
+        char::char('('),
+        char::char(')'),
+        many1::<String, _, _>(satisfy(|c| c != ')')),
+    )
+    .and_then(|raw| {
+        parse_nth(&raw).ok_or_else(|| {
+            StreamErrorFor::<Input>::message_static_message("invalid :nth-child() argument")
+        })
+    });
+    let functional = choice((
+        attempt((name("not"), arguments())).map(|(_, selectors)| PseudoClass::Not(selectors)),
+        attempt((name("is"), arguments())).map(|(_, selectors)| PseudoClass::Is(selectors)),
+        attempt((name("nth-child"), nth)).map(|(_, (a, b))| PseudoClass::NthChild { a, b }),
+    ))
+    .map(|pseudo_class| SimpleSelector::PseudoClassSelector { pseudo_class });
+    let pseudo_element = identifier().and_then(|name| match name.to_ascii_lowercase().as_str() {
+        "before" => Ok(PseudoElement::Before),
+        "after" => Ok(PseudoElement::After),
+        _ => Err(StreamErrorFor::<Input>::message_static_message(
+            "unsupported pseudo-element",
+        )),
+    });
+    let keyword = identifier().and_then(|name| {
+        let pseudo_class = match name.to_ascii_lowercase().as_str() {
+            "first-child" => PseudoClass::FirstChild,
+            "last-child" => PseudoClass::LastChild,
+            "empty" => PseudoClass::Empty,
+            "hover" => PseudoClass::Hover,
+            "focus" => PseudoClass::Focus,
+            "checked" => PseudoClass::Checked,
+            "disabled" => PseudoClass::Disabled,
+            "link" => PseudoClass::Link,
+            "visited" => PseudoClass::Visited,
+            // CSS2的写法 :before, :after
+            "before" => {
+                return Ok(SimpleSelector::PseudoElementSelector {
+                    pseudo_element: PseudoElement::Before,
+                })
+            }
+            "after" => {
+                return Ok(SimpleSelector::PseudoElementSelector {
+                    pseudo_element: PseudoElement::After,
+                })
+            }
+            _ => {
+                return Err(StreamErrorFor::<Input>::message_static_message(
+                    "unsupported pseudo-class",
+                ))
+            }
+        };
+        Ok(SimpleSelector::PseudoClassSelector { pseudo_class })
+    });
+    (
+        char::char(':'),
+        choice((
+            (char::char(':'), pseudo_element).map(|(_, pseudo_element)| {
+                SimpleSelector::PseudoElementSelector { pseudo_element }
+            }),
+            functional,
+            keyword,
+        )),
+    )
+        .map(|(_, s)| s)
+}
+
+// an+b, odd, even，返回(a, b)
+fn parse_nth(raw: &str) -> Option<(i64, i64)> {
+    let raw: String = raw
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match raw.as_str() {
+        "odd" => return Some((2, 1)),
+        "even" => return Some((2, 0)),
+        _ => {}
+    }
+    let (a, b) = match raw.split_once('n') {
+        Some((a, b)) => {
+            let a = match a {
+                "" | "+" => 1,
+                "-" => -1,
+                a => a.parse().ok()?,
+            };
+            // n后面只能是空的或带符号的整数
+            let b = match b {
+                "" => 0,
+                b if b.starts_with('+') || b.starts_with('-') => b.parse().ok()?,
+                _ => return None,
+            };
+            (a, b)
+        }
+        None => (0, raw.parse().ok()?),
+    };
+    Some((a, b))
+}
+
+// 解析通用选择器和type选择器
+fn simple_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    // 通用选择器以*开头
+    let universal_selector = char::char('*').map(|_| SimpleSelector::UniversalSelector);
+    let type_selector = tag_name().map(|tag_name| SimpleSelector::TypeSelector { tag_name });
+
+    choice((universal_selector, type_selector))
+}
+
+// 解析 [href], [type=submit], [href^="https" i] 等
+fn attribute_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let op = choice((
+        char::string("="),
+        char::string("~="),
+        char::string("|="),
+        char::string("^="),
+        char::string("$="),
+        char::string("*="),
+    ))
+    .map(|op| match op {
+        "=" => AttributeSelectorOp::Eq,
+        "~=" => AttributeSelectorOp::Contain,
+        "|=" => AttributeSelectorOp::DashMatch,
+        "^=" => AttributeSelectorOp::Prefix,
+        "$=" => AttributeSelectorOp::Suffix,
+        _ => AttributeSelectorOp::Substring,
+    });
+    // 值可以是identifier或字符串，后面可以有i或s标志
+    let value = (
+        op.skip(whitespaces()),
+        choice((quoted_string(), identifier())).skip(whitespaces()),
+        optional(
+            choice((
+                char::char('i'),
+                char::char('I'),
+                char::char('s'),
+                char::char('S'),
+            ))
+            .skip(whitespaces()),
+        ),
+    );
+    (
+        char::char('[').skip(whitespaces()),
+        identifier()
+            .map(|s| s.to_ascii_lowercase())
+            .skip(whitespaces()),
+        optional(value),
+        char::char(']'),
+    )
+        .map(|(_, attribute, value, _)| match value {
+            Some((op, value, flag)) => SimpleSelector::AttributeSelector {
+                op,
+                attribute,
+                value,
+                case_insensitive: matches!(flag, Some('i' | 'I')),
+            },
+            None => SimpleSelector::AttributeSelector {
+                op: AttributeSelectorOp::Exists,
+                attribute,
+                value: String::new(),
+                case_insensitive: false,
+            },
+        })
+}
+
+// 匹配css每一项和；，无法解析的项跳过到';'或'}'为止并记录位置
+fn declarations<Input>(
+) -> impl Parser<Input, Output = (Vec<Declaration>, Vec<Diagnostic<CSSParseError>>)>
+where
+    Input: Stream<Token = char, Position = SourcePosition>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let end_of_declaration = look_ahead(choice((
+        char::char(';').map(|_| ()),
+        char::char('}').map(|_| ()),
+        eof(),
+    )));
+    let invalid_declaration = (
+        position(),
+        skip_many1(skip_component_value(";}")),
+        position(),
+    )
+        .map(|(start, _, end)| {
+            Err(Diagnostic::new(
+                CSSParseError::InvalidDeclaration,
+                SourceSpan::new(start, end),
+            ))
+        });
+    many::<Vec<_>, _, _>(
+        choice((
+            char::char(';').map(|_| None),
+            attempt(declaration().skip(whitespaces()).skip(end_of_declaration))
+                .map(|d| Some(Ok(d))),
+            invalid_declaration.map(Some),
+        ))
+        .skip(whitespaces()),
+    )
+    .map(|items: Vec<Option<Result<Declaration, _>>>| {
+        let (declarations, errors): (Vec<_>, Vec<_>) =
+            items.into_iter().flatten().partition(Result::is_ok);
+        (
+            declarations.into_iter().filter_map(Result::ok).collect(),
+            errors.into_iter().filter_map(Result::err).collect(),
+        )
+    })
+}
+
+// 匹配css的每一项
+fn declaration<Input>() -> impl Parser<Input, Output = Declaration>
+where
+    Input: Stream<Token = char, Position = SourcePosition>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    // 匹配 key, :, css_value, !important
+    (
+        position(),
+        choice((
+            custom_property_name(),
+            identifier().map(|name| name.to_ascii_lowercase()),
+        ))
+        .skip(whitespaces()),
+        char::char(':').skip(whitespaces()),
+        css_value(),
+        optional(attempt((
+            whitespaces(),
+            char::char('!'),
+            whitespaces(),
+            char::string_cmp("important", |l, r| l.eq_ignore_ascii_case(&r)),
+        ))),
+        position(),
+    )
+        .map(|(start, k, _, v, important, end)| Declaration {
+            name: k,
+            value: v,
+            important: important.is_some(),
+            span: SourceSpan::new(start, end),
+        })
+}
+
+// --brand-color，区分大小写
+fn custom_property_name<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        attempt(char::string("--")),
+        many1::<String, _, _>(alpha_num().or(char::char('-')).or(char::char('_'))),
+    )
+        .map(|(_, name)| format!("--{}", name))
+}
+
+// 解析css的value，多个值时返回List或CommaList
+fn css_value<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    sep_by1(
+        space_separated_values(),
+        attempt((whitespaces(), char::char(','), whitespaces())),
+    )
+    .map(|mut values: Vec<CSSValue>| match values.len() {
+        1 => values.pop().unwrap(),
+        _ => CSSValue::CommaList(values),
+    })
+}
+
+fn space_separated_values<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        component_value(),
+        // 后面不是值时，不消费空白
+        many(choice((
+            attempt((
+                whitespaces(),
+                char::char('/'),
+                whitespaces(),
+                component_value(),
+            ))
+            .map(|(_, _, _, v)| vec![CSSValue::Slash, v]),
+            attempt((whitespaces1(), component_value())).map(|(_, v)| vec![v]),
+        ))),
+    )
+        .map(|(first, rest): (_, Vec<Vec<_>>)| {
+            if rest.is_empty() {
+                return first;
+            }
+            CSSValue::List(
+                std::iter::once(first)
+                    .chain(rest.into_iter().flatten())
+                    .collect(),
+            )
+        })
+}
+
+fn component_value<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        url_function().map(CSSValue::Url),
+        var_function(),
+        calc_function().map(|e| CSSValue::Calc(Box::new(e))),
+        rgb_function().map(CSSValue::Color),
+        hex_color().map(CSSValue::Color),
+        quoted_string().map(CSSValue::String),
+        attempt(dimension()),
+        identifier().map(|s| CSSValue::Keyword(s.to_ascii_lowercase())),
+    ))
+}
+
+parser! {
+    // var(--name) 或 var(--name, fallback)
+    fn var_function[Input]()(Input) -> CSSValue
+    where [
+        Input: Stream<Token = char>,
+        Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    ]
+    {
+        (
+            attempt(char::string_cmp("var(", |l, r| l.eq_ignore_ascii_case(&r))),
+            whitespaces(),
+            custom_property_name().skip(whitespaces()),
+            optional((char::char(',').skip(whitespaces()), optional(css_value())))
+                .map(|fallback| fallback.and_then(|(_, v)| v)),
+            whitespaces(),
+            char::char(')'),
+        )
+            .map(|(_, _, name, fallback, _, _)| CSSValue::Var(name, fallback.map(Box::new)))
+    }
+}
+
+// calc(100% - 2em)
+fn calc_function<Input>() -> impl Parser<Input, Output = CalcExpression>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        attempt(char::string_cmp("calc(", |l, r| l.eq_ignore_ascii_case(&r))),
+        whitespaces(),
+        calc_sum(),
+        whitespaces(),
+        char::char(')'),
+    )
+        .map(|(_, _, e, _, _)| e)
+}
+
+parser! {
+    // + 和 - 的两边必须有空白
+    fn calc_sum[Input]()(Input) -> CalcExpression
+    where [
+        Input: Stream<Token = char>,
+        Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    ]
+    {
+        let operator = attempt((
+            whitespaces1(),
+            char::char('+').or(char::char('-')),
+            whitespaces1(),
+        ))
+        .map(|(_, op, _)| op);
+        (calc_product(), many::<Vec<_>, _, _>((operator, calc_product()))).map(|(first, rest)| {
+            rest.into_iter().fold(first, |left, (op, right)| match op {
+                '+' => CalcExpression::Add(Box::new(left), Box::new(right)),
+                _ => CalcExpression::Sub(Box::new(left), Box::new(right)),
+            })
+        })
+    }
+}
+
+fn calc_product<Input>() -> impl Parser<Input, Output = CalcExpression>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let operator = attempt((
+        whitespaces(),
+        char::char('*').or(char::char('/')),
+        whitespaces(),
+    ))
+    .map(|(_, op, _)| op);
+    (calc_term(), many::<Vec<_>, _, _>((operator, calc_term()))).map(|(first, rest)| {
+        rest.into_iter().fold(first, |left, (op, right)| match op {
+            '*' => CalcExpression::Mul(Box::new(left), Box::new(right)),
+            _ => CalcExpression::Div(Box::new(left), Box::new(right)),
+        })
+    })
+}
+
+// 括号、嵌套的calc()、var()或数值
+fn calc_term<Input>() -> impl Parser<Input, Output = CalcExpression>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        (
+            char::char('(').skip(whitespaces()),
+            calc_sum().skip(whitespaces()),
+            char::char(')'),
+        )
+            .map(|(_, e, _)| e),
+        calc_function(),
+        var_function().map(CalcExpression::Value),
+        dimension().map(CalcExpression::Value),
+    ))
+}
+
+// 1, -0.5, .5
+fn number<Input>() -> impl Parser<Input, Output = f64>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let digits = || many1::<String, _, _>(char::digit());
+    (
+        optional(char::char('+').or(char::char('-'))),
+        choice((
+            (digits(), optional(attempt((char::char('.'), digits())))).map(|(int, fraction)| {
+                match fraction {
+                    Some((_, fraction)) => format!("{}.{}", int, fraction),
+                    None => int,
+                }
+            }),
+            (char::char('.'), digits()).map(|(_, fraction)| format!("0.{}", fraction)),
+        )),
+    )
+        .map(|(sign, n)| {
+            let n = n.parse::<f64>().unwrap();
+            if sign == Some('-') {
+                -n
+            } else {
+                n
+            }
+        })
+}
+
+// 数字，可以带单位或%
+fn dimension<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        number(),
+        optional(choice((
+            char::char('%').map(|_| "%".to_string()),
+            identifier(),
+        ))),
+    )
+        .and_then(|(n, unit)| {
+            let unit = match unit {
+                None => return Ok(CSSValue::Number(n)),
+                Some(unit) => unit.to_ascii_lowercase(),
+            };
+            let unit = match unit.as_str() {
+                "px" => Unit::Px,
+                "em" => Unit::Em,
+                "rem" => Unit::Rem,
+                "ch" => Unit::Ch,
+                "%" => Unit::Percent,
+                "vw" => Unit::Vw,
+                "vh" => Unit::Vh,
+                _ => {
+                    return Err(StreamErrorFor::<Input>::message_static_message(
+                        "unknown unit",
+                    ))
+                }
+            };
+            Ok(CSSValue::Length((n, unit)))
+        })
+}
+
+// #rgb, #rgba, #rrggbb, #rrggbbaa
+fn hex_color<Input>() -> impl Parser<Input, Output = Color>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (char::char('#'), many1(char::hex_digit())).and_then(|(_, hex): (_, String)| {
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        match hex.len() {
+            3 => Ok(Color::rgb(digit(0) * 17, digit(1) * 17, digit(2) * 17)),
+            4 => Ok(Color::rgba(
+                digit(0) * 17,
+                digit(1) * 17,
+                digit(2) * 17,
+                digit(3) * 17,
+            )),
+            6 => Ok(Color::rgb(byte(0), byte(2), byte(4))),
+            8 => Ok(Color::rgba(byte(0), byte(2), byte(4), byte(6))),
+            _ => Err(StreamErrorFor::<Input>::message_static_message(
+                "invalid hex color",
+            )),
+        }
+    })
+}
+
+// rgb(255, 0, 0), rgba(255, 0, 0, 0.5), rgb(100% 0% 0% / 50%)
+fn rgb_function<Input>() -> impl Parser<Input, Output = Color>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    // (数值, 是否是百分比)
+    let argument = || (number(), optional(char::char('%'))).map(|(n, p)| (n, p.is_some()));
+    let separator = choice((
+        attempt((
+            whitespaces(),
+            char::char(',').or(char::char('/')),
+            whitespaces(),
+        ))
+        .map(|_| ()),
+        whitespaces1().map(|_| ()),
+    ));
+    (
+        attempt(char::string_cmp("rgba(", |l, r| l.eq_ignore_ascii_case(&r))).or(attempt(
+            char::string_cmp("rgb(", |l, r| l.eq_ignore_ascii_case(&r)),
+        )),
+        whitespaces(),
+        argument(),
+        many(attempt((separator, argument()))),
+        whitespaces(),
+        char::char(')'),
+    )
+        .and_then(|(_, _, first, rest, _, _): (_, _, _, Vec<_>, _, _)| {
+            let args: Vec<(f64, bool)> = std::iter::once(first)
+                .chain(rest.into_iter().map(|(_, arg)| arg))
+                .collect();
+            let channel = |(n, percent): (f64, bool)| {
+                let n = if percent { n * 255.0 / 100.0 } else { n };
+                n.round().clamp(0.0, 255.0) as u8
+            };
+            let alpha = |(n, percent): (f64, bool)| {
+                let n = if percent { n / 100.0 } else { n };
+                (n.clamp(0.0, 1.0) * 255.0).round() as u8
+            };
+            match args.len() {
+                3 => Ok(Color::rgb(
+                    channel(args[0]),
+                    channel(args[1]),
+                    channel(args[2]),
+                )),
+                4 => Ok(Color::rgba(
+                    channel(args[0]),
+                    channel(args[1]),
+                    channel(args[2]),
+                    alpha(args[3]),
+                )),
+                _ => Err(StreamErrorFor::<Input>::message_static_message(
+                    "invalid number of rgb() arguments",
+                )),
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::dom::element::{AttrMap, Element};
+    use combine::StreamOnce;
+
+    fn parse_positioned<'a, P>(
+        mut parser: P,
+        input: &'a str,
+    ) -> Result<(P::Output, &'a str), <PositionedStream<'a> as StreamOnce>::Error>
+    where
+        P: Parser<PositionedStream<'a>>,
+    {
+        parser
+            .parse(PositionedStream::with_positioner(
+                input,
+                SourcePosition::default(),
+            ))
+            .map(|(output, rest)| (output, rest.input))
+    }
+
+    #[test]
+    fn test_stylesheet() {
+        assert_eq!(
+            parse(
+                "div[display=flex] { background: red; width: 1em } .rule { backgound: none;  }"
+                    .to_string()
+            ),
+            Ok(Stylesheet::new(vec![
+                Rule::new(
+                    vec![Selector::new(vec![
+                        SimpleSelector::TypeSelector {
+                            tag_name: "div".to_string()
+                        },
+                        SimpleSelector::AttributeSelector {
+                            attribute: "display".to_string(),
+                            op: AttributeSelectorOp::Eq,
+                            value: "flex".to_string(),
+                            case_insensitive: false,
+                        }
+                    ])],
+                    vec![
+                        Declaration::new(
+                            "background".to_string(),
+                            CSSValue::Keyword("red".to_string())
+                        ),
+                        Declaration::new("width".to_string(), CSSValue::Length((1.0, Unit::Em)))
+                    ]
+                ),
+                Rule::new(
+                    vec![Selector::new(vec![SimpleSelector::ClassSelector {
+                        class_name: "rule".to_string(),
+                    }])],
+                    vec![Declaration::new(
+                        "backgound".to_string(),
+                        CSSValue::Keyword("none".to_string())
+                    )]
+                ),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let stylesheet = parse(
+            r#"
+            /* comment */
+            @charset "utf-8";
+            @font-face { font-family: "x"; src: url(x.woff) }
+            p /* a */ > .col-2 { font-weight: bold; /* b */ color: @; width: 1px }
+            p:unknown, a { color: red }
+            div { color: { nested; } ; display: block; ; margin: rgb(1) }
+            p..q { color: red }
+            #main-1 { Font-Style: italic }
+            { color: red }
+            p:not() { color: red }
+            "#
+            .to_string(),
+        )
+        .unwrap();
+        let summary: Vec<_> = stylesheet
+            .rules
+            .iter()
+            .map(|r| {
+                r.declarations
+                    .iter()
+                    .map(|d| d.name.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                vec!["font-weight", "width"],
+                vec!["display"],
+                vec!["font-style"]
+            ]
+        );
+        assert_eq!(
+            stylesheet.rules[0].selectors[0]
+                .combinator
+                .as_ref()
+                .unwrap()
+                .0,
+            Combinator::Child
+        );
+        assert_eq!(
+            stylesheet.rules[2].selectors[0].compound,
+            [SimpleSelector::IdSelector {
+                id: "main-1".to_string()
+            }]
+        );
+
+        // 被跳过的规则和声明的位置
+        let errors: Vec<_> = stylesheet
+            .errors
+            .iter()
+            .map(|e| {
+                (
+                    e.error.clone(),
+                    (e.span.start.line, e.span.start.column),
+                    (e.span.end.line, e.span.end.column),
+                )
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (CSSParseError::InvalidDeclaration, (5, 61), (5, 69)),
+                (CSSParseError::InvalidRule, (6, 13), (6, 40)),
+                (CSSParseError::InvalidDeclaration, (7, 19), (7, 38)),
+                (CSSParseError::InvalidDeclaration, (7, 58), (7, 73)),
+                (CSSParseError::InvalidRule, (8, 13), (8, 32)),
+                (CSSParseError::InvalidRule, (10, 13), (10, 27)),
+                (CSSParseError::InvalidRule, (11, 13), (11, 35)),
+            ]
+        );
+
+        // 没有结尾的注释和块
+        assert_eq!(
+            parse("p { color: red } /* x".to_string())
+                .unwrap()
+                .rules
+                .len(),
+            1
+        );
+        assert_eq!(
+            parse("p { color: red } a { color: blue".to_string())
+                .unwrap()
+                .rules
+                .len(),
+            2
+        );
+        // (...)中的'}'不结束规则
+        let stylesheet = parse("p { color: rgb(1 } a { color: blue }".to_string()).unwrap();
+        assert_eq!(stylesheet.rules.len(), 1);
+        assert!(stylesheet.rules[0].declarations.is_empty());
+    }
+
+    #[test]
+    fn test_media_rule() {
+        let stylesheet = parse(
+            "@media not print, (max-width: 600px) and (color) { p { color: red } bad! }\na { color: blue }"
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            stylesheet.rules[0].media,
+            [vec![
+                MediaQuery {
+                    negated: true,
+                    media_type: Some("print".to_string()),
+                    features: vec![],
+                },
+                MediaQuery {
+                    negated: false,
+                    media_type: None,
+                    features: vec![
+                        MediaFeature {
+                            name: "max-width".to_string(),
+                            value: Some(CSSValue::Length((600.0, Unit::Px))),
+                        },
+                        MediaFeature {
+                            name: "color".to_string(),
+                            value: None,
+                        },
+                    ],
+                },
+            ]]
+        );
+        // 无效的规则只影响到@media的'}'为止
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert!(stylesheet.rules[1].media.is_empty());
+    }
+
+    #[test]
+    fn test_import() {
+        let stylesheet = parse(
+            "@import url(base.css);\n@IMPORT \"a b.css\" ;@import url( 'c.css' );\np { color: red }"
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(stylesheet.imports, ["base.css", "a b.css", "c.css"]);
+        assert_eq!(stylesheet.rules.len(), 1);
+
+        // 规则之后的@import无效
+        assert!(parse("p { color: red } @import 'a.css';".to_string())
+            .unwrap()
+            .imports
+            .is_empty());
+    }
+
+    #[test]
+    fn test_whitespaces() {
+        let input_1 = "  \t\n  \r\n ";
+        let test_1 = String::from(input_1);
+        let input_2 = "  \t\n 444 \r\n ";
+        let test_2 = String::from(input_2);
+        let mut parser = whitespaces();
 
-    #[test]
-    fn test_whitespaces() {
-        let input_1 = "  \t\n  \r\n ";
-        let test_1 = String::from(input_1);
-        let input_2 = "  \t\n 444 \r\n ";
-        let test_2 = String::from(input_2);
-        let mut parser = whitespaces();
-
         assert_eq!(parser.parse(input_1), Ok((test_1, "")));
         assert_eq!(
             parser.parse(input_2),
@@ -353,23 +1884,20 @@ mod tests {
     fn test_rule() {
         let input_1 = ".test { display: flex; width: 1em }";
         assert_eq!(
-            rule().parse(input_1),
+            parse_positioned(rule().map(|(rule, _)| rule), input_1),
             Ok((
-                Rule {
-                    selectors: vec![SimpleSelector::ClassSelector {
+                Rule::new(
+                    vec![Selector::new(vec![SimpleSelector::ClassSelector {
                         class_name: "test".to_string(),
-                    }],
-                    declarations: vec![
-                        Declaration {
-                            name: "display".to_string(),
-                            value: CSSValue::Keyword("flex".to_string())
-                        },
-                        Declaration {
-                            name: "width".to_string(),
-                            value: CSSValue::Length((1, Unit::Em)),
-                        }
+                    }])],
+                    vec![
+                        Declaration::new(
+                            "display".to_string(),
+                            CSSValue::Keyword("flex".to_string())
+                        ),
+                        Declaration::new("width".to_string(), CSSValue::Length((1.0, Unit::Em)))
                     ]
-                },
+                ),
                 ""
             ))
         );
@@ -382,39 +1910,396 @@ mod tests {
             selectors().parse(input),
             Ok((
                 vec![
-                    SimpleSelector::AttributeSelector {
-                        tag_name: "a".to_string(),
-                        attribute: "display".to_string(),
-                        op: AttributeSelectorOp::Eq,
-                        value: "flex".to_string()
+                    Selector::new(vec![
+                        SimpleSelector::TypeSelector {
+                            tag_name: "a".to_string()
+                        },
+                        SimpleSelector::AttributeSelector {
+                            attribute: "display".to_string(),
+                            op: AttributeSelectorOp::Eq,
+                            value: "flex".to_string(),
+                            case_insensitive: false,
+                        }
+                    ]),
+                    Selector::new(vec![
+                        SimpleSelector::TypeSelector {
+                            tag_name: "div".to_string()
+                        },
+                        SimpleSelector::AttributeSelector {
+                            attribute: "background".to_string(),
+                            op: AttributeSelectorOp::Contain,
+                            value: "g".to_string(),
+                            case_insensitive: false,
+                        }
+                    ])
+                ],
+                "{"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_attribute_selectors() {
+        assert_eq!(
+            selectors().parse(r#"[data-x], input[ TYPE = "submit" i ] {"#),
+            Ok((
+                vec![
+                    Selector::new(vec![SimpleSelector::AttributeSelector {
+                        attribute: "data-x".to_string(),
+                        op: AttributeSelectorOp::Exists,
+                        value: "".to_string(),
+                        case_insensitive: false,
+                    }]),
+                    Selector::new(vec![
+                        SimpleSelector::TypeSelector {
+                            tag_name: "input".to_string()
+                        },
+                        SimpleSelector::AttributeSelector {
+                            attribute: "type".to_string(),
+                            op: AttributeSelectorOp::Eq,
+                            value: "submit".to_string(),
+                            case_insensitive: true,
+                        }
+                    ]),
+                ],
+                "{"
+            ))
+        );
+
+        let mut attributes = AttrMap::new();
+        attributes.insert("href".to_string(), "https://example.com/a.PDF".to_string());
+        attributes.insert("target".to_string(), "_blank".to_string());
+        attributes.insert("rel".to_string(), "noopener external".to_string());
+        attributes.insert("lang".to_string(), "en-US".to_string());
+        attributes.insert("title".to_string(), "".to_string());
+        let node = Element::new("a".to_string(), attributes, vec![]);
+        let matches = |selector: &str| {
+            let (selectors, _) = selectors().parse(selector).unwrap();
+            selectors[0].matches(NodeRef::new(&node, &[]), &SelectorContext::default())
+        };
+        assert!(matches("a[target=_blank]"));
+        assert!(matches(r#"[href^="https"]"#));
+        assert!(matches("[href$='.PDF']"));
+        assert!(!matches("[href$='.pdf']"));
+        assert!(matches("[href$='.pdf' i]"));
+        assert!(matches("[href*=example]"));
+        assert!(matches("[rel~=external]"));
+        assert!(!matches("[rel~=noopener-external]"));
+        assert!(!matches("[rel~='noopener external']"));
+        assert!(matches("[lang|=en]"));
+        assert!(!matches("[lang|=e]"));
+        assert!(matches("[title]"));
+        assert!(matches("[title='']"));
+        assert!(!matches("[title^='']"));
+        assert!(!matches("[data-x]"));
+        assert!(selectors().parse("[href!=a] {").is_err());
+    }
+
+    #[test]
+    fn test_tag_name_selectors() {
+        assert_eq!(
+            selectors().parse("h1, DIV, my-element {"),
+            Ok((
+                vec![
+                    Selector::new(vec![SimpleSelector::TypeSelector {
+                        tag_name: "h1".to_string()
+                    }]),
+                    Selector::new(vec![SimpleSelector::TypeSelector {
+                        tag_name: "div".to_string()
+                    }]),
+                    Selector::new(vec![SimpleSelector::TypeSelector {
+                        tag_name: "my-element".to_string()
+                    }]),
+                ],
+                "{"
+            ))
+        );
+
+        let node = Element::new("h2".to_string(), AttrMap::new(), vec![]);
+        assert!(SimpleSelector::TypeSelector {
+            tag_name: "H2".to_string()
+        }
+        .matches(NodeRef::new(&node, &[]), &SelectorContext::default()));
+        assert!(!SimpleSelector::TypeSelector {
+            tag_name: "h1".to_string()
+        }
+        .matches(NodeRef::new(&node, &[]), &SelectorContext::default()));
+    }
+
+    #[test]
+    fn test_compound_selectors() {
+        assert_eq!(
+            selectors().parse("div#main.note, .a.b, #x {"),
+            Ok((
+                vec![
+                    Selector::new(vec![
+                        SimpleSelector::TypeSelector {
+                            tag_name: "div".to_string()
+                        },
+                        SimpleSelector::IdSelector {
+                            id: "main".to_string()
+                        },
+                        SimpleSelector::ClassSelector {
+                            class_name: "note".to_string()
+                        },
+                    ]),
+                    Selector::new(vec![
+                        SimpleSelector::ClassSelector {
+                            class_name: "a".to_string()
+                        },
+                        SimpleSelector::ClassSelector {
+                            class_name: "b".to_string()
+                        },
+                    ]),
+                    Selector::new(vec![SimpleSelector::IdSelector {
+                        id: "x".to_string()
+                    }]),
+                ],
+                "{"
+            ))
+        );
+
+        let mut attributes = AttrMap::new();
+        attributes.insert("id".to_string(), "main".to_string());
+        attributes.insert("class".to_string(), "inline  big".to_string());
+        let node = Element::new("div".to_string(), attributes, vec![]);
+        let matches = |selector: &str| {
+            let (selectors, _) = selectors().parse(selector).unwrap();
+            selectors[0].matches(NodeRef::new(&node, &[]), &SelectorContext::default())
+        };
+        assert!(matches(".inline"));
+        assert!(matches(".big.inline"));
+        assert!(matches("div#main.big"));
+        assert!(!matches(".inline.small"));
+        assert!(!matches("p.inline"));
+        assert!(!matches("#other"));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let type_selector = |tag_name: &str| {
+            vec![SimpleSelector::TypeSelector {
+                tag_name: tag_name.to_string(),
+            }]
+        };
+        assert_eq!(
+            selectors().parse("ul > li, nav a+b ~ i {"),
+            Ok((
+                vec![
+                    Selector {
+                        compound: type_selector("li"),
+                        combinator: Some((
+                            Combinator::Child,
+                            Box::new(Selector::new(type_selector("ul")))
+                        )),
                     },
-                    SimpleSelector::AttributeSelector {
-                        tag_name: "div".to_string(),
-                        attribute: "background".to_string(),
-                        op: AttributeSelectorOp::Contain,
-                        value: "g".to_string()
-                    }
+                    Selector {
+                        compound: type_selector("i"),
+                        combinator: Some((
+                            Combinator::SubsequentSibling,
+                            Box::new(Selector {
+                                compound: type_selector("b"),
+                                combinator: Some((
+                                    Combinator::NextSibling,
+                                    Box::new(Selector {
+                                        compound: type_selector("a"),
+                                        combinator: Some((
+                                            Combinator::Descendant,
+                                            Box::new(Selector::new(type_selector("nav")))
+                                        )),
+                                    })
+                                )),
+                            })
+                        )),
+                    },
+                ],
+                "{"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_match_combinators() {
+        let element =
+            |tag_name: &str, children| Element::new(tag_name.to_string(), AttrMap::new(), children);
+        // <div><ul><li></li>text<li></li><p></p></ul></div>
+        let root = element(
+            "div",
+            vec![element(
+                "ul",
+                vec![
+                    element("li", vec![]),
+                    crate::core::dom::text::Text::new("text".to_string()),
+                    element("li", vec![]),
+                    element("p", vec![]),
                 ],
+            )],
+        );
+        let ul = &root.children[0];
+        let ancestors = [&*root, &**ul];
+        let second_li = NodeRef::new(&ul.children[2], &ancestors);
+        let p = NodeRef::new(&ul.children[3], &ancestors);
+
+        let matches = |selector: &str, node: NodeRef| {
+            let (selectors, _) = selectors().parse(selector).unwrap();
+            selectors[0].matches(node, &SelectorContext::default())
+        };
+        assert!(matches("div li", second_li));
+        assert!(matches("div > ul > li", second_li));
+        assert!(!matches("div > li", second_li));
+        // 跳过文本节点
+        assert!(matches("li + li", second_li));
+        assert!(!matches("li + p", second_li));
+        assert!(matches("li ~ p", p));
+        assert!(!matches("p ~ li", second_li));
+        assert!(matches("ul li + p", p));
+    }
+
+    #[test]
+    fn test_pseudo_selectors() {
+        assert_eq!(
+            selectors().parse("li:nth-child( 2n + 1 ):not(.a, #b)::before {"),
+            Ok((
+                vec![Selector::new(vec![
+                    SimpleSelector::TypeSelector {
+                        tag_name: "li".to_string()
+                    },
+                    SimpleSelector::PseudoClassSelector {
+                        pseudo_class: PseudoClass::NthChild { a: 2, b: 1 }
+                    },
+                    SimpleSelector::PseudoClassSelector {
+                        pseudo_class: PseudoClass::Not(vec![
+                            Selector::new(vec![SimpleSelector::ClassSelector {
+                                class_name: "a".to_string()
+                            }]),
+                            Selector::new(vec![SimpleSelector::IdSelector {
+                                id: "b".to_string()
+                            }]),
+                        ])
+                    },
+                    SimpleSelector::PseudoElementSelector {
+                        pseudo_element: PseudoElement::Before
+                    },
+                ])],
                 "{"
             ))
         );
+        // CSS2的写法
+        assert_eq!(
+            selectors().parse("p:after {").unwrap().0[0].pseudo_element(),
+            Some(PseudoElement::After)
+        );
+        assert!(selectors().parse("p:unknown {").is_err());
+        // 空的选择器列表无效
+        assert!(selectors().parse("{").is_err());
+        assert!(selectors().parse("p:is() {").is_err());
+        assert!(selectors().parse("p::before span {").is_err());
+
+        assert_eq!(parse_nth("odd"), Some((2, 1)));
+        assert_eq!(parse_nth("even"), Some((2, 0)));
+        assert_eq!(parse_nth("-n+3"), Some((-1, 3)));
+        assert_eq!(parse_nth("n"), Some((1, 0)));
+        assert_eq!(parse_nth("5"), Some((0, 5)));
+        assert_eq!(parse_nth("2n1"), None);
+
+        let (selectors, _) = selectors()
+            .parse("a:hover, :is(#a, .b) p, li:not(.x):first-child, p::after {")
+            .unwrap();
+        let specificities: Vec<_> = selectors.iter().map(|s| s.specificity()).collect();
+        assert_eq!(specificities, [(0, 1, 1), (1, 0, 1), (0, 2, 1), (0, 0, 2)]);
+    }
+
+    #[test]
+    fn test_match_pseudo_classes() {
+        let element = |tag_name: &str, attributes: &[(&str, &str)], children| {
+            let attributes = attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            Element::new(tag_name.to_string(), attributes, children)
+        };
+        // <ul><li><a href=/a></a></li><li><!----></li><li><input type=checkbox checked disabled></li></ul>
+        let root = element(
+            "ul",
+            &[],
+            vec![
+                element("li", &[], vec![element("a", &[("href", "/a")], vec![])]),
+                element(
+                    "li",
+                    &[],
+                    vec![crate::core::dom::comment::Comment::new("".to_string())],
+                ),
+                element(
+                    "li",
+                    &[],
+                    vec![element(
+                        "input",
+                        &[("type", "checkbox"), ("checked", ""), ("disabled", "")],
+                        vec![],
+                    )],
+                ),
+            ],
+        );
+        let ancestors = [&*root];
+        let li = |i: usize| NodeRef::new(&root.children[i], &ancestors);
+        let first_li_ancestors = [&*root, &*root.children[0]];
+        let a = NodeRef::new(&root.children[0].children[0], &first_li_ancestors);
+        let third_li_ancestors = [&*root, &*root.children[2]];
+        let input = NodeRef::new(&root.children[2].children[0], &third_li_ancestors);
+
+        let matches = |selector: &str, node: NodeRef, context: &SelectorContext| {
+            let (selectors, _) = selectors().parse(selector).unwrap();
+            selectors[0].matches(node, context)
+        };
+        let context = SelectorContext::default();
+        assert!(matches("li:first-child", li(0), &context));
+        assert!(!matches("li:first-child", li(1), &context));
+        assert!(matches("li:last-child", li(2), &context));
+        assert!(matches("li:nth-child(odd)", li(2), &context));
+        assert!(matches("li:nth-child(2)", li(1), &context));
+        assert!(matches("li:nth-child(-n+2)", li(1), &context));
+        assert!(!matches("li:nth-child(-n+2)", li(2), &context));
+        assert!(matches(
+            "li:not(:first-child, :last-child)",
+            li(1),
+            &context
+        ));
+        assert!(matches(":is(ol, ul) > li", li(0), &context));
+        // 注释不算内容
+        assert!(matches("li:empty", li(1), &context));
+        assert!(!matches("li:empty", li(0), &context));
+        assert!(matches("input:checked:disabled", input, &context));
+        // ::after不影响元素本身的匹配
+        assert!(matches("input::after", input, &context));
+
+        assert!(matches("a:link", a, &context));
+        assert!(!matches("a:visited", a, &context));
+        assert!(!matches("a:focus", a, &context));
+        assert!(!matches("li:hover", li(0), &context));
+        let mut visited = HashSet::new();
+        visited.insert("https://example.com/a".to_string());
+        let context = SelectorContext {
+            focused: Some(a.path()),
+            visited,
+            base_url: Url::parse("https://example.com/b/").ok(),
+        };
+        assert!(matches("a:visited:focus:hover", a, &context));
+        assert!(!matches("a:link", a, &context));
+        // 祖先元素也是hover
+        assert!(matches("li:hover", li(0), &context));
+        assert!(!matches("li:focus", li(0), &context));
+        assert!(!matches("li:hover", li(1), &context));
     }
 
     #[test]
     fn test_declarations() {
         let input = "display: flex; width: 16em; }";
         assert_eq!(
-            declarations().parse(input),
+            parse_positioned(declarations().map(|(declarations, _)| declarations), input),
             Ok((
                 vec![
-                    Declaration {
-                        name: "display".to_string(),
-                        value: CSSValue::Keyword("flex".to_string())
-                    },
-                    Declaration {
-                        name: "width".to_string(),
-                        value: CSSValue::Length((16, Unit::Em))
-                    }
+                    Declaration::new("display".to_string(), CSSValue::Keyword("flex".to_string())),
+                    Declaration::new("width".to_string(), CSSValue::Length((16.0, Unit::Em)))
                 ],
                 "}"
             ))
@@ -424,23 +2309,255 @@ mod tests {
     #[test]
     fn test_declaratione() {
         let input_1 = "display: flex";
-        let mut parser = declaration();
-
         assert_eq!(
-            parser.parse(input_1),
+            parse_positioned(declaration(), input_1),
             Ok((
-                Declaration {
-                    name: "display".to_string(),
-                    value: CSSValue::Keyword("flex".to_string()),
-                },
+                Declaration::new("display".to_string(), CSSValue::Keyword("flex".to_string())),
                 ""
             ))
         );
     }
 
+    #[test]
+    fn test_parse_declarations() {
+        assert_eq!(
+            parse_declarations(" color: red; display: block !important; "),
+            Ok(vec![
+                Declaration::new("color".to_string(), CSSValue::Keyword("red".to_string())),
+                Declaration::new(
+                    "display".to_string(),
+                    CSSValue::Keyword("block".to_string())
+                )
+                .important(),
+            ])
+        );
+        assert_eq!(parse_declarations(""), Ok(vec![]));
+        assert!(parse_declarations("color: red } p { color: blue").is_err());
+    }
+
+    #[test]
+    fn test_important() {
+        assert_eq!(
+            parse_positioned(
+                declarations().map(|(declarations, _)| declarations),
+                "color: red !important; width: 1em ! IMPORTANT }"
+            ),
+            Ok((
+                vec![
+                    Declaration::new("color".to_string(), CSSValue::Keyword("red".to_string()))
+                        .important(),
+                    Declaration::new("width".to_string(), CSSValue::Length((1.0, Unit::Em)))
+                        .important(),
+                ],
+                "}"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_specificity() {
+        let (selectors, _) = selectors()
+            .parse("*, li, ul li, ul ol+li, h1 + a[rel=up], ul ol li.red, li.red.level, #main {")
+            .unwrap();
+        let specificities: Vec<_> = selectors.iter().map(|s| s.specificity()).collect();
+        assert_eq!(
+            specificities,
+            [
+                (0, 0, 0),
+                (0, 0, 1),
+                (0, 0, 2),
+                (0, 0, 3),
+                (0, 1, 2),
+                (0, 1, 3),
+                (0, 2, 1),
+                (1, 0, 0),
+            ]
+        );
+    }
+
     #[test]
     fn test_css_value() {
         let expected = css_value().parse("1em");
-        assert_eq!(expected, Ok((CSSValue::Length((1, Unit::Em)), "")))
+        assert_eq!(expected, Ok((CSSValue::Length((1.0, Unit::Em)), "")));
+
+        let value = |input| css_value().parse(input).map(|(v, _)| v);
+        assert_eq!(value("10px"), Ok(CSSValue::Length((10.0, Unit::Px))));
+        assert_eq!(value("1.5REM"), Ok(CSSValue::Length((1.5, Unit::Rem))));
+        assert_eq!(value("-.5ch"), Ok(CSSValue::Length((-0.5, Unit::Ch))));
+        assert_eq!(value("50%"), Ok(CSSValue::Length((50.0, Unit::Percent))));
+        assert_eq!(value("100vw"), Ok(CSSValue::Length((100.0, Unit::Vw))));
+        assert_eq!(value("100vh"), Ok(CSSValue::Length((100.0, Unit::Vh))));
+        assert_eq!(value("0"), Ok(CSSValue::Number(0.0)));
+        assert_eq!(
+            value("Pre-Wrap"),
+            Ok(CSSValue::Keyword("pre-wrap".to_string()))
+        );
+        assert_eq!(value("\"a b\""), Ok(CSSValue::String("a b".to_string())));
+        assert_eq!(
+            value("url(img.png)"),
+            Ok(CSSValue::Url("img.png".to_string()))
+        );
+        assert!(value("1xx").is_err());
+    }
+
+    #[test]
+    fn test_var_and_calc() {
+        assert_eq!(
+            parse_declarations("--Brand-Color: #fff; color: var( --Brand-Color , red )"),
+            Ok(vec![
+                Declaration::new(
+                    "--Brand-Color".to_string(),
+                    CSSValue::Color(Color::rgb(255, 255, 255))
+                ),
+                Declaration::new(
+                    "color".to_string(),
+                    CSSValue::Var(
+                        "--Brand-Color".to_string(),
+                        Some(Box::new(CSSValue::Keyword("red".to_string())))
+                    )
+                ),
+            ])
+        );
+
+        let value = |input| css_value().parse(input).map(|(v, _)| v);
+        assert_eq!(
+            value("1px solid var(--c,)"),
+            Ok(CSSValue::List(vec![
+                CSSValue::Length((1.0, Unit::Px)),
+                CSSValue::Keyword("solid".to_string()),
+                CSSValue::Var("--c".to_string(), None),
+            ]))
+        );
+        let length = |n, unit| Box::new(CalcExpression::Value(CSSValue::Length((n, unit))));
+        // *和/优先于+和-
+        assert_eq!(
+            value("calc(100% - 2em * 2 + (1px - var(--x)))"),
+            Ok(CSSValue::Calc(Box::new(CalcExpression::Add(
+                Box::new(CalcExpression::Sub(
+                    length(100.0, Unit::Percent),
+                    Box::new(CalcExpression::Mul(
+                        length(2.0, Unit::Em),
+                        Box::new(CalcExpression::Value(CSSValue::Number(2.0)))
+                    ))
+                )),
+                Box::new(CalcExpression::Sub(
+                    length(1.0, Unit::Px),
+                    Box::new(CalcExpression::Value(CSSValue::Var(
+                        "--x".to_string(),
+                        None
+                    )))
+                )),
+            ))))
+        );
+        assert_eq!(
+            value("calc(calc(1px)/2)"),
+            Ok(CSSValue::Calc(Box::new(CalcExpression::Div(
+                length(1.0, Unit::Px),
+                Box::new(CalcExpression::Value(CSSValue::Number(2.0)))
+            ))))
+        );
+        // +和-两边需要空白
+        assert!(value("calc(1px -2px)").is_err());
+        assert!(value("var(brand)").is_err());
+    }
+
+    #[test]
+    fn test_color_value() {
+        let color = |input| match css_value().parse(input) {
+            Ok((CSSValue::Color(c), "")) => Some(c),
+            _ => None,
+        };
+        assert_eq!(color("#f00"), Some(Color::rgb(255, 0, 0)));
+        assert_eq!(color("#f008"), Some(Color::rgba(255, 0, 0, 136)));
+        assert_eq!(color("#00FF7f"), Some(Color::rgb(0, 255, 127)));
+        assert_eq!(color("#00ff7f80"), Some(Color::rgba(0, 255, 127, 128)));
+        assert_eq!(color("#12345"), None);
+        assert_eq!(color("rgb(255, 0, 300)"), Some(Color::rgb(255, 0, 255)));
+        assert_eq!(
+            color("RGBA( 0 , 0 , 255 , 0.5 )"),
+            Some(Color::rgba(0, 0, 255, 128))
+        );
+        assert_eq!(
+            color("rgb(100% 0% 50% / 25%)"),
+            Some(Color::rgba(255, 0, 128, 64))
+        );
+        assert_eq!(color("rgb(1, 2)"), None);
+    }
+
+    #[test]
+    fn test_value_lists() {
+        assert_eq!(
+            parse_positioned(declaration(), "border: 1px solid #000 !important"),
+            Ok((
+                Declaration::new(
+                    "border".to_string(),
+                    CSSValue::List(vec![
+                        CSSValue::Length((1.0, Unit::Px)),
+                        CSSValue::Keyword("solid".to_string()),
+                        CSSValue::Color(Color::rgb(0, 0, 0)),
+                    ])
+                )
+                .important(),
+                ""
+            ))
+        );
+        assert_eq!(
+            css_value().parse("\"Noto Sans\" , serif;"),
+            Ok((
+                CSSValue::CommaList(vec![
+                    CSSValue::String("Noto Sans".to_string()),
+                    CSSValue::Keyword("serif".to_string()),
+                ]),
+                ";"
+            ))
+        );
+        assert_eq!(
+            parse_value(" bold 12px / 1.5 serif"),
+            Ok(CSSValue::List(vec![
+                CSSValue::Keyword("bold".to_string()),
+                CSSValue::Length((12.0, Unit::Px)),
+                CSSValue::Slash,
+                CSSValue::Number(1.5),
+                CSSValue::Keyword("serif".to_string()),
+            ]))
+        );
+        assert_eq!(
+            parse_value("12px/normal"),
+            Ok(CSSValue::List(vec![
+                CSSValue::Length((12.0, Unit::Px)),
+                CSSValue::Slash,
+                CSSValue::Keyword("normal".to_string()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_source_spans() {
+        let stylesheet = parse("p {\n  color: red;\n}\ndiv { width: 1em }".to_string()).unwrap();
+        let position = |line, column, offset| SourcePosition {
+            line,
+            column,
+            offset,
+        };
+        assert_eq!(
+            stylesheet.rules[0].span,
+            SourceSpan::new(position(1, 1, 0), position(3, 2, 19))
+        );
+        assert_eq!(
+            stylesheet.rules[0].declarations[0].span,
+            SourceSpan::new(position(2, 3, 6), position(2, 13, 16))
+        );
+        assert_eq!(
+            stylesheet.rules[1].declarations[0].span,
+            SourceSpan::new(position(4, 7, 26), position(4, 17, 36))
+        );
+
+        let error = parse_declarations("color: red;\n width: 1px }").unwrap_err();
+        // 多余的'}'
+        assert_eq!(error.span.start, position(2, 13, 24));
+        assert_eq!(
+            error.to_string().split(':').take(2).collect::<Vec<_>>(),
+            ["2", "13"]
+        );
     }
 }
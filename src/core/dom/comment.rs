@@ -0,0 +1,17 @@
+use super::node::{Node, NodeType};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comment {
+    pub data: String,
+}
+
+impl Comment {
+    pub fn new(data: String) -> Box<Node> {
+        Box::new(Node {
+            node_type: NodeType::Comment(Comment { data: data }),
+            children: vec![],
+            span: None,
+            parser_id: None,
+        })
+    }
+}
@@ -1,10 +1,37 @@
 use super::node::{Node, NodeType};
+use crate::core::html::HTMLParseError;
+use crate::core::source::Diagnostic;
+use std::collections::HashMap;
+
+// 根据doctype决定的渲染模式
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum QuirksMode {
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+// 作者样式表的来源
+#[derive(Debug, PartialEq)]
+pub enum StylesheetSource {
+    // <style>的内容
+    Inline(String),
+    // <link rel="stylesheet">的href
+    External(String),
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Document {
     pub url: String,
     pub document_uri: String,
+    pub character_set: String,
+    pub doctype: Option<Box<Node>>,
     pub document_element: Box<Node>,
+    pub quirks_mode: QuirksMode,
+    pub parse_errors: Vec<Diagnostic<HTMLParseError>>,
+    // 外部样式表 url -> css，读取失败为None
+    // 由style::load_stylesheets()读取，计算样式时不访问网络
+    pub external_stylesheets: HashMap<String, Option<String>>,
 }
 
 impl Document {
@@ -12,10 +39,27 @@ impl Document {
         Document {
             url: url,
             document_uri: document_uri,
+            character_set: "UTF-8".to_string(),
+            doctype: None,
             document_element: document_element,
+            quirks_mode: QuirksMode::NoQuirks,
+            parse_errors: vec![],
+            external_stylesheets: HashMap::new(),
         }
     }
 
+    // document.compatMode
+    pub fn compat_mode(&self) -> &'static str {
+        match self.quirks_mode {
+            QuirksMode::Quirks => "BackCompat",
+            _ => "CSS1Compat",
+        }
+    }
+
+    pub fn is_quirks_mode(&self) -> bool {
+        self.quirks_mode == QuirksMode::Quirks
+    }
+
     pub fn collect_tag_inners(&self, tag_name: &str) -> Vec<String> {
         Self::intl(&self.document_element, tag_name)
     }
@@ -45,4 +89,39 @@ impl Document {
     pub fn get_style_inners(&self) -> Vec<String> {
         self.collect_tag_inners("style")
     }
+
+    // <style>和<link rel="stylesheet">，按文档中出现的顺序
+    pub fn get_stylesheet_sources(&self) -> Vec<StylesheetSource> {
+        let mut sources = vec![];
+        Self::collect_stylesheet_sources(&self.document_element, &mut sources);
+        sources
+    }
+
+    fn collect_stylesheet_sources(node: &Node, sources: &mut Vec<StylesheetSource>) {
+        if let NodeType::Element(ref element) = node.node_type {
+            match element.tag_name.as_str() {
+                "style" => {
+                    sources.push(StylesheetSource::Inline(node.inner_text()));
+                    return;
+                }
+                "link" => {
+                    // rel是空白分隔的列表，不区分大小写
+                    let is_stylesheet = element.attributes.get("rel").is_some_and(|rel| {
+                        rel.split_ascii_whitespace()
+                            .any(|r| r.eq_ignore_ascii_case("stylesheet"))
+                    });
+                    match element.attributes.get("href") {
+                        Some(href) if is_stylesheet => {
+                            sources.push(StylesheetSource::External(href.clone()))
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        for child in &node.children {
+            Self::collect_stylesheet_sources(child, sources);
+        }
+    }
 }
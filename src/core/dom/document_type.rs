@@ -0,0 +1,24 @@
+use super::node::{Node, NodeType};
+
+// <!DOCTYPE name PUBLIC "public_id" "system_id">
+#[derive(Debug, PartialEq, Clone)]
+pub struct DocumentType {
+    pub name: String,
+    pub public_id: String,
+    pub system_id: String,
+}
+
+impl DocumentType {
+    pub fn new(name: String, public_id: String, system_id: String) -> Box<Node> {
+        Box::new(Node {
+            node_type: NodeType::DocumentType(DocumentType {
+                name: name,
+                public_id: public_id,
+                system_id: system_id,
+            }),
+            children: vec![],
+            span: None,
+            parser_id: None,
+        })
+    }
+}
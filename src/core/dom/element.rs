@@ -3,7 +3,17 @@ use std::collections::HashMap;
 
 pub type AttrMap = HashMap<String, String>;
 
-#[derive(Debug, PartialEq)]
+// 没有子节点和结束标签的元素
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+pub fn is_void_element(tag_name: &str) -> bool {
+    VOID_ELEMENTS.contains(&tag_name)
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Element {
     pub tag_name: String,
     pub attributes: AttrMap,
@@ -17,9 +27,15 @@ impl Element {
                 attributes: attributes,
             }),
             children,
+            span: None,
+            parser_id: None,
         })
     }
 
+    pub fn is_void(&self) -> bool {
+        is_void_element(self.tag_name.as_str())
+    }
+
     pub fn id(&self) -> Option<&String> {
         self.attributes.get("id")
     }
@@ -1,4 +1,6 @@
+pub mod comment;
 pub mod document;
+pub mod document_type;
 pub mod element;
 pub mod node;
 pub mod text;
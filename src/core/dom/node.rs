@@ -1,17 +1,56 @@
 use std::error::Error;
 
 use crate::core::html::parse_without_normalziation;
+use crate::core::source::SourceSpan;
 
-#[derive(Debug, PartialEq)]
+const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "style",
+    "script",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "plaintext",
+    "noscript",
+];
+
+fn escape_text(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('\u{a0}', "&nbsp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_attribute(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('\u{a0}', "&nbsp;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum NodeType {
     Element(super::element::Element),
     Text(super::text::Text),
+    Comment(super::comment::Comment),
+    DocumentType(super::document_type::DocumentType),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Node {
     pub node_type: NodeType,
     pub children: Vec<Box<Node>>,
+    // 元素为开始标签的位置，文本为整段文本的位置，补全的元素为None
+    pub span: Option<SourceSpan>,
+    // 解析途中取出的文档里，对应的解析器内部节点
+    // 脚本修改文档之后用来找回还在构建中的节点
+    pub parser_id: Option<usize>,
+}
+
+// 比较时忽略位置和解析器节点
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_type == other.node_type && self.children == other.children
+    }
 }
 
 impl Node {
@@ -39,12 +78,77 @@ impl Node {
     }
 
     pub fn set_inner_html(&mut self, html: String) -> Result<(), Box<dyn Error>> {
-        let node = parse_without_normalziation(html.as_bytes().into())?;
-        self.children = node;
+        self.children = parse_without_normalziation(html.as_bytes().into());
         Ok(())
     }
 }
 
+// 选择器匹配用的节点引用，通过祖先节点访问父节点和兄弟节点
+#[derive(Debug, Clone, Copy)]
+pub struct NodeRef<'a, 'b> {
+    pub node: &'a Node,
+    // 从根节点到父节点
+    pub ancestors: &'b [&'a Node],
+}
+
+impl<'a, 'b> NodeRef<'a, 'b> {
+    pub fn new(node: &'a Node, ancestors: &'b [&'a Node]) -> Self {
+        NodeRef { node, ancestors }
+    }
+
+    pub fn parent(&self) -> Option<NodeRef<'a, 'b>> {
+        self.ancestors
+            .split_last()
+            .map(|(parent, ancestors)| NodeRef::new(parent, ancestors))
+    }
+
+    pub fn parent_element(&self) -> Option<NodeRef<'a, 'b>> {
+        self.parent()
+            .filter(|p| matches!(p.node.node_type, NodeType::Element(_)))
+    }
+
+    // 在父节点children中的位置
+    pub fn index(&self) -> Option<usize> {
+        self.ancestors.last().and_then(|parent| {
+            parent
+                .children
+                .iter()
+                .position(|child| std::ptr::eq(&**child, self.node))
+        })
+    }
+
+    pub fn previous_sibling(&self) -> Option<NodeRef<'a, 'b>> {
+        let parent = self.ancestors.last()?;
+        let index = self.index()?.checked_sub(1)?;
+        Some(NodeRef::new(&parent.children[index], self.ancestors))
+    }
+
+    pub fn previous_element_sibling(&self) -> Option<NodeRef<'a, 'b>> {
+        std::iter::successors(self.previous_sibling(), |n| n.previous_sibling())
+            .find(|n| matches!(n.node.node_type, NodeType::Element(_)))
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeRef<'a, 'b>> {
+        let parent = self.ancestors.last()?;
+        let next = parent.children.get(self.index()? + 1)?;
+        Some(NodeRef::new(next, self.ancestors))
+    }
+
+    pub fn next_element_sibling(&self) -> Option<NodeRef<'a, 'b>> {
+        std::iter::successors(self.next_sibling(), |n| n.next_sibling())
+            .find(|n| matches!(n.node.node_type, NodeType::Element(_)))
+    }
+
+    // 从根节点开始的children下标，重新解析后同一位置的节点路径不变
+    pub fn path(&self) -> Vec<usize> {
+        let mut path: Vec<usize> = std::iter::successors(Some(*self), |n| n.parent())
+            .filter_map(|n| n.index())
+            .collect();
+        path.reverse();
+        path
+    }
+}
+
 impl ToString for Node {
     fn to_string(&self) -> String {
         match self.node_type {
@@ -54,7 +158,7 @@ impl ToString for Node {
                     .iter()
                     .clone()
                     .into_iter()
-                    .map(|(k, v)| format!("{}=\"{}\"", k, v))
+                    .map(|(k, v)| format!("{}=\"{}\"", k, escape_attribute(v)))
                     .collect::<Vec<_>>()
                     .join(" ");
                 let children = self
@@ -62,16 +166,31 @@ impl ToString for Node {
                     .iter()
                     .clone()
                     .into_iter()
-                    .map(|node| node.to_string())
+                    .map(|node| match node.node_type {
+                        // <script>, <style>等的内容不转义
+                        NodeType::Text(ref t)
+                            if RAW_TEXT_ELEMENTS.contains(&e.tag_name.as_str()) =>
+                        {
+                            t.data.clone()
+                        }
+                        _ => node.to_string(),
+                    })
                     .collect::<Vec<_>>()
                     .join("");
-                if attrs != "" {
-                    format!("<{} {}>{}</{}>", e.tag_name, attrs, children, e.tag_name)
+                let open_tag = if attrs != "" {
+                    format!("<{} {}>", e.tag_name, attrs)
+                } else {
+                    format!("<{}>", e.tag_name)
+                };
+                if e.is_void() {
+                    open_tag
                 } else {
-                    format!("<{}>{}</{}>", e.tag_name, children, e.tag_name)
+                    format!("{}{}</{}>", open_tag, children, e.tag_name)
                 }
             }
-            NodeType::Text(ref t) => t.data.clone(),
+            NodeType::Text(ref t) => escape_text(&t.data),
+            NodeType::Comment(ref c) => format!("<!--{}-->", c.data),
+            NodeType::DocumentType(ref d) => format!("<!DOCTYPE {}>", d.name),
         }
     }
 }
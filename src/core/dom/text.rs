@@ -1,6 +1,6 @@
 use super::node::{Node, NodeType};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Text {
     pub data: String,
 }
@@ -10,6 +10,8 @@ impl Text {
         Box::new(Node {
             node_type: NodeType::Text(Text { data: text }),
             children: vec![],
+            span: None,
+            parser_id: None,
         })
     }
 }
@@ -0,0 +1,345 @@
+use encoding_rs::{
+    CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED,
+};
+
+// 只在前1024字节中查找<meta>
+const PRESCAN_LIMIT: usize = 1024;
+
+// bytes -> (text, encoding)
+// 优先级: BOM > Content-Type的charset > <meta>，都没有时按UTF-8处理
+// 无法解码的字节替换为U+FFFD
+pub fn decode(data: &[u8], content_type: Option<&str>) -> (String, &'static Encoding) {
+    let mut decoder = StreamDecoder::new(content_type);
+    let text = decoder.decode(data, true);
+    (text, decoder.encoding().unwrap_or(UTF_8))
+}
+
+// 分块解码，收到足够用于判断编码的字节之前不输出
+pub struct StreamDecoder {
+    charset: Option<&'static Encoding>,
+    pending: Vec<u8>,
+    decoder: Option<Decoder>,
+}
+
+impl StreamDecoder {
+    pub fn new(content_type: Option<&str>) -> Self {
+        StreamDecoder {
+            charset: content_type.and_then(extract_charset),
+            pending: vec![],
+            decoder: None,
+        }
+    }
+
+    // 还没确定时返回None
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        self.decoder.as_ref().map(|d| d.encoding())
+    }
+
+    // last为true时输出剩余的全部内容
+    pub fn decode(&mut self, chunk: &[u8], last: bool) -> String {
+        let decoder = match self.decoder {
+            Some(ref mut decoder) => decoder,
+            None => {
+                self.pending.extend_from_slice(chunk);
+                // 没有Content-Type时需要前1024字节来查找<meta>
+                if self.charset.is_none() && self.pending.len() < PRESCAN_LIMIT && !last {
+                    return String::new();
+                }
+                let fallback = self
+                    .charset
+                    .or_else(|| prescan(&self.pending))
+                    .unwrap_or(UTF_8);
+                // new_decoder()会先检查BOM
+                let pending = std::mem::take(&mut self.pending);
+                let decoder = self.decoder.insert(fallback.new_decoder());
+                return decode_to_string(decoder, &pending, last);
+            }
+        };
+        decode_to_string(decoder, chunk, last)
+    }
+}
+
+fn decode_to_string(decoder: &mut Decoder, bytes: &[u8], last: bool) -> String {
+    let capacity = decoder
+        .max_utf8_buffer_length(bytes.len())
+        .expect("chunk is too large");
+    let mut text = String::with_capacity(capacity);
+    let (result, _, _) = decoder.decode_to_string(bytes, &mut text, last);
+    debug_assert_eq!(result, CoderResult::InputEmpty);
+    text
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn find(data: &[u8], from: usize, pattern: &[u8]) -> Option<usize> {
+    data.get(from..)?
+        .windows(pattern.len())
+        .position(|w| w == pattern)
+        .map(|p| from + p)
+}
+
+fn skip_spaces(data: &[u8], pos: &mut usize) {
+    while data.get(*pos).is_some_and(|&b| is_space(b)) {
+        *pos += 1;
+    }
+}
+
+// "text/html; charset=gbk"或<meta content>中的charset
+fn extract_charset(s: &str) -> Option<&'static Encoding> {
+    let s = s.to_ascii_lowercase();
+    let b = s.as_bytes();
+    let mut pos = 0;
+    loop {
+        pos = find(b, pos, b"charset")? + "charset".len();
+        skip_spaces(b, &mut pos);
+        if b.get(pos) != Some(&b'=') {
+            continue;
+        }
+        pos += 1;
+        skip_spaces(b, &mut pos);
+        return match *b.get(pos)? {
+            q @ (b'"' | b'\'') => {
+                let end = find(b, pos + 1, &[q])?;
+                Encoding::for_label(&b[pos + 1..end])
+            }
+            _ => {
+                let end = b[pos..]
+                    .iter()
+                    .position(|&c| is_space(c) || c == b';')
+                    .map_or(b.len(), |p| pos + p);
+                Encoding::for_label(&b[pos..end])
+            }
+        };
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#prescan-a-byte-stream-to-determine-its-encoding
+fn prescan(data: &[u8]) -> Option<&'static Encoding> {
+    let data = &data[..data.len().min(PRESCAN_LIMIT)];
+    let mut pos = 0;
+    while pos < data.len() {
+        let rest = &data[pos..];
+        if rest.starts_with(b"<!--") {
+            // "<!-->"也算作结束
+            pos = find(data, pos + 2, b"-->").map_or(data.len(), |p| p + 3);
+        } else if rest.len() > 5
+            && rest[..5].eq_ignore_ascii_case(b"<meta")
+            && (is_space(rest[5]) || rest[5] == b'/')
+        {
+            pos += 5;
+            if let Some(encoding) = meta_encoding(data, &mut pos) {
+                return Some(encoding);
+            }
+        } else if rest.len() > 2
+            && rest[0] == b'<'
+            && (rest[1].is_ascii_alphabetic() || (rest[1] == b'/' && rest[2].is_ascii_alphabetic()))
+        {
+            // 跳过其他标签及其属性
+            pos += rest
+                .iter()
+                .position(|&b| is_space(b) || b == b'>')
+                .unwrap_or(rest.len());
+            while get_attribute(data, &mut pos).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos = find(data, pos, b">").map_or(data.len(), |p| p + 1);
+        } else {
+            pos += 1;
+        }
+    }
+    None
+}
+
+// <meta charset="..."> 或 <meta http-equiv="content-type" content="...">
+fn meta_encoding(data: &[u8], pos: &mut usize) -> Option<&'static Encoding> {
+    let mut names = vec![];
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+    while let Some((name, value)) = get_attribute(data, pos) {
+        if names.contains(&name) {
+            continue;
+        }
+        match name.as_str() {
+            "http-equiv" => got_pragma |= value == "content-type",
+            "content" if charset.is_none() => {
+                if let Some(encoding) = extract_charset(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            }
+            "charset" if charset.is_none() => {
+                charset = Encoding::for_label(value.as_bytes());
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        names.push(name);
+    }
+    match need_pragma {
+        Some(true) if !got_pragma => None,
+        None => None,
+        _ => match charset? {
+            // 能读到<meta>说明文档是ASCII兼容的
+            e if e == UTF_16BE || e == UTF_16LE => Some(UTF_8),
+            e if e == X_USER_DEFINED => Some(WINDOWS_1252),
+            e => Some(e),
+        },
+    }
+}
+
+// 返回小写的(name, value)，遇到'>'或输入结束时返回None
+fn get_attribute(data: &[u8], pos: &mut usize) -> Option<(String, String)> {
+    while data.get(*pos).is_some_and(|&b| is_space(b) || b == b'/') {
+        *pos += 1;
+    }
+    let mut name = vec![];
+    loop {
+        match *data.get(*pos)? {
+            b'>' if name.is_empty() => return None,
+            b'=' if !name.is_empty() => break,
+            b if is_space(b) => {
+                skip_spaces(data, pos);
+                if data.get(*pos) != Some(&b'=') {
+                    return Some((into_string(name), String::new()));
+                }
+                break;
+            }
+            b'/' | b'>' => return Some((into_string(name), String::new())),
+            b => name.push(b.to_ascii_lowercase()),
+        }
+        *pos += 1;
+    }
+    // 跳过'='
+    *pos += 1;
+    skip_spaces(data, pos);
+
+    let mut value = vec![];
+    match *data.get(*pos)? {
+        q @ (b'"' | b'\'') => {
+            *pos += 1;
+            loop {
+                let b = *data.get(*pos)?;
+                *pos += 1;
+                if b == q {
+                    break;
+                }
+                value.push(b.to_ascii_lowercase());
+            }
+        }
+        b'>' => {}
+        _ => {
+            while let Some(&b) = data.get(*pos) {
+                if is_space(b) || b == b'>' {
+                    break;
+                }
+                value.push(b.to_ascii_lowercase());
+                *pos += 1;
+            }
+        }
+    }
+    Some((into_string(name), into_string(value)))
+}
+
+fn into_string(bytes: Vec<u8>) -> String {
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, SHIFT_JIS, WINDOWS_1251};
+
+    #[test]
+    fn test_decode_bom() {
+        assert_eq!(
+            decode(b"\xef\xbb\xbf<p>a</p>", Some("text/html; charset=gbk")),
+            ("<p>a</p>".to_string(), UTF_8)
+        );
+        assert_eq!(
+            decode(b"\xff\xfe<\x00p\x00>\x00", None),
+            ("<p>".to_string(), UTF_16LE)
+        );
+    }
+
+    #[test]
+    fn test_decode_content_type() {
+        assert_eq!(
+            decode(b"caf\xe9", Some("text/html; charset=ISO-8859-1")),
+            ("café".to_string(), WINDOWS_1252)
+        );
+        assert_eq!(
+            decode(b"\xc4\xe3\xba\xc3", Some("text/html;charset=\"GBK\"")),
+            ("你好".to_string(), GBK)
+        );
+        // header优先于<meta>
+        assert_eq!(
+            decode(b"<meta charset=gbk>\xe9", Some("text/html; charset=latin1")).1,
+            WINDOWS_1252
+        );
+    }
+
+    #[test]
+    fn test_decode_meta() {
+        assert_eq!(
+            decode(
+                b"<!DOCTYPE html><meta charset=\"Shift_JIS\"><p>\x82\xa0</p>",
+                None
+            ),
+            (
+                "<!DOCTYPE html><meta charset=\"Shift_JIS\"><p>あ</p>".to_string(),
+                SHIFT_JIS
+            )
+        );
+        assert_eq!(
+            decode(
+                b"<meta http-equiv=Content-Type content='text/html; charset=windows-1251'>",
+                None
+            )
+            .1,
+            WINDOWS_1251
+        );
+        // 没有http-equiv时忽略content
+        assert_eq!(
+            decode(b"<meta content='text/html; charset=windows-1251'>", None).1,
+            UTF_8
+        );
+        // 注释和其他标签属性中的charset不生效
+        assert_eq!(
+            decode(
+                b"<!-- <meta charset=gbk> --><p title='<meta charset=gbk>'>",
+                None
+            )
+            .1,
+            UTF_8
+        );
+        assert_eq!(decode(b"<meta charset=utf-16le>", None).1, UTF_8);
+    }
+
+    #[test]
+    fn test_decode_invalid_bytes() {
+        assert_eq!(
+            decode(b"<p>a\xffb\xe3\x81</p>", None),
+            ("<p>a\u{fffd}b\u{fffd}</p>".to_string(), UTF_8)
+        );
+    }
+
+    #[test]
+    fn test_stream_decoder() {
+        let mut decoder = StreamDecoder::new(None);
+        // 不足1024字节时等待<meta>
+        assert_eq!(decoder.decode(b"<meta charset=gbk><p>\xc4", false), "");
+        assert_eq!(decoder.encoding(), None);
+        assert_eq!(
+            decoder.decode(b"\xe3\xba", true),
+            "<meta charset=gbk><p>\u{4f60}\u{fffd}"
+        );
+        assert_eq!(decoder.encoding(), Some(GBK));
+
+        // Content-Type确定时立即输出，多字节字符可以跨块
+        let mut decoder = StreamDecoder::new(Some("text/html; charset=utf-8"));
+        assert_eq!(decoder.decode(b"a\xe3\x81", false), "a");
+        assert_eq!(decoder.decode(b"\x82b", true), "\u{3042}b");
+    }
+}
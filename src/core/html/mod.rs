@@ -0,0 +1,206 @@
+mod encoding;
+mod entities;
+mod parser;
+mod tokenizer;
+mod tree_builder;
+
+pub use self::parser::{HTMLParser, ParseStatus};
+use self::tokenizer::Tokenizer;
+use self::tree_builder::TreeBuilder;
+use super::dom::{document::Document, node::Node};
+use super::fetch::Response;
+use thiserror::Error;
+
+// 解析不会失败，遇到的错误和位置记录在Document::parse_errors中
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum HTMLParseError {
+    #[error("invalid markup; {0}")]
+    TokenizerError(&'static str),
+    #[error("unexpected start tag <{0}>")]
+    UnexpectedStartTag(String),
+    #[error("unexpected end tag </{0}>")]
+    UnexpectedEndTag(String),
+    #[error("element <{0}> is not closed")]
+    UnclosedElement(String),
+    #[error("non-void element <{0}> cannot be self-closing")]
+    NonVoidSelfClosingTag(String),
+    #[error("misnested tag <{0}>")]
+    MisnestedTag(String),
+    #[error("unexpected content after </body>")]
+    UnexpectedContentAfterBody,
+    #[error("missing doctype")]
+    MissingDoctype,
+    #[error("invalid doctype")]
+    InvalidDoctype,
+    #[error("unexpected doctype")]
+    UnexpectedDoctype,
+}
+
+pub fn parse(response: Response) -> Document {
+    let mut parser = HTMLParser::new(response.url, &response.headers);
+    parser.push(&response.data);
+    parser.end();
+    // 不执行脚本
+    while parser.run() != ParseStatus::Finished {}
+    parser.finish()
+}
+
+// data -> nodes
+pub fn parse_without_normalziation(data: Vec<u8>) -> Vec<Box<Node>> {
+    let (body, _) = encoding::decode(&data, None);
+    let mut builder = TreeBuilder::new_fragment();
+    let mut tokenizer = Tokenizer::new(&body);
+    run(&mut tokenizer, &mut builder);
+
+    builder.finish_fragment()
+}
+
+fn run(tokenizer: &mut Tokenizer, builder: &mut TreeBuilder) {
+    while !builder.is_stopped() {
+        let (token, span) = tokenizer
+            .next_token()
+            .expect("the whole input is given at once");
+        builder.add_errors(tokenizer.take_errors());
+        builder.process(token, span);
+        if let Some(state) = builder.take_tokenizer_state() {
+            tokenizer.set_state(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::dom::{
+        document_type::DocumentType,
+        element::{AttrMap, Element},
+        text::Text,
+    };
+    use crate::core::fetch::{HTTPStatus, HeaderMap, ResponseType};
+    use url::Url;
+
+    fn response(html: &str) -> Response {
+        Response {
+            rtype: ResponseType::Basic,
+            url: Url::parse("file:///index.html").unwrap(),
+            status: HTTPStatus::OK,
+            headers: HeaderMap::new(),
+            data: html.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let document = parse(response("<!DOCTYPE html><p id=\"test\">Hello World</p>"));
+        let mut attributes = AttrMap::new();
+        attributes.insert("id".to_string(), "test".to_string());
+        assert_eq!(
+            document.document_element,
+            Element::new(
+                "html".to_string(),
+                AttrMap::new(),
+                vec![
+                    Element::new("head".to_string(), AttrMap::new(), vec![]),
+                    Element::new(
+                        "body".to_string(),
+                        AttrMap::new(),
+                        vec![Element::new(
+                            "p".to_string(),
+                            attributes,
+                            vec![Text::new("Hello World".to_string())]
+                        )]
+                    ),
+                ]
+            )
+        );
+        assert_eq!(document.parse_errors, vec![]);
+        assert_eq!(
+            document.doctype,
+            Some(DocumentType::new(
+                "html".to_string(),
+                "".to_string(),
+                "".to_string()
+            ))
+        );
+        assert!(!document.is_quirks_mode());
+        assert_eq!(document.character_set, "UTF-8");
+    }
+
+    #[test]
+    fn test_parse_legacy_encoding() {
+        let mut response = response("");
+        response.data = b"<!DOCTYPE html><p>caf\xe9</p>".to_vec();
+        response.headers.insert(
+            "Content-Type".to_string(),
+            "text/html; charset=latin1".to_string(),
+        );
+        let document = parse(response);
+        assert_eq!(document.character_set, "windows-1252");
+        assert_eq!(document.document_element.inner_text(), "café");
+
+        let nodes = parse_without_normalziation(b"<p>caf\xe9</p>".to_vec());
+        assert_eq!(nodes[0].inner_text(), "caf\u{fffd}");
+    }
+
+    #[test]
+    fn test_parse_recovers_from_errors() {
+        let document = parse(response("<p>Hello World</div>"));
+        assert!(document.is_quirks_mode());
+        assert_eq!(
+            document.document_element.to_string(),
+            "<html><head></head><body><p>Hello World</p></body></html>"
+        );
+        assert_eq!(
+            document
+                .parse_errors
+                .iter()
+                .map(|e| e.error.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                HTMLParseError::MissingDoctype,
+                HTMLParseError::UnexpectedEndTag("div".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_serialize_character_references() {
+        let nodes = parse_without_normalziation(
+            "<p title=\"&quot;a&amp;b&quot;\">&lt;b&gt; &amp;&nbsp;&#x2014;</p><script>a && b < c</script>"
+                .as_bytes()
+                .to_vec(),
+        );
+        assert_eq!(nodes[0].inner_text(), "<b> &\u{a0}\u{2014}");
+        assert_eq!(
+            nodes
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(""),
+            "<p title=\"&quot;a&amp;b&quot;\">&lt;b&gt; &amp;&nbsp;\u{2014}</p><script>a && b < c</script>"
+        );
+    }
+
+    #[test]
+    fn test_parse_without_normalziation() {
+        assert_eq!(
+            parse_without_normalziation("<p>a</p>b".as_bytes().to_vec()),
+            vec![
+                Element::new(
+                    "p".to_string(),
+                    AttrMap::new(),
+                    vec![Text::new("a".to_string())]
+                ),
+                Text::new("b".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_without_normalziation("<i>a<b>b</i>c".as_bytes().to_vec())
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(""),
+            "<i>a<b>b</b></i><b>c</b>"
+        );
+    }
+}
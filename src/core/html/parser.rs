@@ -0,0 +1,238 @@
+use super::encoding::StreamDecoder;
+use super::tokenizer::Tokenizer;
+use super::tree_builder::TreeBuilder;
+use super::HTMLParseError;
+use crate::core::dom::{document::Document, node::Node};
+use crate::core::fetch::HeaderMap;
+use crate::core::source::Diagnostic;
+use url::Url;
+
+// run()停下的原因
+#[derive(Debug, PartialEq)]
+pub enum ParseStatus {
+    // 已有的输入都处理完了，需要push()或end()
+    NeedsInput,
+    // 解析到</script>，执行完脚本后再继续run()
+    Script(String),
+    Finished,
+}
+
+// 分块接收数据的解析器，解析途中可以用document()取得已经构建的部分
+pub struct HTMLParser {
+    url: Url,
+    decoder: StreamDecoder,
+    tokenizer: Tokenizer,
+    builder: TreeBuilder,
+}
+
+impl HTMLParser {
+    pub fn new(url: Url, headers: &HeaderMap) -> Self {
+        let content_type = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.as_str());
+        HTMLParser {
+            url,
+            decoder: StreamDecoder::new(content_type),
+            tokenizer: Tokenizer::incremental(),
+            builder: TreeBuilder::new(),
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        let text = self.decoder.decode(chunk, false);
+        self.tokenizer.feed(&text);
+    }
+
+    // 输入结束
+    pub fn end(&mut self) {
+        let text = self.decoder.decode(&[], true);
+        self.tokenizer.feed(&text);
+        self.tokenizer.end();
+    }
+
+    pub fn run(&mut self) -> ParseStatus {
+        while !self.builder.is_stopped() {
+            let (token, span) = match self.tokenizer.next_token() {
+                Some(token) => token,
+                None => return ParseStatus::NeedsInput,
+            };
+            self.builder.add_errors(self.tokenizer.take_errors());
+            self.builder.process(token, span);
+            if let Some(state) = self.builder.take_tokenizer_state() {
+                self.tokenizer.set_state(state);
+            }
+            if let Some(script) = self.builder.take_script() {
+                return ParseStatus::Script(script);
+            }
+        }
+        ParseStatus::Finished
+    }
+
+    pub fn errors(&self) -> &[Diagnostic<HTMLParseError>] {
+        self.builder.errors()
+    }
+
+    // 当前已经构建的部分
+    pub fn document(&self) -> Document {
+        let (doctype, document_element) = self.builder.snapshot();
+        let mut document = self.new_document(doctype, document_element);
+        document.quirks_mode = self.builder.quirks_mode();
+        document.parse_errors = self.errors().to_vec();
+        document
+    }
+
+    // 脚本对document()返回的文档做的修改，需要交给解析器才能保留
+    pub fn update_document(&mut self, document: &Document) {
+        self.builder
+            .import_document_element(&document.document_element);
+    }
+
+    pub fn finish(mut self) -> Document {
+        let parse_errors = self.builder.take_errors();
+        let quirks_mode = self.builder.quirks_mode();
+        let (doctype, document_element) =
+            std::mem::replace(&mut self.builder, TreeBuilder::new()).finish();
+        let mut document = self.new_document(doctype, document_element);
+        document.quirks_mode = quirks_mode;
+        document.parse_errors = parse_errors;
+        document
+    }
+
+    fn new_document(&self, doctype: Option<Box<Node>>, document_element: Box<Node>) -> Document {
+        let url = self.url.to_string();
+        let mut document = Document::new(url.clone(), url, document_element);
+        document.doctype = doctype;
+        if let Some(encoding) = self.decoder.encoding() {
+            document.character_set = encoding.name().to_string();
+        }
+        document
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::dom::element::{AttrMap, Element};
+    use crate::core::dom::text::Text;
+
+    fn parser() -> HTMLParser {
+        HTMLParser::new(Url::parse("file:///index.html").unwrap(), &HeaderMap::new())
+    }
+
+    #[test]
+    fn test_parse_in_chunks() {
+        let mut parser = parser();
+        assert_eq!(parser.run(), ParseStatus::NeedsInput);
+        assert_eq!(
+            parser.document().document_element.to_string(),
+            "<html></html>"
+        );
+
+        // UTF-8的多字节字符跨块
+        let html = "<!DOCTYPE html><p>\u{3042}</p><div>b".as_bytes();
+        parser.push(&html[..19]);
+        parser.push(&html[19..]);
+        parser.end();
+        assert_eq!(parser.run(), ParseStatus::Finished);
+        let document = parser.finish();
+        assert_eq!(
+            document.document_element.to_string(),
+            "<html><head></head><body><p>\u{3042}</p><div>b</div></body></html>"
+        );
+        assert!(!document.is_quirks_mode());
+        assert_eq!(document.character_set, "UTF-8");
+    }
+
+    #[test]
+    fn test_pause_at_script() {
+        let mut parser = parser();
+        parser.push(b"<!DOCTYPE html><p id=a>x</p><script>document.x()</script><p>y</p>");
+        parser.end();
+        assert_eq!(
+            parser.run(),
+            ParseStatus::Script("document.x()".to_string())
+        );
+        // 脚本只能看到它之前的内容
+        let mut document = parser.document();
+        assert_eq!(
+            document.document_element.to_string(),
+            "<html><head></head><body><p id=\"a\">x</p><script>document.x()</script></body></html>"
+        );
+
+        // 脚本对文档的修改保留下来
+        let body = &mut document.document_element.children[1];
+        body.children[0].children = vec![Text::new("z".to_string())];
+        parser.update_document(&document);
+        assert_eq!(parser.run(), ParseStatus::Finished);
+        assert_eq!(
+            parser.finish().document_element.to_string(),
+            "<html><head></head><body><p id=\"a\">z</p><script>document.x()</script><p>y</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_continue_after_failed_script() {
+        let mut parser = parser();
+        parser.push(b"<script>throw 1</script><script>f()</script><p>after</p>");
+        parser.end();
+        assert_eq!(parser.run(), ParseStatus::Script("throw 1".to_string()));
+        // 脚本出错时也交回文档，之后的内容和脚本照常处理
+        let document = parser.document();
+        parser.update_document(&document);
+        assert_eq!(parser.run(), ParseStatus::Script("f()".to_string()));
+        assert_eq!(parser.run(), ParseStatus::Finished);
+        assert_eq!(
+            parser.finish().document_element.to_string(),
+            "<html><head><script>throw 1</script><script>f()</script></head><body><p>after</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_insert_before_open_element() {
+        // 指定了charset时不等待<meta>，收到的内容马上被解析
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Content-Type".to_string(),
+            "text/html; charset=utf-8".to_string(),
+        );
+        let mut parser = HTMLParser::new(Url::parse("file:///index.html").unwrap(), &headers);
+        parser.push(b"<body><div id=main>a");
+        assert_eq!(parser.run(), ParseStatus::NeedsInput);
+
+        // 脚本在还没有关闭的<div id=main>前插入<div>
+        let mut document = parser.document();
+        let body = &mut document.document_element.children[1];
+        let mut attributes = AttrMap::new();
+        attributes.insert("id".to_string(), "banner".to_string());
+        body.children
+            .insert(0, Element::new("div".to_string(), attributes, vec![]));
+        parser.update_document(&document);
+
+        parser.push(b"b</div><p>c</p>");
+        parser.end();
+        assert_eq!(parser.run(), ParseStatus::Finished);
+        assert_eq!(
+            parser.finish().document_element.to_string(),
+            "<html><head></head><body><div id=\"banner\"></div><div id=\"main\">ab</div><p>c</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_head_removed_by_script() {
+        let mut parser = parser();
+        parser.push(b"<head></head><script>s()</script><meta charset=utf-8><p>x</p>");
+        parser.end();
+        assert_eq!(parser.run(), ParseStatus::Script("s()".to_string()));
+
+        // 脚本移除<head>之后，<head>后面的<meta>插入到当前节点
+        let mut document = parser.document();
+        document.document_element.children.remove(0);
+        parser.update_document(&document);
+        assert_eq!(parser.run(), ParseStatus::Finished);
+        assert_eq!(
+            parser.finish().document_element.to_string(),
+            "<html><meta charset=\"utf-8\"><body><p>x</p></body></html>"
+        );
+    }
+}
@@ -0,0 +1,1406 @@
+use super::entities::NAMED_CHARACTER_REFERENCES;
+use super::HTMLParseError;
+use crate::core::dom::element::AttrMap;
+use crate::core::source::{Diagnostic, SourcePosition, SourceSpan};
+use std::collections::VecDeque;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    StartTag {
+        tag_name: String,
+        attributes: AttrMap,
+        self_closing: bool,
+    },
+    EndTag {
+        tag_name: String,
+    },
+    Comment(String),
+    Doctype {
+        name: Option<String>,
+        public_id: Option<String>,
+        system_id: Option<String>,
+        force_quirks: bool,
+    },
+    Character(char),
+    Eof,
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum State {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    TextLessThanSign(TextContent),
+    TextEndTagOpen(TextContent),
+    TextEndTagName(TextContent),
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypeKeyword(DoctypeId),
+    BeforeDoctypeIdentifier(DoctypeId),
+    DoctypeIdentifierQuoted(DoctypeId, char),
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+}
+
+// 内容一直持续到对应结束标签的元素
+// script data的<!-- -->转义没有实现，按RAWTEXT处理
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TextContent {
+    // <textarea>, <title>, 解析字符引用
+    Rcdata,
+    // <style>, <xmp>, <iframe>等
+    Rawtext,
+    // <script>
+    ScriptData,
+}
+
+impl TextContent {
+    pub fn state(&self) -> State {
+        match self {
+            TextContent::Rcdata => State::Rcdata,
+            TextContent::Rawtext => State::Rawtext,
+            TextContent::ScriptData => State::ScriptData,
+        }
+    }
+}
+
+// PUBLIC或SYSTEM
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DoctypeId {
+    Public,
+    System,
+}
+
+// 正在解析的tag
+#[derive(Debug, Default)]
+struct TagBuilder {
+    is_end_tag: bool,
+    self_closing: bool,
+    tag_name: String,
+    attributes: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+struct DoctypeBuilder {
+    name: Option<String>,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    force_quirks: bool,
+}
+
+pub struct Tokenizer {
+    input: Vec<char>,
+    // 每个字符在原文中的位置
+    positions: Vec<SourcePosition>,
+    // 已输入内容结束的位置
+    end_position: SourcePosition,
+    // 上一块输入以\r结尾，下一块开头的\n需要跳过
+    pending_cr: bool,
+    // 输入是否已经全部给出
+    ended: bool,
+    pos: usize,
+    // 当前token开始的字符
+    token_start: usize,
+    state: State,
+    tag: TagBuilder,
+    last_start_tag_name: String,
+    temporary_buffer: String,
+    comment: String,
+    doctype: DoctypeBuilder,
+    tokens: VecDeque<(Token, SourceSpan)>,
+    errors: Vec<Diagnostic<HTMLParseError>>,
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | ' ')
+}
+
+// 最长的实体名称为"CounterClockwiseContourIntegral;"
+const LONGEST_CHARACTER_REFERENCE: usize = 32;
+
+// &#x80;~&#x9F;按windows-1252解释
+const C1_REPLACEMENTS: &[(u32, char)] = &[
+    (0x80, '\u{20AC}'),
+    (0x82, '\u{201A}'),
+    (0x83, '\u{0192}'),
+    (0x84, '\u{201E}'),
+    (0x85, '\u{2026}'),
+    (0x86, '\u{2020}'),
+    (0x87, '\u{2021}'),
+    (0x88, '\u{02C6}'),
+    (0x89, '\u{2030}'),
+    (0x8A, '\u{0160}'),
+    (0x8B, '\u{2039}'),
+    (0x8C, '\u{0152}'),
+    (0x8E, '\u{017D}'),
+    (0x91, '\u{2018}'),
+    (0x92, '\u{2019}'),
+    (0x93, '\u{201C}'),
+    (0x94, '\u{201D}'),
+    (0x95, '\u{2022}'),
+    (0x96, '\u{2013}'),
+    (0x97, '\u{2014}'),
+    (0x98, '\u{02DC}'),
+    (0x99, '\u{2122}'),
+    (0x9A, '\u{0161}'),
+    (0x9B, '\u{203A}'),
+    (0x9C, '\u{0153}'),
+    (0x9E, '\u{017E}'),
+    (0x9F, '\u{0178}'),
+];
+
+fn is_noncharacter(n: u32) -> bool {
+    (0xFDD0..=0xFDEF).contains(&n) || (n & 0xFFFE) == 0xFFFE
+}
+
+impl Tokenizer {
+    // 一次给出全部输入
+    pub fn new(input: &str) -> Self {
+        let mut tokenizer = Self::incremental();
+        tokenizer.feed(input);
+        tokenizer.end();
+        tokenizer
+    }
+
+    // 之后用feed()追加输入，end()结束
+    pub fn incremental() -> Self {
+        Tokenizer {
+            input: vec![],
+            positions: vec![],
+            end_position: SourcePosition::default(),
+            pending_cr: false,
+            ended: false,
+            pos: 0,
+            token_start: 0,
+            state: State::Data,
+            tag: TagBuilder::default(),
+            last_start_tag_name: String::new(),
+            temporary_buffer: String::new(),
+            comment: String::new(),
+            doctype: DoctypeBuilder::default(),
+            tokens: VecDeque::new(),
+            errors: vec![],
+        }
+    }
+
+    pub fn feed(&mut self, input: &str) {
+        // 换行统一为\n，位置仍按原文计算
+        for c in input.chars() {
+            if std::mem::take(&mut self.pending_cr) && c == '\n' {
+                self.end_position.offset += 1;
+                continue;
+            }
+            self.positions.push(self.end_position);
+            if c == '\r' {
+                self.pending_cr = true;
+                self.end_position.advance('\n');
+                self.input.push('\n');
+            } else {
+                self.end_position.advance(c);
+                self.input.push(c);
+            }
+        }
+    }
+
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+
+    // 需要更多输入时返回None
+    pub fn next_token(&mut self) -> Option<(Token, SourceSpan)> {
+        while self.tokens.is_empty() {
+            if self.needs_more_input() {
+                return None;
+            }
+            self.step();
+        }
+        self.tokens.pop_front()
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Diagnostic<HTMLParseError>> {
+        std::mem::take(&mut self.errors)
+    }
+
+    // tree builder插入<script>, <textarea>等之后切换状态
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    fn consume(&mut self) -> Option<char> {
+        let c = self.input.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    // 下一个状态重新处理当前字符
+    fn reconsume_in(&mut self, state: State) {
+        self.pos -= 1;
+        self.token_start = self.token_start.min(self.pos);
+        self.state = state;
+    }
+
+    // EOF之后pos可能超出输入
+    fn position(&self, index: usize) -> SourcePosition {
+        self.positions
+            .get(index)
+            .copied()
+            .unwrap_or(self.end_position)
+    }
+
+    // 输入未结束时，当前状态需要查看的字符还没到齐
+    fn needs_more_input(&self) -> bool {
+        if self.ended {
+            return false;
+        }
+        let rest = self.input.get(self.pos..).unwrap_or_default();
+        match (self.state, rest.first()) {
+            (_, None) => true,
+            (State::MarkupDeclarationOpen, _) => rest.len() < "DOCTYPE".len(),
+            (State::AfterDoctypeName, Some(c)) if !is_whitespace(*c) && *c != '>' => {
+                rest.len() < "PUBLIC".len()
+            }
+            // 字符引用在遇到字母数字以外的字符时结束
+            (
+                State::Data
+                | State::Rcdata
+                | State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted,
+                Some('&'),
+            ) => !rest[1..]
+                .iter()
+                .any(|c| !c.is_ascii_alphanumeric() && *c != '#'),
+            _ => false,
+        }
+    }
+
+    // 一次输出多个token时，第一个token包含全部字符，之后的为空
+    fn emit(&mut self, token: Token) {
+        let span = SourceSpan::new(self.position(self.token_start), self.position(self.pos));
+        self.tokens.push_back((token, span));
+        self.token_start = self.pos;
+    }
+
+    // 位置为当前字符
+    fn error(&mut self, code: &'static str) {
+        let span = SourceSpan::new(
+            self.position(self.pos.saturating_sub(1)),
+            self.position(self.pos),
+        );
+        self.errors
+            .push(Diagnostic::new(HTMLParseError::TokenizerError(code), span));
+    }
+
+    fn new_tag(&mut self, is_end_tag: bool) {
+        self.tag = TagBuilder {
+            is_end_tag,
+            ..TagBuilder::default()
+        };
+    }
+
+    fn new_attribute(&mut self) {
+        self.tag.attributes.push((String::new(), String::new()));
+    }
+
+    fn push_attribute_name(&mut self, c: char) {
+        if let Some(attr) = self.tag.attributes.last_mut() {
+            attr.0.push(c);
+        }
+    }
+
+    fn push_attribute_value(&mut self, c: char) {
+        if let Some(attr) = self.tag.attributes.last_mut() {
+            attr.1.push(c);
+        }
+    }
+
+    fn push_character_reference_to_attribute(&mut self) {
+        match self.consume_character_reference(true) {
+            Some(s) => s.chars().for_each(|c| self.push_attribute_value(c)),
+            None => self.push_attribute_value('&'),
+        }
+    }
+
+    fn emit_tag(&mut self) {
+        let tag = std::mem::take(&mut self.tag);
+        if tag.is_end_tag {
+            if !tag.attributes.is_empty() {
+                self.error("end-tag-with-attributes");
+            }
+            self.emit(Token::EndTag {
+                tag_name: tag.tag_name,
+            });
+            return;
+        }
+
+        self.last_start_tag_name = tag.tag_name.clone();
+
+        // 重复的属性只保留第一个
+        let mut attributes = AttrMap::new();
+        for (name, value) in tag.attributes {
+            if attributes.contains_key(&name) {
+                self.error("duplicate-attribute");
+                continue;
+            }
+            attributes.insert(name, value);
+        }
+        self.emit(Token::StartTag {
+            tag_name: tag.tag_name,
+            attributes,
+            self_closing: tag.self_closing,
+        });
+    }
+
+    fn is_appropriate_end_tag(&self) -> bool {
+        self.tag.is_end_tag && self.tag.tag_name == self.last_start_tag_name
+    }
+
+    // 不是对应的结束标签，作为文本输出
+    fn flush_text_end_tag(&mut self, content: TextContent) {
+        self.emit(Token::Character('<'));
+        self.emit(Token::Character('/'));
+        for c in std::mem::take(&mut self.temporary_buffer).chars() {
+            self.emit(Token::Character(c));
+        }
+        self.reconsume_in(content.state());
+    }
+
+    fn emit_eof_in_tag(&mut self) {
+        self.error("eof-in-tag");
+        self.emit(Token::Eof);
+    }
+
+    // 从start开始是否为word，忽略大小写。匹配时消费掉word
+    fn consume_if_matches(&mut self, start: usize, word: &str) -> bool {
+        let len = word.chars().count();
+        let matched = self.input.len() >= start + len
+            && self.input[start..start + len]
+                .iter()
+                .zip(word.chars())
+                .all(|(a, b)| a.eq_ignore_ascii_case(&b));
+        if matched {
+            self.pos = start + len;
+        }
+        matched
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_eof_in_comment(&mut self) {
+        self.error("eof-in-comment");
+        self.emit_comment();
+        self.emit(Token::Eof);
+    }
+
+    fn emit_doctype(&mut self) {
+        let doctype = std::mem::take(&mut self.doctype);
+        self.emit(Token::Doctype {
+            name: doctype.name,
+            public_id: doctype.public_id,
+            system_id: doctype.system_id,
+            force_quirks: doctype.force_quirks,
+        });
+    }
+
+    fn emit_eof_in_doctype(&mut self) {
+        self.error("eof-in-doctype");
+        self.doctype.force_quirks = true;
+        self.emit_doctype();
+        self.emit(Token::Eof);
+    }
+
+    fn doctype_id_mut(&mut self, id: DoctypeId) -> &mut Option<String> {
+        match id {
+            DoctypeId::Public => &mut self.doctype.public_id,
+            DoctypeId::System => &mut self.doctype.system_id,
+        }
+    }
+
+    fn bogus_doctype(&mut self, code: &'static str) {
+        self.error(code);
+        self.doctype.force_quirks = true;
+        self.reconsume_in(State::BogusDoctype);
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#character-reference-state
+    // '&'之后调用，无法解析时返回None且不消费任何字符
+    fn consume_character_reference(&mut self, in_attribute: bool) -> Option<String> {
+        match self.input.get(self.pos) {
+            Some('#') => {
+                self.pos += 1;
+                match self.consume_numeric_character_reference() {
+                    Some(c) => Some(c.to_string()),
+                    None => {
+                        self.pos -= 1;
+                        None
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_alphanumeric() => {
+                self.consume_named_character_reference(in_attribute)
+            }
+            _ => None,
+        }
+    }
+
+    fn consume_named_character_reference(&mut self, in_attribute: bool) -> Option<String> {
+        let start = self.pos;
+        let mut candidate = String::new();
+        for c in self.input[start..].iter().take(LONGEST_CHARACTER_REFERENCE) {
+            if c.is_ascii_alphanumeric() {
+                candidate.push(*c);
+            } else {
+                if *c == ';' {
+                    candidate.push(';');
+                }
+                break;
+            }
+        }
+
+        // 最长匹配
+        let matched = (1..=candidate.len()).rev().find_map(|len| {
+            NAMED_CHARACTER_REFERENCES
+                .binary_search_by_key(&&candidate[..len], |(name, _)| name)
+                .ok()
+                .map(|index| NAMED_CHARACTER_REFERENCES[index])
+        });
+        let (name, value) = match matched {
+            Some(matched) => matched,
+            None => {
+                if candidate.ends_with(';') {
+                    self.error("unknown-named-character-reference");
+                }
+                return None;
+            }
+        };
+
+        if !name.ends_with(';') {
+            // 属性中的"&not=1"等保持原样
+            let next = self.input.get(start + name.len());
+            if in_attribute && next.is_some_and(|c| *c == '=' || c.is_ascii_alphanumeric()) {
+                return None;
+            }
+            self.error("missing-semicolon-after-character-reference");
+        }
+        self.pos = start + name.len();
+        Some(value.to_string())
+    }
+
+    fn consume_numeric_character_reference(&mut self) -> Option<char> {
+        let radix = match self.input.get(self.pos) {
+            Some('x') | Some('X') => {
+                self.pos += 1;
+                16
+            }
+            _ => 10,
+        };
+        let start = self.pos;
+        let mut n: u32 = 0;
+        while let Some(d) = self.input.get(self.pos).and_then(|c| c.to_digit(radix)) {
+            n = n.saturating_mul(radix).saturating_add(d);
+            self.pos += 1;
+        }
+        if self.pos == start {
+            self.error("absence-of-digits-in-numeric-character-reference");
+            self.pos = start - if radix == 16 { 1 } else { 0 };
+            return None;
+        }
+        if self.input.get(self.pos) == Some(&';') {
+            self.pos += 1;
+        } else {
+            self.error("missing-semicolon-after-character-reference");
+        }
+
+        if n == 0 {
+            self.error("null-character-reference");
+            return Some(char::REPLACEMENT_CHARACTER);
+        }
+        if n > 0x10FFFF {
+            self.error("character-reference-outside-unicode-range");
+            return Some(char::REPLACEMENT_CHARACTER);
+        }
+        if (0xD800..=0xDFFF).contains(&n) {
+            self.error("surrogate-character-reference");
+            return Some(char::REPLACEMENT_CHARACTER);
+        }
+        if is_noncharacter(n) {
+            self.error("noncharacter-character-reference");
+        }
+        if n == 0x0D
+            || ((n < 0x20 || (0x7F..=0x9F).contains(&n)) && !matches!(n, 0x09 | 0x0A | 0x0C | 0x20))
+        {
+            self.error("control-character-reference");
+            if let Some((_, c)) = C1_REPLACEMENTS.iter().find(|(code, _)| *code == n) {
+                return Some(*c);
+            }
+        }
+        char::from_u32(n)
+    }
+
+    fn step(&mut self) {
+        if self.state == State::MarkupDeclarationOpen {
+            let pos = self.pos;
+            if self.consume_if_matches(pos, "--") {
+                self.comment = String::new();
+                self.state = State::CommentStart;
+            } else if self.consume_if_matches(pos, "DOCTYPE") {
+                self.doctype = DoctypeBuilder::default();
+                self.state = State::Doctype;
+            } else {
+                self.error("incorrectly-opened-comment");
+                self.comment = String::new();
+                self.state = State::BogusComment;
+            }
+            return;
+        }
+
+        let c = self.consume();
+        match self.state {
+            State::Data => match c {
+                Some('<') => self.state = State::TagOpen,
+                Some('&') => match self.consume_character_reference(false) {
+                    Some(s) => s.chars().for_each(|c| self.emit(Token::Character(c))),
+                    None => self.emit(Token::Character('&')),
+                },
+                Some(c) => self.emit(Token::Character(c)),
+                None => self.emit(Token::Eof),
+            },
+            State::Rcdata => match c {
+                Some('<') => self.state = State::TextLessThanSign(TextContent::Rcdata),
+                Some('&') => match self.consume_character_reference(false) {
+                    Some(s) => s.chars().for_each(|c| self.emit(Token::Character(c))),
+                    None => self.emit(Token::Character('&')),
+                },
+                Some(c) => self.emit(Token::Character(c)),
+                None => self.emit(Token::Eof),
+            },
+            State::Rawtext | State::ScriptData => match c {
+                Some('<') => {
+                    self.state = State::TextLessThanSign(if self.state == State::Rawtext {
+                        TextContent::Rawtext
+                    } else {
+                        TextContent::ScriptData
+                    })
+                }
+                Some(c) => self.emit(Token::Character(c)),
+                None => self.emit(Token::Eof),
+            },
+            State::Plaintext => match c {
+                Some(c) => self.emit(Token::Character(c)),
+                None => self.emit(Token::Eof),
+            },
+            State::TextLessThanSign(content) => match c {
+                Some('/') => {
+                    self.temporary_buffer = String::new();
+                    self.state = State::TextEndTagOpen(content);
+                }
+                _ => {
+                    self.emit(Token::Character('<'));
+                    self.reconsume_in(content.state());
+                }
+            },
+            State::TextEndTagOpen(content) => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::TextEndTagName(content));
+                }
+                _ => {
+                    self.emit(Token::Character('<'));
+                    self.emit(Token::Character('/'));
+                    self.reconsume_in(content.state());
+                }
+            },
+            State::TextEndTagName(content) => match c {
+                Some(c) if is_whitespace(c) && self.is_appropriate_end_tag() => {
+                    self.state = State::BeforeAttributeName
+                }
+                Some('/') if self.is_appropriate_end_tag() => {
+                    self.state = State::SelfClosingStartTag
+                }
+                Some('>') if self.is_appropriate_end_tag() => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.tag.tag_name.push(c.to_ascii_lowercase());
+                    self.temporary_buffer.push(c);
+                }
+                _ => self.flush_text_end_tag(content),
+            },
+            State::TagOpen => match c {
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(false);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('?') => {
+                    // <?xml ...?>等processing instruction作为注释处理
+                    self.error("unexpected-question-mark-instead-of-tag-name");
+                    self.comment = String::new();
+                    self.reconsume_in(State::BogusComment);
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.emit(Token::Character('<'));
+                    self.reconsume_in(State::Data);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit(Token::Character('<'));
+                    self.emit(Token::Eof);
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('>') => {
+                    self.error("missing-end-tag-name");
+                    self.state = State::Data;
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.comment = String::new();
+                    self.reconsume_in(State::BogusComment);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit(Token::Character('<'));
+                    self.emit(Token::Character('/'));
+                    self.emit(Token::Eof);
+                }
+            },
+            State::TagName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(c) => self.tag.tag_name.push(c.to_ascii_lowercase()),
+                None => self.emit_eof_in_tag(),
+            },
+            State::BeforeAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') | Some('>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => {
+                    self.error("unexpected-equals-sign-before-attribute-name");
+                    self.new_attribute();
+                    self.push_attribute_name('=');
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.new_attribute();
+                    self.reconsume_in(State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some(c) if is_whitespace(c) => self.reconsume_in(State::AfterAttributeName),
+                Some('/') | Some('>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some(c) => {
+                    if c == '"' || c == '\'' || c == '<' {
+                        self.error("unexpected-character-in-attribute-name");
+                    }
+                    self.push_attribute_name(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(_) => {
+                    self.new_attribute();
+                    self.reconsume_in(State::AttributeName);
+                }
+                None => self.emit_eof_in_tag(),
+            },
+            State::BeforeAttributeValue => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    self.error("missing-attribute-value");
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                _ => self.reconsume_in(State::AttributeValueUnquoted),
+            },
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote = if self.state == State::AttributeValueDoubleQuoted {
+                    '"'
+                } else {
+                    '\''
+                };
+                match c {
+                    Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+                    Some('&') => self.push_character_reference_to_attribute(),
+                    Some(c) => self.push_attribute_value(c),
+                    None => self.emit_eof_in_tag(),
+                }
+            }
+            State::AttributeValueUnquoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('&') => self.push_character_reference_to_attribute(),
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                        self.error("unexpected-character-in-unquoted-attribute-value");
+                    }
+                    self.push_attribute_value(c);
+                }
+                None => self.emit_eof_in_tag(),
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(_) => {
+                    self.error("missing-whitespace-between-attributes");
+                    self.reconsume_in(State::BeforeAttributeName);
+                }
+                None => self.emit_eof_in_tag(),
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(_) => {
+                    self.error("unexpected-solidus-in-tag");
+                    self.reconsume_in(State::BeforeAttributeName);
+                }
+                None => self.emit_eof_in_tag(),
+            },
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit(Token::Eof);
+                }
+            },
+            State::MarkupDeclarationOpen => unreachable!(),
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+                None => self.emit_eof_in_comment(),
+            },
+            State::Comment => match c {
+                Some('-') => self.state = State::CommentEndDash,
+                Some(c) => self.comment.push(c),
+                None => self.emit_eof_in_comment(),
+            },
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+                None => self.emit_eof_in_comment(),
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume_in(State::Comment);
+                }
+                None => self.emit_eof_in_comment(),
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.error("incorrectly-closed-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume_in(State::Comment);
+                }
+                None => self.emit_eof_in_comment(),
+            },
+            State::Doctype => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeDoctypeName,
+                Some('>') => self.reconsume_in(State::BeforeDoctypeName),
+                Some(_) => {
+                    self.error("missing-whitespace-before-doctype-name");
+                    self.reconsume_in(State::BeforeDoctypeName);
+                }
+                None => self.emit_eof_in_doctype(),
+            },
+            State::BeforeDoctypeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.error("missing-doctype-name");
+                    self.doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(c) => {
+                    self.doctype.name = Some(c.to_ascii_lowercase().to_string());
+                    self.state = State::DoctypeName;
+                }
+                None => self.emit_eof_in_doctype(),
+            },
+            State::DoctypeName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(c) => {
+                    if let Some(name) = self.doctype.name.as_mut() {
+                        name.push(c.to_ascii_lowercase());
+                    }
+                }
+                None => self.emit_eof_in_doctype(),
+            },
+            State::AfterDoctypeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(_) => {
+                    let start = self.pos - 1;
+                    if self.consume_if_matches(start, "PUBLIC") {
+                        self.state = State::AfterDoctypeKeyword(DoctypeId::Public);
+                    } else if self.consume_if_matches(start, "SYSTEM") {
+                        self.state = State::AfterDoctypeKeyword(DoctypeId::System);
+                    } else {
+                        self.bogus_doctype("invalid-character-sequence-after-doctype-name");
+                    }
+                }
+                None => self.emit_eof_in_doctype(),
+            },
+            State::AfterDoctypeKeyword(id) => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeDoctypeIdentifier(id),
+                Some('"') | Some('\'') => {
+                    self.error("missing-whitespace-after-doctype-keyword");
+                    *self.doctype_id_mut(id) = Some(String::new());
+                    self.state = State::DoctypeIdentifierQuoted(id, c.unwrap());
+                }
+                Some('>') => {
+                    self.error("missing-doctype-identifier");
+                    self.doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(_) => self.bogus_doctype("missing-quote-before-doctype-identifier"),
+                None => self.emit_eof_in_doctype(),
+            },
+            State::BeforeDoctypeIdentifier(id) => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('"') | Some('\'') => {
+                    *self.doctype_id_mut(id) = Some(String::new());
+                    self.state = State::DoctypeIdentifierQuoted(id, c.unwrap());
+                }
+                Some('>') => {
+                    self.error("missing-doctype-identifier");
+                    self.doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(_) => self.bogus_doctype("missing-quote-before-doctype-identifier"),
+                None => self.emit_eof_in_doctype(),
+            },
+            State::DoctypeIdentifierQuoted(id, quote) => match c {
+                Some(c) if c == quote => {
+                    self.state = match id {
+                        DoctypeId::Public => State::AfterDoctypePublicIdentifier,
+                        DoctypeId::System => State::AfterDoctypeSystemIdentifier,
+                    }
+                }
+                Some('>') => {
+                    self.error("abrupt-doctype-identifier");
+                    self.doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(c) => {
+                    if let Some(value) = self.doctype_id_mut(id).as_mut() {
+                        value.push(c);
+                    }
+                }
+                None => self.emit_eof_in_doctype(),
+            },
+            State::AfterDoctypePublicIdentifier
+            | State::BetweenDoctypePublicAndSystemIdentifiers => match c {
+                Some(c) if is_whitespace(c) => {
+                    self.state = State::BetweenDoctypePublicAndSystemIdentifiers
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('"') | Some('\'') => {
+                    if self.state == State::AfterDoctypePublicIdentifier {
+                        self.error("missing-whitespace-between-doctype-identifiers");
+                    }
+                    self.doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeIdentifierQuoted(DoctypeId::System, c.unwrap());
+                }
+                Some(_) => self.bogus_doctype("missing-quote-before-doctype-identifier"),
+                None => self.emit_eof_in_doctype(),
+            },
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(_) => {
+                    self.error("unexpected-character-after-doctype-identifier");
+                    self.reconsume_in(State::BogusDoctype);
+                }
+                None => self.emit_eof_in_doctype(),
+            },
+            State::BogusDoctype => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(_) => {}
+                None => {
+                    self.emit_doctype();
+                    self.emit(Token::Eof);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(input: &str) -> Vec<Token> {
+        let mut tokenizer = Tokenizer::new(input);
+        let mut tokens = vec![];
+        loop {
+            let (token, _) = tokenizer.next_token().unwrap();
+            if token == Token::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    fn start_tag(tag_name: &str, attributes: Vec<(&str, &str)>) -> Token {
+        Token::StartTag {
+            tag_name: tag_name.to_string(),
+            attributes: attributes
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            self_closing: false,
+        }
+    }
+
+    fn end_tag(tag_name: &str) -> Token {
+        Token::EndTag {
+            tag_name: tag_name.to_string(),
+        }
+    }
+
+    fn characters(s: &str) -> Vec<Token> {
+        s.chars().map(Token::Character).collect()
+    }
+
+    #[test]
+    fn test_tokenize_tags() {
+        assert_eq!(
+            tokenize("<p>Hi</p>"),
+            [
+                vec![start_tag("p", vec![])],
+                characters("Hi"),
+                vec![end_tag("p")]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_tokenize_attributes() {
+        assert_eq!(
+            tokenize("<p id=\"test\" class = \"sample\">"),
+            vec![start_tag("p", vec![("id", "test"), ("class", "sample")])]
+        );
+        assert_eq!(
+            tokenize("<p test=\"&quot;&quot;\">"),
+            vec![start_tag("p", vec![("test", "\"\"")])]
+        );
+        assert_eq!(
+            tokenize("<p id=\"a\" id=\"b\">"),
+            vec![start_tag("p", vec![("id", "a")])]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_attribute_syntax() {
+        assert_eq!(
+            tokenize("<input type=text class='a b' data-id=\"1\" aria-label=x&amp;y disabled alt=\"\" checked>"),
+            vec![start_tag(
+                "input",
+                vec![
+                    ("type", "text"),
+                    ("class", "a b"),
+                    ("data-id", "1"),
+                    ("aria-label", "x&y"),
+                    ("disabled", ""),
+                    ("alt", ""),
+                    ("checked", ""),
+                ]
+            )]
+        );
+        assert_eq!(
+            tokenize("<meta http-equiv=refresh content='0;url=a.html'/>"),
+            vec![Token::StartTag {
+                tag_name: "meta".to_string(),
+                attributes: vec![
+                    ("http-equiv".to_string(), "refresh".to_string()),
+                    ("content".to_string(), "0;url=a.html".to_string()),
+                ]
+                .into_iter()
+                .collect(),
+                self_closing: true,
+            }]
+        );
+        assert_eq!(
+            tokenize("<p ID=a CLASS = 'b'>"),
+            vec![start_tag("p", vec![("id", "a"), ("class", "b")])]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_self_closing_tag() {
+        assert_eq!(
+            tokenize("<br/><img src=\"a.png\" />"),
+            vec![
+                Token::StartTag {
+                    tag_name: "br".to_string(),
+                    attributes: AttrMap::new(),
+                    self_closing: true,
+                },
+                Token::StartTag {
+                    tag_name: "img".to_string(),
+                    attributes: vec![("src".to_string(), "a.png".to_string())]
+                        .into_iter()
+                        .collect(),
+                    self_closing: true,
+                },
+            ]
+        );
+        // 属性中间的'/'被忽略
+        assert_eq!(
+            tokenize("<p / id=\"a\">"),
+            vec![start_tag("p", vec![("id", "a")])]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_comment() {
+        assert_eq!(
+            tokenize("<!-- a -- b -->"),
+            vec![Token::Comment(" a -- b ".to_string())]
+        );
+        assert_eq!(tokenize("<!---->"), vec![Token::Comment("".to_string())]);
+        assert_eq!(
+            tokenize("<?xml version=\"1.0\"?>"),
+            vec![Token::Comment("?xml version=\"1.0\"?".to_string())]
+        );
+        assert_eq!(
+            tokenize("<!-- unclosed"),
+            vec![Token::Comment(" unclosed".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_doctype() {
+        assert_eq!(
+            tokenize("<!DOCTYPE html>"),
+            vec![Token::Doctype {
+                name: Some("html".to_string()),
+                public_id: None,
+                system_id: None,
+                force_quirks: false,
+            }]
+        );
+        assert_eq!(
+            tokenize("<!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" 'http://www.w3.org/TR/html4/strict.dtd'>"),
+            vec![Token::Doctype {
+                name: Some("html".to_string()),
+                public_id: Some("-//W3C//DTD HTML 4.01//EN".to_string()),
+                system_id: Some("http://www.w3.org/TR/html4/strict.dtd".to_string()),
+                force_quirks: false,
+            }]
+        );
+        assert_eq!(
+            tokenize("<!DOCTYPE>"),
+            vec![Token::Doctype {
+                name: None,
+                public_id: None,
+                system_id: None,
+                force_quirks: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_character_reference() {
+        assert_eq!(
+            tokenize("&amp;&lt;&nbsp;&#x2014;&#65;&#X42;"),
+            characters("&<\u{a0}\u{2014}AB")
+        );
+        // 历史遗留的无分号写法
+        assert_eq!(tokenize("&copy 2021 &amp"), characters("\u{a9} 2021 &"));
+        assert_eq!(
+            tokenize("&notit; &notin;"),
+            characters("\u{ac}it; \u{2209}")
+        );
+        // 无法解析的引用保持原样
+        assert_eq!(
+            tokenize("a & b &foo; &#; &#xZ"),
+            characters("a & b &foo; &#; &#xZ")
+        );
+        assert_eq!(
+            tokenize("&#0;&#x110000;&#xD800;&#x80;"),
+            characters("\u{fffd}\u{fffd}\u{fffd}\u{20ac}")
+        );
+    }
+
+    #[test]
+    fn test_tokenize_character_reference_in_attribute() {
+        assert_eq!(
+            tokenize("<a href=\"?a=1&amp;b=2&not=3&copy\" title=\"&lt;&#39;&gt;\">"),
+            vec![start_tag(
+                "a",
+                vec![("href", "?a=1&b=2&not=3\u{a9}"), ("title", "<'>")]
+            )]
+        );
+    }
+
+    fn tokenize_in(state: State, last_start_tag_name: &str, input: &str) -> Vec<Token> {
+        let mut tokenizer = Tokenizer::new(input);
+        tokenizer.set_state(state);
+        tokenizer.last_start_tag_name = last_start_tag_name.to_string();
+        let mut tokens = vec![];
+        loop {
+            let (token, _) = tokenizer.next_token().unwrap();
+            if token == Token::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_tokenize_raw_text() {
+        assert_eq!(
+            tokenize_in(
+                State::ScriptData,
+                "script",
+                "if (a<b && c>d) { s = '</div>&amp;</scriptx>'; }</SCRIPT >x"
+            ),
+            [
+                characters("if (a<b && c>d) { s = '</div>&amp;</scriptx>'; }"),
+                vec![end_tag("script")],
+                characters("x"),
+            ]
+            .concat()
+        );
+        assert_eq!(
+            tokenize_in(State::Rawtext, "style", "ul > li { }</style>"),
+            [characters("ul > li { }"), vec![end_tag("style")]].concat()
+        );
+        assert_eq!(
+            tokenize_in(State::Plaintext, "plaintext", "<p>a</plaintext>"),
+            characters("<p>a</plaintext>")
+        );
+    }
+
+    #[test]
+    fn test_tokenize_rcdata() {
+        assert_eq!(
+            tokenize_in(State::Rcdata, "textarea", "<b>&lt;&amp;</b></textarea>"),
+            [characters("<b><&</b>"), vec![end_tag("textarea")]].concat()
+        );
+        assert_eq!(
+            tokenize_in(State::Rcdata, "title", "a</"),
+            characters("a</")
+        );
+    }
+
+    #[test]
+    fn test_tokenize_invalid_markup() {
+        // 不是tag的'<'作为文本处理
+        assert_eq!(tokenize("a < b"), characters("a < b"));
+        assert_eq!(tokenize("a <"), characters("a <"));
+        // 未结束的tag被丢弃
+        assert_eq!(tokenize("a<p id=\"x"), characters("a"));
+        assert_eq!(tokenize("</>a"), characters("a"));
+
+        let mut tokenizer = Tokenizer::new("<p id=\"x");
+        assert_eq!(tokenizer.next_token().unwrap().0, Token::Eof);
+        assert_eq!(
+            tokenizer.take_errors(),
+            vec![Diagnostic::new(
+                HTMLParseError::TokenizerError("eof-in-tag"),
+                SourceSpan::new(
+                    SourcePosition {
+                        line: 1,
+                        column: 9,
+                        offset: 8
+                    },
+                    SourcePosition {
+                        line: 1,
+                        column: 9,
+                        offset: 8
+                    }
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let position = |line, column, offset| SourcePosition {
+            line,
+            column,
+            offset,
+        };
+        let mut tokenizer = Tokenizer::new("<p>\r\n&amp;<br/></p>");
+        let mut spans = vec![];
+        loop {
+            let (token, span) = tokenizer.next_token().unwrap();
+            if token == Token::Eof {
+                break;
+            }
+            spans.push((token, span.start, span.end));
+        }
+        assert_eq!(
+            spans,
+            vec![
+                (start_tag("p", vec![]), position(1, 1, 0), position(1, 4, 3)),
+                (Token::Character('\n'), position(1, 4, 3), position(2, 1, 5)),
+                (Token::Character('&'), position(2, 1, 5), position(2, 6, 10)),
+                (
+                    Token::StartTag {
+                        tag_name: "br".to_string(),
+                        attributes: AttrMap::new(),
+                        self_closing: true
+                    },
+                    position(2, 6, 10),
+                    position(2, 11, 15)
+                ),
+                (end_tag("p"), position(2, 11, 15), position(2, 15, 19)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_incrementally() {
+        let mut tokenizer = Tokenizer::incremental();
+        let mut tokens = vec![];
+        for chunk in ["<p cl", "ass=a>x &am", "p; <!-", "- c -->\r", "\ny"] {
+            tokenizer.feed(chunk);
+            while let Some((token, _)) = tokenizer.next_token() {
+                tokens.push(token);
+            }
+        }
+        // 输入结束前不会输出Eof
+        assert_eq!(tokens.last(), Some(&Token::Character('y')));
+        tokenizer.end();
+        while let Some((token, _)) = tokenizer.next_token() {
+            tokens.push(token);
+            if tokens.last() == Some(&Token::Eof) {
+                break;
+            }
+        }
+        assert_eq!(
+            tokens,
+            [
+                vec![start_tag("p", vec![("class", "a")])],
+                characters("x & "),
+                vec![Token::Comment(" c ".to_string())],
+                characters("\ny"),
+                vec![Token::Eof],
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,1739 @@
+use super::tokenizer::{State, TextContent, Token};
+use super::HTMLParseError;
+use crate::core::dom::{
+    comment::Comment,
+    document::QuirksMode,
+    document_type::DocumentType,
+    element::{is_void_element, AttrMap, Element},
+    node::{Node, NodeType},
+    text::Text,
+};
+use crate::core::source::{Diagnostic, SourceSpan};
+use std::collections::HashMap;
+
+// https://html.spec.whatwg.org/multipage/parsing.html#tree-construction
+// table/select/frameset相关的insertion mode没有实现，这些元素按普通元素处理
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Text,
+    AfterBody,
+    AfterAfterBody,
+}
+
+type NodeId = usize;
+
+// 构建过程中的节点，children用下标保存，方便移动节点
+#[derive(Debug)]
+struct ArenaNode {
+    node_type: NodeType,
+    children: Vec<NodeId>,
+    parent: Option<NodeId>,
+    span: Option<SourceSpan>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum FormattingEntry {
+    Marker,
+    Element(NodeId),
+}
+
+// 插入位置，adoption agency algorithm用
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Bookmark {
+    Replace(NodeId),
+    After(NodeId),
+}
+
+const FORMATTING_ELEMENTS: &[&str] = &[
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+const SPECIAL_ELEMENTS: &[&str] = &[
+    "address",
+    "applet",
+    "area",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "bgsound",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "iframe",
+    "img",
+    "input",
+    "keygen",
+    "li",
+    "link",
+    "listing",
+    "main",
+    "marquee",
+    "menu",
+    "meta",
+    "nav",
+    "noembed",
+    "noframes",
+    "noscript",
+    "object",
+    "ol",
+    "p",
+    "param",
+    "plaintext",
+    "pre",
+    "script",
+    "search",
+    "section",
+    "select",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+    "wbr",
+    "xmp",
+];
+
+// 开始前需要关闭<p>的块级元素
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "header",
+    "hgroup",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "search",
+    "section",
+    "summary",
+    "ul",
+];
+
+const HEADING_ELEMENTS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+const IMPLIED_END_TAG_ELEMENTS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+// 文档结束时允许不关闭的元素
+const OPTIONAL_END_TAG_ELEMENTS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc", "tbody", "td", "tfoot",
+    "th", "thead", "tr", "body", "html",
+];
+
+const DEFAULT_SCOPE_BOUNDARY: &[&str] = &[
+    "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template",
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+}
+
+impl Scope {
+    fn is_boundary(&self, tag_name: &str) -> bool {
+        DEFAULT_SCOPE_BOUNDARY.contains(&tag_name)
+            || match self {
+                Scope::Default => false,
+                Scope::ListItem => tag_name == "ol" || tag_name == "ul",
+                Scope::Button => tag_name == "button",
+            }
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+const QUIRKS_PUBLIC_IDS: &[&str] = &[
+    "-//w3o//dtd w3 html strict 3.0//en//",
+    "-/w3c/dtd html 4.0 transitional/en",
+    "html",
+];
+
+const QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+fn doctype_quirks_mode(
+    name: &str,
+    public_id: Option<&str>,
+    system_id: Option<&str>,
+    force_quirks: bool,
+) -> QuirksMode {
+    let public_id = public_id.map(|id| id.to_ascii_lowercase());
+    let system_id = system_id.map(|id| id.to_ascii_lowercase());
+    let public_id_starts_with =
+        |prefix: &str| public_id.as_ref().is_some_and(|id| id.starts_with(prefix));
+    let html4_frameset_or_transitional = public_id_starts_with("-//w3c//dtd html 4.01 frameset//")
+        || public_id_starts_with("-//w3c//dtd html 4.01 transitional//");
+
+    if force_quirks
+        || name != "html"
+        || public_id
+            .as_ref()
+            .is_some_and(|id| QUIRKS_PUBLIC_IDS.contains(&id.as_str()))
+        || system_id.as_deref()
+            == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || QUIRKS_PUBLIC_ID_PREFIXES
+            .iter()
+            .any(|prefix| public_id_starts_with(prefix))
+        || (system_id.is_none() && html4_frameset_or_transitional)
+    {
+        QuirksMode::Quirks
+    } else if public_id_starts_with("-//w3c//dtd xhtml 1.0 frameset//")
+        || public_id_starts_with("-//w3c//dtd xhtml 1.0 transitional//")
+        || (system_id.is_some() && html4_frameset_or_transitional)
+    {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+pub struct TreeBuilder {
+    arena: Vec<ArenaNode>,
+    document_children: Vec<NodeId>,
+    open_elements: Vec<NodeId>,
+    active_formatting_elements: Vec<FormattingEntry>,
+    head: Option<NodeId>,
+    quirks_mode: QuirksMode,
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    tokenizer_state: Option<State>,
+    ignore_next_newline: bool,
+    stopped: bool,
+    // 正在处理的token的位置和tag名，用于错误和节点的位置
+    span: SourceSpan,
+    token_tag_name: Option<String>,
+    // 刚解析完的<script>的内容
+    pending_script: Option<String>,
+    errors: Vec<Diagnostic<HTMLParseError>>,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        TreeBuilder {
+            arena: vec![],
+            document_children: vec![],
+            open_elements: vec![],
+            active_formatting_elements: vec![],
+            head: None,
+            quirks_mode: QuirksMode::NoQuirks,
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            tokenizer_state: None,
+            ignore_next_newline: false,
+            stopped: false,
+            span: SourceSpan::default(),
+            token_tag_name: None,
+            pending_script: None,
+            errors: vec![],
+        }
+    }
+
+    // innerHTML等使用，以<body>为context解析
+    pub fn new_fragment() -> Self {
+        let mut builder = Self::new();
+        let html = builder.create_element("html".to_string(), AttrMap::new());
+        builder.document_children.push(html);
+        builder.open_elements.push(html);
+        builder.mode = InsertionMode::InBody;
+        builder
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn add_errors(&mut self, errors: Vec<Diagnostic<HTMLParseError>>) {
+        self.errors.extend(errors);
+    }
+
+    pub fn errors(&self) -> &[Diagnostic<HTMLParseError>] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<Diagnostic<HTMLParseError>> {
+        std::mem::take(&mut self.errors)
+    }
+
+    // 遇到</script>之后返回脚本内容，由调用者在继续解析前执行
+    pub fn take_script(&mut self) -> Option<String> {
+        self.pending_script.take()
+    }
+
+    // 需要tokenizer切换的状态
+    pub fn take_tokenizer_state(&mut self) -> Option<State> {
+        self.tokenizer_state.take()
+    }
+
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    // 取出doctype和<html>节点，<html>外的注释被丢弃
+    pub fn finish(mut self) -> (Option<Box<Node>>, Box<Node>) {
+        let doctype = self.doctype_id();
+        let html = self
+            .html_id()
+            .expect("html element is always inserted before EOF");
+        (doctype.map(|id| self.build_node(id)), self.build_node(html))
+    }
+
+    // 解析途中的doctype和<html>节点，<html>还没有插入时返回空的<html>
+    pub fn snapshot(&self) -> (Option<Box<Node>>, Box<Node>) {
+        let html = match self.html_id() {
+            Some(id) => self.clone_node(id),
+            None => Element::new("html".to_string(), AttrMap::new(), vec![]),
+        };
+        (self.doctype_id().map(|id| self.clone_node(id)), html)
+    }
+
+    // 脚本修改文档之后，用修改后的树替换构建中的<html>
+    // 按snapshot()时记录的parser_id对应新旧节点，被脚本移除的节点不再接收解析出的内容
+    pub fn import_document_element(&mut self, document_element: &Node) {
+        let html = match self.html_id() {
+            Some(id) => id,
+            None => return,
+        };
+        let mut imported = HashMap::new();
+        let new_html = self.import_node(document_element, &mut imported);
+        for id in self.document_children.iter_mut() {
+            if *id == html {
+                *id = new_html;
+            }
+        }
+        self.open_elements = self
+            .open_elements
+            .iter()
+            .filter_map(|id| imported.get(id).copied())
+            .collect();
+        self.active_formatting_elements = self
+            .active_formatting_elements
+            .iter()
+            .filter_map(|entry| match entry {
+                FormattingEntry::Marker => Some(FormattingEntry::Marker),
+                FormattingEntry::Element(id) => {
+                    imported.get(id).map(|id| FormattingEntry::Element(*id))
+                }
+            })
+            .collect();
+        self.head = self.head.and_then(|id| imported.get(&id).copied());
+    }
+
+    fn import_node(&mut self, node: &Node, imported: &mut HashMap<NodeId, NodeId>) -> NodeId {
+        self.arena.push(ArenaNode {
+            node_type: node.node_type.clone(),
+            children: vec![],
+            parent: None,
+            span: node.span,
+        });
+        let id = self.arena.len() - 1;
+        // 脚本复制的节点带着同样的parser_id，只对应第一个
+        if let Some(old) = node.parser_id.filter(|old| *old < id) {
+            imported.entry(old).or_insert(id);
+        }
+        for child in node.children.iter() {
+            let child_id = self.import_node(child, imported);
+            self.append_child(id, child_id);
+        }
+        id
+    }
+
+    fn doctype_id(&self) -> Option<NodeId> {
+        self.document_children
+            .iter()
+            .copied()
+            .find(|id| matches!(self.arena[*id].node_type, NodeType::DocumentType(_)))
+    }
+
+    fn html_id(&self) -> Option<NodeId> {
+        self.document_children
+            .iter()
+            .copied()
+            .find(|id| self.tag_name(*id) == Some("html"))
+    }
+
+    fn clone_node(&self, id: NodeId) -> Box<Node> {
+        Box::new(Node {
+            node_type: self.arena[id].node_type.clone(),
+            children: self.arena[id]
+                .children
+                .iter()
+                .map(|child| self.clone_node(*child))
+                .collect(),
+            span: self.arena[id].span,
+            parser_id: Some(id),
+        })
+    }
+
+    // 取出<html>的子节点
+    pub fn finish_fragment(mut self) -> Vec<Box<Node>> {
+        let html = self.document_children[0];
+        let children = std::mem::take(&mut self.arena[html].children);
+        children.into_iter().map(|id| self.build_node(id)).collect()
+    }
+
+    fn build_node(&mut self, id: NodeId) -> Box<Node> {
+        let node_type = std::mem::replace(
+            &mut self.arena[id].node_type,
+            NodeType::Text(Text {
+                data: String::new(),
+            }),
+        );
+        let children = std::mem::take(&mut self.arena[id].children)
+            .into_iter()
+            .map(|child| self.build_node(child))
+            .collect();
+        Box::new(Node {
+            node_type,
+            children,
+            span: self.arena[id].span,
+            parser_id: None,
+        })
+    }
+
+    pub fn process(&mut self, token: Token, span: SourceSpan) {
+        self.span = span;
+        self.token_tag_name = match token {
+            Token::StartTag { ref tag_name, .. } | Token::EndTag { ref tag_name } => {
+                Some(tag_name.clone())
+            }
+            _ => None,
+        };
+
+        // <pre>, <textarea>开头的换行被忽略
+        if std::mem::take(&mut self.ignore_next_newline) && token == Token::Character('\n') {
+            return;
+        }
+        // 只有void element可以使用<tag />
+        if let Token::StartTag {
+            ref tag_name,
+            self_closing: true,
+            ..
+        } = token
+        {
+            if !is_void_element(tag_name) {
+                self.error(HTMLParseError::NonVoidSelfClosingTag(tag_name.clone()));
+            }
+        }
+        self.dispatch(token);
+    }
+
+    fn dispatch(&mut self, token: Token) {
+        if self.stopped {
+            return;
+        }
+        match self.mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+        }
+    }
+
+    fn reprocess_in(&mut self, mode: InsertionMode, token: Token) {
+        self.mode = mode;
+        self.dispatch(token);
+    }
+
+    fn error(&mut self, error: HTMLParseError) {
+        self.errors.push(Diagnostic::new(error, self.span));
+    }
+
+    fn stop(&mut self) {
+        for id in self.open_elements.clone() {
+            if let Some(tag_name) = self.tag_name(id) {
+                if !OPTIONAL_END_TAG_ELEMENTS.contains(&tag_name) {
+                    let tag_name = tag_name.to_string();
+                    self.error(HTMLParseError::UnclosedElement(tag_name));
+                }
+            }
+        }
+        self.stopped = true;
+    }
+
+    // ---- insertion modes ----
+
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype {
+                name,
+                public_id,
+                system_id,
+                force_quirks,
+            } => {
+                let name = name.unwrap_or_default();
+                if name != "html"
+                    || public_id.is_some()
+                    || system_id
+                        .as_ref()
+                        .is_some_and(|id| id != "about:legacy-compat")
+                {
+                    self.error(HTMLParseError::InvalidDoctype);
+                }
+                self.quirks_mode = doctype_quirks_mode(
+                    &name,
+                    public_id.as_deref(),
+                    system_id.as_deref(),
+                    force_quirks,
+                );
+                self.arena.push(ArenaNode {
+                    node_type: NodeType::DocumentType(DocumentType {
+                        name,
+                        public_id: public_id.unwrap_or_default(),
+                        system_id: system_id.unwrap_or_default(),
+                    }),
+                    children: vec![],
+                    parent: None,
+                    span: Some(self.span),
+                });
+                self.document_children.push(self.arena.len() - 1);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            token => {
+                self.error(HTMLParseError::MissingDoctype);
+                self.quirks_mode = QuirksMode::Quirks;
+                self.reprocess_in(InsertionMode::BeforeHtml, token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.error(HTMLParseError::UnexpectedDoctype),
+            Token::StartTag {
+                ref tag_name,
+                ref attributes,
+                ..
+            } if tag_name == "html" => {
+                let html = self.create_element(tag_name.clone(), attributes.clone());
+                self.document_children.push(html);
+                self.open_elements.push(html);
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag { ref tag_name }
+                if !matches!(tag_name.as_str(), "head" | "body" | "html" | "br") =>
+            {
+                self.error(HTMLParseError::UnexpectedEndTag(tag_name.clone()));
+            }
+            token => {
+                let html = self.create_element("html".to_string(), AttrMap::new());
+                self.document_children.push(html);
+                self.open_elements.push(html);
+                self.reprocess_in(InsertionMode::BeforeHead, token);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(data, self.current_node()),
+            Token::Doctype { .. } => self.error(HTMLParseError::UnexpectedDoctype),
+            Token::StartTag { ref tag_name, .. } if tag_name == "html" => self.in_body(token),
+            Token::StartTag {
+                ref tag_name,
+                ref attributes,
+                ..
+            } if tag_name == "head" => {
+                let head = self.insert_element(tag_name.clone(), attributes.clone());
+                self.head = Some(head);
+                self.mode = InsertionMode::InHead;
+            }
+            Token::EndTag { ref tag_name }
+                if !matches!(tag_name.as_str(), "head" | "body" | "html" | "br") =>
+            {
+                self.error(HTMLParseError::UnexpectedEndTag(tag_name.clone()));
+            }
+            token => {
+                let head = self.insert_element("head".to_string(), AttrMap::new());
+                self.head = Some(head);
+                self.reprocess_in(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, self.current_node()),
+            Token::Doctype { .. } => self.error(HTMLParseError::UnexpectedDoctype),
+            Token::StartTag { ref tag_name, .. } if tag_name == "html" => self.in_body(token),
+            Token::StartTag {
+                ref tag_name,
+                ref attributes,
+                ..
+            } if matches!(
+                tag_name.as_str(),
+                "base" | "basefont" | "bgsound" | "link" | "meta"
+            ) =>
+            {
+                self.insert_element(tag_name.clone(), attributes.clone());
+                self.open_elements.pop();
+            }
+            Token::StartTag {
+                ref tag_name,
+                ref attributes,
+                ..
+            } if matches!(
+                tag_name.as_str(),
+                "title" | "noscript" | "noframes" | "style" | "script"
+            ) =>
+            {
+                // 支持javascript，<noscript>按RAWTEXT处理
+                let content = match tag_name.as_str() {
+                    "title" => TextContent::Rcdata,
+                    "script" => TextContent::ScriptData,
+                    _ => TextContent::Rawtext,
+                };
+                self.insert_text_element(tag_name.clone(), attributes.clone(), content);
+            }
+            Token::StartTag { ref tag_name, .. } if tag_name == "head" => {
+                self.error(HTMLParseError::UnexpectedStartTag(tag_name.clone()));
+            }
+            Token::EndTag { ref tag_name } if tag_name == "head" => {
+                self.open_elements.pop();
+                self.mode = InsertionMode::AfterHead;
+            }
+            Token::EndTag { ref tag_name }
+                if !matches!(tag_name.as_str(), "body" | "html" | "br") =>
+            {
+                self.error(HTMLParseError::UnexpectedEndTag(tag_name.clone()));
+            }
+            token => {
+                self.open_elements.pop();
+                self.reprocess_in(InsertionMode::AfterHead, token);
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(data) => self.insert_comment(data, self.current_node()),
+            Token::Doctype { .. } => self.error(HTMLParseError::UnexpectedDoctype),
+            Token::StartTag { ref tag_name, .. } if tag_name == "html" => self.in_body(token),
+            Token::StartTag {
+                ref tag_name,
+                ref attributes,
+                ..
+            } if tag_name == "body" => {
+                self.insert_element(tag_name.clone(), attributes.clone());
+                self.mode = InsertionMode::InBody;
+            }
+            Token::StartTag { ref tag_name, .. }
+                if matches!(
+                    tag_name.as_str(),
+                    "base"
+                        | "basefont"
+                        | "bgsound"
+                        | "link"
+                        | "meta"
+                        | "noframes"
+                        | "script"
+                        | "style"
+                        | "title"
+                ) =>
+            {
+                self.error(HTMLParseError::UnexpectedStartTag(tag_name.clone()));
+                // 脚本移除了<head>时插入到当前节点
+                match self.head {
+                    Some(head) => {
+                        self.open_elements.push(head);
+                        self.in_head(token);
+                        self.open_elements.retain(|id| *id != head);
+                    }
+                    None => self.in_head(token),
+                }
+            }
+            Token::StartTag { ref tag_name, .. } if tag_name == "head" => {
+                self.error(HTMLParseError::UnexpectedStartTag(tag_name.clone()));
+            }
+            Token::EndTag { ref tag_name }
+                if !matches!(tag_name.as_str(), "body" | "html" | "br") =>
+            {
+                self.error(HTMLParseError::UnexpectedEndTag(tag_name.clone()));
+            }
+            token => {
+                self.insert_element("body".to_string(), AttrMap::new());
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {}
+            Token::Character(c) => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_character(c);
+            }
+            Token::Comment(data) => self.insert_comment(data, self.current_node()),
+            Token::Doctype { .. } => self.error(HTMLParseError::UnexpectedDoctype),
+            Token::StartTag {
+                tag_name,
+                attributes,
+                ..
+            } => self.in_body_start_tag(tag_name, attributes),
+            Token::EndTag { tag_name } => self.in_body_end_tag(tag_name),
+            Token::Eof => self.stop(),
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag_name: String, attributes: AttrMap) {
+        match tag_name.as_str() {
+            "html" | "body" | "head" | "frame" | "frameset" => {
+                self.error(HTMLParseError::UnexpectedStartTag(tag_name));
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "title" => self.in_head(Token::StartTag {
+                tag_name,
+                attributes,
+                self_closing: false,
+            }),
+            t if BLOCK_ELEMENTS.contains(&t) => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag_name, attributes);
+            }
+            t if HEADING_ELEMENTS.contains(&t) => {
+                self.close_p_element_in_button_scope();
+                if self.current_node_is_one_of(HEADING_ELEMENTS) {
+                    self.error(HTMLParseError::UnexpectedStartTag(tag_name.clone()));
+                    self.open_elements.pop();
+                }
+                self.insert_element(tag_name, attributes);
+            }
+            "pre" | "listing" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag_name, attributes);
+                self.ignore_next_newline = true;
+            }
+            "form" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag_name, attributes);
+            }
+            "plaintext" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag_name, attributes);
+                self.tokenizer_state = Some(State::Plaintext);
+            }
+            "textarea" => {
+                self.insert_text_element(tag_name, attributes, TextContent::Rcdata);
+                self.ignore_next_newline = true;
+            }
+            "xmp" => {
+                self.close_p_element_in_button_scope();
+                self.reconstruct_active_formatting_elements();
+                self.insert_text_element(tag_name, attributes, TextContent::Rawtext);
+            }
+            "iframe" | "noembed" | "noscript" => {
+                self.insert_text_element(tag_name, attributes, TextContent::Rawtext);
+            }
+            "table" => {
+                if self.quirks_mode != QuirksMode::Quirks {
+                    self.close_p_element_in_button_scope();
+                }
+                self.insert_element(tag_name, attributes);
+            }
+            "li" | "dd" | "dt" => {
+                // 关闭未结束的同类列表项
+                let closes: &[&str] = if tag_name == "li" {
+                    &["li"]
+                } else {
+                    &["dd", "dt"]
+                };
+                for id in self.open_elements.clone().into_iter().rev() {
+                    let node_tag_name = self.tag_name(id).unwrap_or("").to_string();
+                    if closes.contains(&node_tag_name.as_str()) {
+                        self.generate_implied_end_tags(Some(&node_tag_name));
+                        if !self.current_node_is(&node_tag_name) {
+                            self.error(HTMLParseError::UnclosedElement(
+                                self.current_tag_name().to_string(),
+                            ));
+                        }
+                        self.pop_until(&[node_tag_name.as_str()]);
+                        break;
+                    }
+                    if SPECIAL_ELEMENTS.contains(&node_tag_name.as_str())
+                        && !matches!(node_tag_name.as_str(), "address" | "div" | "p")
+                    {
+                        break;
+                    }
+                }
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag_name, attributes);
+            }
+            "button" => {
+                if self.has_element_in_scope("button", Scope::Default) {
+                    self.error(HTMLParseError::UnexpectedStartTag(tag_name.clone()));
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag_name, attributes);
+            }
+            "a" => {
+                if let Some(a) = self.formatting_element_after_last_marker("a") {
+                    self.error(HTMLParseError::MisnestedTag(tag_name.clone()));
+                    self.adoption_agency("a");
+                    self.remove_from_active_formatting_elements(a);
+                    self.open_elements.retain(|id| *id != a);
+                }
+                self.reconstruct_active_formatting_elements();
+                let id = self.insert_element(tag_name, attributes);
+                self.push_active_formatting_element(id);
+            }
+            "nobr" => {
+                self.reconstruct_active_formatting_elements();
+                if self.has_element_in_scope("nobr", Scope::Default) {
+                    self.error(HTMLParseError::MisnestedTag(tag_name.clone()));
+                    self.adoption_agency("nobr");
+                    self.reconstruct_active_formatting_elements();
+                }
+                let id = self.insert_element(tag_name, attributes);
+                self.push_active_formatting_element(id);
+            }
+            t if FORMATTING_ELEMENTS.contains(&t) => {
+                self.reconstruct_active_formatting_elements();
+                let id = self.insert_element(tag_name, attributes);
+                self.push_active_formatting_element(id);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag_name, attributes);
+                self.active_formatting_elements
+                    .push(FormattingEntry::Marker);
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" | "input" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag_name, attributes);
+                self.open_elements.pop();
+            }
+            "param" | "source" | "track" => {
+                self.insert_element(tag_name, attributes);
+                self.open_elements.pop();
+            }
+            "hr" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag_name, attributes);
+                self.open_elements.pop();
+            }
+            "image" => {
+                self.error(HTMLParseError::UnexpectedStartTag(tag_name));
+                self.in_body_start_tag("img".to_string(), attributes);
+            }
+            "optgroup" | "option" => {
+                if self.current_node_is("option") {
+                    self.open_elements.pop();
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag_name, attributes);
+            }
+            _ => {
+                self.reconstruct_active_formatting_elements();
+                let is_void = is_void_element(&tag_name);
+                self.insert_element(tag_name, attributes);
+                if is_void {
+                    self.open_elements.pop();
+                }
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag_name: String) {
+        match tag_name.as_str() {
+            "body" | "html" => {
+                if !self.has_element_in_scope("body", Scope::Default) {
+                    self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+                    return;
+                }
+                self.mode = InsertionMode::AfterBody;
+                if tag_name == "html" {
+                    self.dispatch(Token::EndTag { tag_name });
+                }
+            }
+            "br" => {
+                self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+                self.in_body_start_tag("br".to_string(), AttrMap::new());
+            }
+            "p" => {
+                if !self.has_element_in_scope("p", Scope::Button) {
+                    self.error(HTMLParseError::UnexpectedEndTag(tag_name.clone()));
+                    self.insert_element(tag_name, AttrMap::new());
+                }
+                self.close_p_element();
+            }
+            "li" | "dd" | "dt" => {
+                let scope = if tag_name == "li" {
+                    Scope::ListItem
+                } else {
+                    Scope::Default
+                };
+                if !self.has_element_in_scope(&tag_name, scope) {
+                    self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+                    return;
+                }
+                self.generate_implied_end_tags(Some(&tag_name));
+                if !self.current_node_is(&tag_name) {
+                    self.error(HTMLParseError::MisnestedTag(tag_name.clone()));
+                }
+                self.pop_until(&[tag_name.as_str()]);
+            }
+            t if HEADING_ELEMENTS.contains(&t) => {
+                if !HEADING_ELEMENTS
+                    .iter()
+                    .any(|h| self.has_element_in_scope(h, Scope::Default))
+                {
+                    self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_node_is(&tag_name) {
+                    self.error(HTMLParseError::MisnestedTag(tag_name.clone()));
+                }
+                self.pop_until(HEADING_ELEMENTS);
+            }
+            t if FORMATTING_ELEMENTS.contains(&t) => {
+                if !self.adoption_agency(&tag_name) {
+                    self.any_other_end_tag(tag_name);
+                }
+            }
+            "applet" | "marquee" | "object" => {
+                if !self.has_element_in_scope(&tag_name, Scope::Default) {
+                    self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_node_is(&tag_name) {
+                    self.error(HTMLParseError::MisnestedTag(tag_name.clone()));
+                }
+                self.pop_until(&[tag_name.as_str()]);
+                self.clear_active_formatting_elements_to_last_marker();
+            }
+            t if BLOCK_ELEMENTS.contains(&t)
+                || matches!(t, "button" | "listing" | "pre" | "form" | "table") =>
+            {
+                if !self.has_element_in_scope(&tag_name, Scope::Default) {
+                    self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if !self.current_node_is(&tag_name) {
+                    self.error(HTMLParseError::MisnestedTag(tag_name.clone()));
+                }
+                self.pop_until(&[tag_name.as_str()]);
+            }
+            _ => self.any_other_end_tag(tag_name),
+        }
+    }
+
+    fn any_other_end_tag(&mut self, tag_name: String) {
+        for index in (0..self.open_elements.len()).rev() {
+            let id = self.open_elements[index];
+            let node_tag_name = self.tag_name(id).unwrap_or("");
+            if node_tag_name == tag_name {
+                self.generate_implied_end_tags(Some(&tag_name));
+                if self.current_node() != Some(id) {
+                    self.error(HTMLParseError::MisnestedTag(tag_name));
+                }
+                self.open_elements.truncate(index);
+                return;
+            }
+            if SPECIAL_ELEMENTS.contains(&node_tag_name) {
+                self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+                return;
+            }
+        }
+    }
+
+    // <script>, <style>, <title>等的内容
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Character(c) => self.insert_character(c),
+            Token::Comment(_) | Token::Doctype { .. } => {}
+            Token::EndTag { ref tag_name } if self.current_node_is(tag_name) => {
+                if let Some(id) = self.open_elements.pop() {
+                    if tag_name == "script" {
+                        self.pending_script = Some(self.text_content(id));
+                    }
+                }
+                self.mode = self.original_mode;
+            }
+            Token::StartTag { tag_name, .. } => {
+                self.error(HTMLParseError::UnexpectedStartTag(tag_name));
+            }
+            Token::EndTag { tag_name } => {
+                self.error(HTMLParseError::UnexpectedEndTag(tag_name));
+            }
+            Token::Eof => {
+                let tag_name = self.current_tag_name().to_string();
+                self.error(HTMLParseError::UnclosedElement(tag_name));
+                self.open_elements.pop();
+                self.reprocess_in(self.original_mode, Token::Eof);
+            }
+        }
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::Comment(data) => self.insert_comment(data, self.open_elements.first().copied()),
+            Token::Doctype { .. } => self.in_body(token),
+            Token::StartTag { ref tag_name, .. } if tag_name == "html" => self.in_body(token),
+            Token::EndTag { ref tag_name } if tag_name == "html" => {
+                self.mode = InsertionMode::AfterAfterBody;
+            }
+            Token::Eof => self.stop(),
+            token => {
+                self.error(HTMLParseError::UnexpectedContentAfterBody);
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.in_body(token),
+            Token::StartTag { ref tag_name, .. } if tag_name == "html" => self.in_body(token),
+            Token::Eof => self.stop(),
+            token => {
+                self.error(HTMLParseError::UnexpectedContentAfterBody);
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    // ---- tree operations ----
+
+    // 由当前token直接生成的元素记录token的位置，补全的元素没有位置
+    fn create_element(&mut self, tag_name: String, attributes: AttrMap) -> NodeId {
+        let span = if self.token_tag_name.as_ref() == Some(&tag_name) {
+            Some(self.span)
+        } else {
+            None
+        };
+        self.arena.push(ArenaNode {
+            node_type: NodeType::Element(Element {
+                tag_name,
+                attributes,
+            }),
+            children: vec![],
+            parent: None,
+            span,
+        });
+        self.arena.len() - 1
+    }
+
+    fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        if let Some(old_parent) = self.arena[child].parent {
+            self.arena[old_parent].children.retain(|id| *id != child);
+        }
+        self.arena[child].parent = Some(parent);
+        self.arena[parent].children.push(child);
+    }
+
+    fn insert_element(&mut self, tag_name: String, attributes: AttrMap) -> NodeId {
+        let id = self.create_element(tag_name, attributes);
+        if let Some(parent) = self.current_node() {
+            self.append_child(parent, id);
+        }
+        self.open_elements.push(id);
+        id
+    }
+
+    // 连续的字符合并到同一个Text节点
+    fn insert_character(&mut self, c: char) {
+        let parent = match self.current_node() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if let Some(&last) = self.arena[parent].children.last() {
+            let end = self.span.end;
+            let node = &mut self.arena[last];
+            if let NodeType::Text(ref mut t) = node.node_type {
+                t.data.push(c);
+                if let Some(ref mut span) = node.span {
+                    span.end = span.end.max(end);
+                }
+                return;
+            }
+        }
+        self.arena.push(ArenaNode {
+            node_type: NodeType::Text(Text {
+                data: c.to_string(),
+            }),
+            children: vec![],
+            parent: None,
+            span: Some(self.span),
+        });
+        let id = self.arena.len() - 1;
+        self.append_child(parent, id);
+    }
+
+    // 内容直到结束标签为止都作为文本
+    fn insert_text_element(&mut self, tag_name: String, attributes: AttrMap, content: TextContent) {
+        self.insert_element(tag_name, attributes);
+        self.tokenizer_state = Some(content.state());
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    // parent为None时插入到document
+    fn insert_comment(&mut self, data: String, parent: Option<NodeId>) {
+        self.arena.push(ArenaNode {
+            node_type: NodeType::Comment(Comment { data }),
+            children: vec![],
+            parent: None,
+            span: Some(self.span),
+        });
+        let id = self.arena.len() - 1;
+        match parent {
+            Some(parent) => self.append_child(parent, id),
+            None => self.document_children.push(id),
+        }
+    }
+
+    fn clone_element(&mut self, id: NodeId) -> NodeId {
+        let element = match self.arena[id].node_type {
+            NodeType::Element(ref e) => e.clone(),
+            _ => unreachable!("only elements are cloned"),
+        };
+        let clone = self.create_element(element.tag_name, element.attributes);
+        self.arena[clone].span = self.arena[id].span;
+        clone
+    }
+
+    fn text_content(&self, id: NodeId) -> String {
+        self.arena[id]
+            .children
+            .iter()
+            .filter_map(|child| match self.arena[*child].node_type {
+                NodeType::Text(ref t) => Some(t.data.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn tag_name(&self, id: NodeId) -> Option<&str> {
+        match self.arena[id].node_type {
+            NodeType::Element(ref e) => Some(e.tag_name.as_str()),
+            _ => None,
+        }
+    }
+
+    fn current_node(&self) -> Option<NodeId> {
+        self.open_elements.last().copied()
+    }
+
+    fn current_tag_name(&self) -> &str {
+        self.current_node()
+            .and_then(|id| self.tag_name(id))
+            .unwrap_or("")
+    }
+
+    fn current_node_is(&self, tag_name: &str) -> bool {
+        self.current_tag_name() == tag_name
+    }
+
+    fn current_node_is_one_of(&self, tag_names: &[&str]) -> bool {
+        tag_names.contains(&self.current_tag_name())
+    }
+
+    fn has_element_in_scope(&self, tag_name: &str, scope: Scope) -> bool {
+        for id in self.open_elements.iter().rev() {
+            let node_tag_name = self.tag_name(*id).unwrap_or("");
+            if node_tag_name == tag_name {
+                return true;
+            }
+            if scope.is_boundary(node_tag_name) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn has_node_in_scope(&self, target: NodeId) -> bool {
+        for id in self.open_elements.iter().rev() {
+            if *id == target {
+                return true;
+            }
+            if Scope::Default.is_boundary(self.tag_name(*id).unwrap_or("")) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn pop_until(&mut self, tag_names: &[&str]) {
+        while let Some(id) = self.open_elements.pop() {
+            if tag_names.contains(&self.tag_name(id).unwrap_or("")) {
+                break;
+            }
+        }
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while self.current_node_is_one_of(IMPLIED_END_TAG_ELEMENTS)
+            && Some(self.current_tag_name()) != except
+        {
+            self.open_elements.pop();
+        }
+    }
+
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        if !self.current_node_is("p") {
+            self.error(HTMLParseError::UnclosedElement(
+                self.current_tag_name().to_string(),
+            ));
+        }
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_element_in_button_scope(&mut self) {
+        if self.has_element_in_scope("p", Scope::Button) {
+            self.close_p_element();
+        }
+    }
+
+    // ---- list of active formatting elements ----
+
+    fn push_active_formatting_element(&mut self, id: NodeId) {
+        // Noah's Ark: 相同的元素最多保留3个
+        let element = match self.arena[id].node_type {
+            NodeType::Element(ref e) => e,
+            _ => return,
+        };
+        let same: Vec<usize> = self
+            .active_formatting_elements
+            .iter()
+            .enumerate()
+            .rev()
+            .take_while(|(_, entry)| **entry != FormattingEntry::Marker)
+            .filter(|(_, entry)| match entry {
+                FormattingEntry::Element(other) => match self.arena[*other].node_type {
+                    NodeType::Element(ref e) => e == element,
+                    _ => false,
+                },
+                FormattingEntry::Marker => false,
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if same.len() >= 3 {
+            self.active_formatting_elements
+                .remove(*same.last().unwrap());
+        }
+        self.active_formatting_elements
+            .push(FormattingEntry::Element(id));
+    }
+
+    fn formatting_element_after_last_marker(&self, tag_name: &str) -> Option<NodeId> {
+        for entry in self.active_formatting_elements.iter().rev() {
+            match entry {
+                FormattingEntry::Marker => return None,
+                FormattingEntry::Element(id) if self.tag_name(*id) == Some(tag_name) => {
+                    return Some(*id)
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn formatting_position(&self, id: NodeId) -> Option<usize> {
+        self.active_formatting_elements
+            .iter()
+            .position(|entry| *entry == FormattingEntry::Element(id))
+    }
+
+    fn remove_from_active_formatting_elements(&mut self, id: NodeId) {
+        self.active_formatting_elements
+            .retain(|entry| *entry != FormattingEntry::Element(id));
+    }
+
+    fn clear_active_formatting_elements_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting_elements.pop() {
+            if entry == FormattingEntry::Marker {
+                break;
+            }
+        }
+    }
+
+    fn reconstruct_active_formatting_elements(&mut self) {
+        let is_open = |builder: &Self, entry: &FormattingEntry| match entry {
+            FormattingEntry::Marker => true,
+            FormattingEntry::Element(id) => builder.open_elements.contains(id),
+        };
+        let last = match self.active_formatting_elements.last() {
+            Some(entry) => entry,
+            None => return,
+        };
+        if is_open(self, last) {
+            return;
+        }
+
+        let mut index = self.active_formatting_elements.len() - 1;
+        while index > 0 && !is_open(self, &self.active_formatting_elements[index - 1]) {
+            index -= 1;
+        }
+        for i in index..self.active_formatting_elements.len() {
+            if let FormattingEntry::Element(id) = self.active_formatting_elements[i] {
+                let new_id = self.clone_element(id);
+                if let Some(parent) = self.current_node() {
+                    self.append_child(parent, new_id);
+                }
+                self.open_elements.push(new_id);
+                self.active_formatting_elements[i] = FormattingEntry::Element(new_id);
+            }
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm
+    // 返回false时按照any other end tag处理
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        if let Some(current) = self.current_node() {
+            if self.tag_name(current) == Some(subject)
+                && self.formatting_position(current).is_none()
+            {
+                self.open_elements.pop();
+                return true;
+            }
+        }
+
+        for _ in 0..8 {
+            let formatting_element = match self.formatting_element_after_last_marker(subject) {
+                Some(id) => id,
+                None => return false,
+            };
+            let fe_index = match self
+                .open_elements
+                .iter()
+                .position(|id| *id == formatting_element)
+            {
+                Some(index) => index,
+                None => {
+                    self.error(HTMLParseError::MisnestedTag(subject.to_string()));
+                    self.remove_from_active_formatting_elements(formatting_element);
+                    return true;
+                }
+            };
+            if !self.has_node_in_scope(formatting_element) {
+                self.error(HTMLParseError::MisnestedTag(subject.to_string()));
+                return true;
+            }
+            if self.current_node() != Some(formatting_element) {
+                self.error(HTMLParseError::MisnestedTag(subject.to_string()));
+            }
+
+            let furthest_block = self.open_elements[fe_index + 1..]
+                .iter()
+                .copied()
+                .find(|id| SPECIAL_ELEMENTS.contains(&self.tag_name(*id).unwrap_or("")));
+            let furthest_block = match furthest_block {
+                Some(id) => id,
+                None => {
+                    self.open_elements.truncate(fe_index);
+                    self.remove_from_active_formatting_elements(formatting_element);
+                    return true;
+                }
+            };
+
+            let common_ancestor = self.open_elements[fe_index - 1];
+            let mut bookmark = Bookmark::Replace(formatting_element);
+            let mut node_index = self
+                .open_elements
+                .iter()
+                .position(|id| *id == furthest_block)
+                .unwrap();
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_index -= 1;
+                let node = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                if inner_loop_counter > 3 {
+                    self.remove_from_active_formatting_elements(node);
+                }
+                let position = match self.formatting_position(node) {
+                    Some(position) => position,
+                    None => {
+                        self.open_elements.remove(node_index);
+                        continue;
+                    }
+                };
+                let new_node = self.clone_element(node);
+                self.active_formatting_elements[position] = FormattingEntry::Element(new_node);
+                self.open_elements[node_index] = new_node;
+                if last_node == furthest_block {
+                    bookmark = Bookmark::After(new_node);
+                }
+                self.append_child(new_node, last_node);
+                last_node = new_node;
+            }
+
+            self.append_child(common_ancestor, last_node);
+
+            let new_element = self.clone_element(formatting_element);
+            let children = std::mem::take(&mut self.arena[furthest_block].children);
+            for child in children {
+                self.arena[child].parent = Some(new_element);
+                self.arena[new_element].children.push(child);
+            }
+            self.append_child(furthest_block, new_element);
+
+            let position = match bookmark {
+                Bookmark::Replace(id) => self.formatting_position(id).unwrap(),
+                Bookmark::After(id) => self.formatting_position(id).unwrap() + 1,
+            };
+            self.active_formatting_elements
+                .insert(position, FormattingEntry::Element(new_element));
+            self.remove_from_active_formatting_elements(formatting_element);
+
+            self.open_elements.retain(|id| *id != formatting_element);
+            let fb_index = self
+                .open_elements
+                .iter()
+                .position(|id| *id == furthest_block)
+                .unwrap();
+            self.open_elements.insert(fb_index + 1, new_element);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tokenizer::Tokenizer;
+    use super::*;
+
+    fn build_document(html: &str) -> (Box<Node>, Vec<Diagnostic<HTMLParseError>>) {
+        let mut tokenizer = Tokenizer::new(html);
+        let mut builder = TreeBuilder::new();
+        while !builder.is_stopped() {
+            let (token, span) = tokenizer.next_token().unwrap();
+            builder.process(token, span);
+            if let Some(state) = builder.take_tokenizer_state() {
+                tokenizer.set_state(state);
+            }
+        }
+        let errors = builder.take_errors();
+        (builder.finish().1, errors)
+    }
+
+    fn build(html: &str) -> (String, Vec<HTMLParseError>) {
+        let (html, errors) = build_document(html);
+        (
+            html.to_string(),
+            errors.into_iter().map(|e| e.error).collect(),
+        )
+    }
+
+    #[test]
+    fn test_synthesize_html_head_body() {
+        assert_eq!(
+            build("<p>Hello</p>").0,
+            "<html><head></head><body><p>Hello</p></body></html>"
+        );
+        assert_eq!(
+            build("\n<title>t</title><div>x</div>").0,
+            "<html><head><title>t</title></head><body><div>x</div></body></html>"
+        );
+        assert_eq!(build("").0, "<html><head></head><body></body></html>");
+    }
+
+    #[test]
+    fn test_implied_end_tags() {
+        assert_eq!(
+            build("<p>one<p>two<div>three</div>").0,
+            "<html><head></head><body><p>one</p><p>two</p><div>three</div></body></html>"
+        );
+        assert_eq!(
+            build("<ul><li>a<li>b</ul>").0,
+            "<html><head></head><body><ul><li>a</li><li>b</li></ul></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_stray_end_tag() {
+        let (html, errors) = build("<!DOCTYPE html><div>a</span></div></div>b");
+        assert_eq!(html, "<html><head></head><body><div>a</div>b</body></html>");
+        assert_eq!(
+            errors,
+            vec![
+                HTMLParseError::UnexpectedEndTag("span".to_string()),
+                HTMLParseError::UnexpectedEndTag("div".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_misnested_formatting_elements() {
+        assert_eq!(
+            build("<b>1<i>2</b>3</i>").0,
+            "<html><head></head><body><b>1<i>2</i></b><i>3</i></body></html>"
+        );
+        assert_eq!(
+            build("<b>1<p>2</b>3</p>").0,
+            "<html><head></head><body><b>1</b><p><b>2</b>3</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_tag_name_case_and_digits() {
+        assert_eq!(
+            build("<H1>a</h1><DiV><h2>b<h3>c</h2></DIV>").0,
+            "<html><head></head><body><h1>a</h1><div><h2>b</h2><h3>c</h3></div></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_raw_text_elements() {
+        assert_eq!(
+            build("<title>a<b>&amp;</title><script>if (a<b) {}</script><style>ul > li {}</style>").0,
+            "<html><head><title>a&lt;b&gt;&amp;</title><script>if (a<b) {}</script><style>ul > li {}</style></head><body></body></html>"
+        );
+        assert_eq!(
+            build("<textarea>\n<p>x</p></textarea><pre>\n\ny</pre>").0,
+            "<html><head></head><body><textarea>&lt;p&gt;x&lt;/p&gt;</textarea><pre>\ny</pre></body></html>"
+        );
+        let (html, errors) = build("<!DOCTYPE html><script>a<b");
+        assert_eq!(
+            html,
+            "<html><head><script>a<b</script></head><body></body></html>"
+        );
+        assert_eq!(
+            errors,
+            vec![HTMLParseError::UnclosedElement("script".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_void_elements() {
+        let (html, errors) = build(
+            "<!DOCTYPE html><p>a<br>b<img src=\"x.png\"/>c</p><hr><input type=\"text\"></input><div/>d</div>",
+        );
+        assert_eq!(
+            html,
+            "<html><head></head><body><p>a<br>b<img src=\"x.png\">c</p><hr><input type=\"text\"><div>d</div></body></html>"
+        );
+        assert_eq!(
+            errors,
+            vec![
+                HTMLParseError::UnexpectedEndTag("input".to_string()),
+                HTMLParseError::NonVoidSelfClosingTag("div".to_string()),
+            ]
+        );
+        assert_eq!(
+            build("<p>a</br>b</p>").0,
+            "<html><head></head><body><p>a<br>b</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            build("<!-- a --><html><!-- b --><p>c<!-- d --></p></html><!-- e -->").0,
+            "<html><!-- b --><head></head><body><p>c<!-- d --></p></body></html>"
+        );
+        assert_eq!(
+            build("<p>a</p></body><!-- b -->").0,
+            "<html><head></head><body><p>a</p></body><!-- b --></html>"
+        );
+    }
+
+    #[test]
+    fn test_doctype_quirks_mode() {
+        let quirks_mode = |html: &str| {
+            let mut tokenizer = Tokenizer::new(html);
+            let mut builder = TreeBuilder::new();
+            while !builder.is_stopped() {
+                let (token, span) = tokenizer.next_token().unwrap();
+                builder.process(token, span);
+            }
+            builder.quirks_mode()
+        };
+        assert_eq!(quirks_mode("<!DOCTYPE html><p>"), QuirksMode::NoQuirks);
+        assert_eq!(quirks_mode("<p>"), QuirksMode::Quirks);
+        assert_eq!(
+            quirks_mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">"),
+            QuirksMode::Quirks
+        );
+        assert_eq!(
+            quirks_mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">"),
+            QuirksMode::LimitedQuirks
+        );
+        assert_eq!(
+            quirks_mode("<!DOCTYPE html SYSTEM \"about:legacy-compat\">"),
+            QuirksMode::NoQuirks
+        );
+    }
+
+    #[test]
+    fn test_unclosed_element() {
+        let (html, errors) = build("<!DOCTYPE html><div><span>a");
+        assert_eq!(
+            html,
+            "<html><head></head><body><div><span>a</span></div></body></html>"
+        );
+        assert_eq!(
+            errors,
+            vec![
+                HTMLParseError::UnclosedElement("div".to_string()),
+                HTMLParseError::UnclosedElement("span".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_node_spans() {
+        let (html, errors) = build_document("<!DOCTYPE html>\n<p>a&amp;b\n</div>");
+        let body = &html.children[1];
+        let p = &body.children[0];
+        let span = |node: &Node| node.span.map(|s| (s.start.offset, s.end.offset));
+        // 补全的<html>, <body>没有位置
+        assert_eq!(span(&html), None);
+        assert_eq!(span(body), None);
+        assert_eq!(span(p), Some((16, 19)));
+        assert_eq!(span(&p.children[0]), Some((19, 27)));
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.error.clone(), e.span.start.line, e.span.start.column))
+                .collect::<Vec<_>>(),
+            vec![(HTMLParseError::UnexpectedEndTag("div".to_string()), 3, 1)]
+        );
+    }
+}
@@ -0,0 +1,482 @@
+use super::{BoxType, Fragment, FragmentKind, LayoutBox, LineBox, Rect};
+use crate::core::{
+    css::CSSValue,
+    dom::{element::Element, node::NodeType},
+    style::PropertyMap,
+};
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// pre中的制表符
+const TAB_SIZE: usize = 8;
+// 断行时代表不能折行的行内元素
+const OBJECT_REPLACEMENT: char = '\u{fffc}';
+
+// 行内内容展开后的单位，boxes为从根开始的children下标
+#[derive(Debug, PartialEq)]
+pub enum InlineItem {
+    // 已经处理过空白的文本
+    Text {
+        text: String,
+        white_space: WhiteSpace,
+        boxes: Vec<usize>,
+    },
+    Atomic {
+        width: i32,
+        boxes: Vec<usize>,
+    },
+    Block {
+        boxes: Vec<usize>,
+    },
+}
+
+// white-space属性
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WhiteSpace {
+    // 合并连续的空白
+    collapse: bool,
+    // 保留换行
+    newlines: bool,
+    // 允许自动折行
+    wrap: bool,
+}
+
+impl WhiteSpace {
+    fn from_properties(properties: &PropertyMap) -> Self {
+        let (collapse, newlines, wrap) = match properties.get("white-space") {
+            Some(CSSValue::Keyword(k)) => match k.as_str() {
+                "pre" => (false, true, false),
+                "pre-wrap" => (false, true, true),
+                "pre-line" => (true, true, true),
+                "nowrap" => (true, false, false),
+                _ => (true, false, true),
+            },
+            _ => (true, false, true),
+        };
+        WhiteSpace {
+            collapse,
+            newlines,
+            wrap,
+        }
+    }
+}
+
+// 按文档顺序收集lbox中的行内内容
+// last_space为前面是否是可以合并的空白，行首的空白也要去掉
+pub fn collect_items(
+    lbox: &LayoutBox,
+    boxes: &mut Vec<usize>,
+    items: &mut Vec<InlineItem>,
+    last_space: &mut bool,
+) {
+    let props = lbox.box_props.as_ref();
+    match props.map(|p| p.node_type.as_ref()) {
+        Some(NodeType::Text(t)) => {
+            let white_space = WhiteSpace::from_properties(&props.unwrap().properties);
+            let text = process_white_space(&t.data, white_space, last_space);
+            if !text.is_empty() {
+                items.push(InlineItem::Text {
+                    text,
+                    white_space,
+                    boxes: boxes.clone(),
+                });
+            }
+        }
+        Some(NodeType::Element(e)) if e.tag_name == "input" => {
+            items.push(InlineItem::Atomic {
+                width: input_width(e),
+                boxes: boxes.clone(),
+            });
+            *last_space = false;
+        }
+        _ if matches!(lbox.box_type, BoxType::BlockBox) && !boxes.is_empty() => {
+            items.push(InlineItem::Block {
+                boxes: boxes.clone(),
+            });
+            *last_space = true;
+        }
+        _ => {
+            for (i, child) in lbox.children.iter().enumerate() {
+                boxes.push(i);
+                collect_items(child, boxes, items, last_space);
+                boxes.pop();
+            }
+        }
+    }
+}
+
+// 和ui::render中的view宽度一致
+fn input_width(element: &Element) -> i32 {
+    let attribute = |name: &str| element.attributes.get(name).map_or("", |v| v.as_str());
+    match attribute("type") {
+        // <value>
+        "button" | "submit" => UnicodeWidthStr::width(attribute("value")) as i32 + 2,
+        _ => 10,
+    }
+}
+
+// https://www.w3.org/TR/css-text-3/#white-space-processing
+fn process_white_space(raw: &str, white_space: WhiteSpace, last_space: &mut bool) -> String {
+    let mut text = String::new();
+    for c in raw.chars() {
+        match c {
+            '\n' if white_space.newlines => {
+                // 换行前后的空白也去掉
+                if white_space.collapse {
+                    text.truncate(text.trim_end_matches(' ').len());
+                }
+                text.push('\n');
+                *last_space = true;
+            }
+            ' ' | '\t' | '\n' | '\r' | '\x0c' if white_space.collapse => {
+                if !*last_space {
+                    text.push(' ');
+                    *last_space = true;
+                }
+            }
+            '\t' => {
+                let column = text.rsplit('\n').next().map_or(0, |l| l.width());
+                text.push_str(&" ".repeat(TAB_SIZE - column % TAB_SIZE));
+                *last_space = false;
+            }
+            '\r' => {}
+            c => {
+                text.push(c);
+                *last_space = false;
+            }
+        }
+    }
+    text
+}
+
+// 一行中排好的部分，item为所属的InlineItem
+struct Piece {
+    item: usize,
+    text: String,
+    width: i32,
+}
+
+// 正在排列的行
+struct LineBuilder<'i> {
+    items: &'i [InlineItem],
+    x: i32,
+    y: i32,
+    width: i32,
+    pieces: Vec<Piece>,
+    lines: Vec<LineBox>,
+}
+
+impl<'i> LineBuilder<'i> {
+    fn line_width(&self) -> i32 {
+        self.pieces.iter().map(|p| p.width).sum()
+    }
+
+    // 同一个文本的连续部分合并为一个片段
+    fn push(&mut self, piece: Piece) {
+        match self.pieces.last_mut() {
+            Some(last)
+                if last.item == piece.item
+                    && matches!(self.items[piece.item], InlineItem::Text { .. }) =>
+            {
+                last.text.push_str(&piece.text);
+                last.width += piece.width;
+            }
+            _ => self.pieces.push(piece),
+        }
+    }
+
+    // 放入一段两端都是断行机会的内容，放不下时先换行
+    fn push_segment(&mut self, segment: Vec<Piece>) {
+        let width: i32 = segment.iter().map(|p| p.width).sum();
+        let visible = width - hanging_width(self.items, &segment);
+        if self.line_width() + visible <= self.width {
+            segment.into_iter().for_each(|p| self.push(p));
+            return;
+        }
+        if !self.pieces.is_empty() {
+            self.finish_line(false);
+        }
+        if visible <= self.width {
+            segment.into_iter().for_each(|p| self.push(p));
+            return;
+        }
+        // 终端不能横向滚动，一行放不下的词按字符折断
+        for piece in segment {
+            match self.items[piece.item] {
+                InlineItem::Text {
+                    ref white_space, ..
+                } if white_space.wrap => {
+                    for c in piece.text.chars() {
+                        let width = c.width().unwrap_or(0) as i32;
+                        if self.line_width() + width > self.width && !self.pieces.is_empty() {
+                            self.finish_line(false);
+                        }
+                        self.push(Piece {
+                            item: piece.item,
+                            text: c.to_string(),
+                            width,
+                        });
+                    }
+                }
+                _ => self.push(piece),
+            }
+        }
+    }
+
+    // forced为true时即使是空行也保留，如pre中连续的换行
+    fn finish_line(&mut self, forced: bool) {
+        // 行末可以合并的空白去掉
+        if let Some(last) = self.pieces.last_mut() {
+            if let InlineItem::Text { white_space, .. } = self.items[last.item] {
+                if white_space.collapse {
+                    let trimmed = last.text.trim_end_matches(' ').len();
+                    last.width -= (last.text.len() - trimmed) as i32;
+                    last.text.truncate(trimmed);
+                }
+            }
+        }
+        self.pieces.retain(|p| !p.text.is_empty() || p.width > 0);
+        if self.pieces.is_empty() && !forced {
+            return;
+        }
+
+        let y = self.y + self.lines.len() as i32;
+        let mut x = self.x;
+        let fragments = std::mem::take(&mut self.pieces)
+            .into_iter()
+            .map(|piece| {
+                let (kind, boxes) = match &self.items[piece.item] {
+                    InlineItem::Text { boxes, .. } => (FragmentKind::Text(piece.text), boxes),
+                    InlineItem::Atomic { boxes, .. } => (FragmentKind::Atomic, boxes),
+                    InlineItem::Block { boxes } => (FragmentKind::Block, boxes),
+                };
+                let rect = Rect {
+                    x,
+                    y,
+                    width: piece.width,
+                    height: 1,
+                };
+                x += piece.width;
+                Fragment {
+                    kind,
+                    rect,
+                    boxes: boxes.clone(),
+                }
+            })
+            .collect();
+        self.lines.push(LineBox {
+            rect: Rect {
+                x: self.x,
+                y,
+                width: self.width,
+                height: 1,
+            },
+            fragments,
+        });
+    }
+}
+
+// 行末的空白不占宽度，不影响能否放下
+fn hanging_width(items: &[InlineItem], segment: &[Piece]) -> i32 {
+    let mut width = 0;
+    for piece in segment.iter().rev() {
+        match items[piece.item] {
+            InlineItem::Text { white_space, .. } if white_space.wrap => {
+                let trimmed = piece.text.trim_end_matches(' ');
+                width += (piece.text.len() - trimmed.len()) as i32;
+                if !trimmed.is_empty() {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+    width
+}
+
+// 从(x, y)开始把不含块级元素的行内内容排成不超过width的行
+// 断行机会按UAX #14，不允许折行的white-space中只在换行处断行
+pub fn break_lines(items: &[InlineItem], x: i32, y: i32, width: i32) -> Vec<LineBox> {
+    // 所有文本连在一起才能找到跨元素的断行机会
+    let mut paragraph = String::new();
+    let mut starts = vec![];
+    for item in items {
+        starts.push(paragraph.len());
+        match item {
+            InlineItem::Text { text, .. } => paragraph.push_str(text),
+            _ => paragraph.push(OBJECT_REPLACEMENT),
+        }
+    }
+    // 包含第offset个字节的item
+    let item_at = |offset: usize| starts.partition_point(|&s| s <= offset) - 1;
+    let wraps = |offset: usize| match items[item_at(offset)] {
+        InlineItem::Text { white_space, .. } => white_space.wrap,
+        _ => true,
+    };
+
+    let mut builder = LineBuilder {
+        items,
+        x,
+        y,
+        width,
+        pieces: vec![],
+        lines: vec![],
+    };
+    let mut start = 0;
+    for (end, opportunity) in linebreaks(&paragraph) {
+        let mandatory = opportunity == BreakOpportunity::Mandatory;
+        if !mandatory && !wraps(end - 1) {
+            continue;
+        }
+        let mut segment = vec![];
+        let mut offset = start;
+        while offset < end {
+            let item = item_at(offset);
+            let item_end = starts.get(item + 1).map_or(end, |&s| s.min(end));
+            let piece = match items[item] {
+                InlineItem::Atomic { width, .. } => Piece {
+                    item,
+                    text: String::new(),
+                    width,
+                },
+                _ => {
+                    let text = paragraph[offset..item_end].replace('\n', "");
+                    let width = text.width() as i32;
+                    Piece { item, text, width }
+                }
+            };
+            segment.push(piece);
+            offset = item_end;
+        }
+        builder.push_segment(segment);
+        if mandatory {
+            // 文本末尾的断行机会不算换行
+            let forced = paragraph[..end].ends_with('\n');
+            builder.finish_line(forced);
+        }
+        start = end;
+    }
+    builder.lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str, white_space: &str, boxes: Vec<usize>) -> InlineItem {
+        let mut properties = PropertyMap::new();
+        properties.insert(
+            "white-space".to_string(),
+            CSSValue::Keyword(white_space.to_string()),
+        );
+        InlineItem::Text {
+            text: process_white_space(s, WhiteSpace::from_properties(&properties), &mut false),
+            white_space: WhiteSpace::from_properties(&properties),
+            boxes,
+        }
+    }
+
+    // 每行的文本
+    fn line_texts(lines: &[LineBox]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| {
+                line.fragments
+                    .iter()
+                    .map(|f| match f.kind {
+                        FragmentKind::Text(ref t) => t.clone(),
+                        _ => "[]".to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("|")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_process_white_space() {
+        let normal = WhiteSpace::from_properties(&PropertyMap::new());
+        let mut last_space = true;
+        assert_eq!(
+            process_white_space("  a \n\t b  ", normal, &mut last_space),
+            "a b "
+        );
+        // 跨文本节点合并
+        assert_eq!(process_white_space(" c", normal, &mut last_space), "c");
+
+        let mut properties = PropertyMap::new();
+        let mut white_space = |value: &str| {
+            properties.insert(
+                "white-space".to_string(),
+                CSSValue::Keyword(value.to_string()),
+            );
+            WhiteSpace::from_properties(&properties)
+        };
+        assert_eq!(
+            process_white_space("a  \n  b\tc", white_space("pre-line"), &mut false),
+            "a\nb c"
+        );
+        assert_eq!(
+            process_white_space("a \n\tb", white_space("pre"), &mut false),
+            "a \n        b"
+        );
+    }
+
+    #[test]
+    fn test_break_lines() {
+        // <p>The quick <i>brown</i> fox<input> jumps</p>
+        let items = vec![
+            text("The quick ", "normal", vec![0]),
+            text("brown", "normal", vec![1, 0]),
+            text(" fox", "normal", vec![2]),
+            InlineItem::Atomic {
+                width: 10,
+                boxes: vec![3],
+            },
+            text(" jumps", "normal", vec![4]),
+        ];
+        let lines = break_lines(&items, 2, 5, 16);
+        assert_eq!(
+            line_texts(&lines),
+            vec!["The quick |brown", "fox|[]", "jumps"]
+        );
+        assert_eq!(
+            lines[0].fragments[1],
+            Fragment {
+                kind: FragmentKind::Text("brown".to_string()),
+                rect: Rect {
+                    x: 12,
+                    y: 5,
+                    width: 5,
+                    height: 1
+                },
+                boxes: vec![1, 0],
+            }
+        );
+        assert_eq!(lines[1].fragments[1].rect.x, 5);
+        assert_eq!(lines[2].rect.y, 7);
+
+        // 太长的词按字符折断
+        let lines = break_lines(&[text("abcdefgh ij", "normal", vec![])], 0, 0, 3);
+        assert_eq!(line_texts(&lines), vec!["abc", "def", "gh", "ij"]);
+    }
+
+    #[test]
+    fn test_break_lines_cjk_and_white_space() {
+        // 汉字之间可以断行，每个占两格
+        let lines = break_lines(&[text("你好，世界！", "normal", vec![])], 0, 0, 7);
+        assert_eq!(line_texts(&lines), vec!["你好，", "世界！"]);
+        assert_eq!(lines[0].fragments[0].rect.width, 6);
+
+        let lines = break_lines(&[text("😀😀 a", "normal", vec![])], 0, 0, 4);
+        assert_eq!(line_texts(&lines), vec!["😀😀", "a"]);
+
+        // pre中只在换行处断行，保留空行
+        let lines = break_lines(&[text("a b c\n\nd", "pre", vec![])], 0, 0, 2);
+        assert_eq!(line_texts(&lines), vec!["a b c", "", "d"]);
+        let lines = break_lines(&[text("a b c", "nowrap", vec![])], 0, 0, 2);
+        assert_eq!(line_texts(&lines), vec!["a b c"]);
+        let lines = break_lines(&[text("a b\nc", "pre-wrap", vec![])], 0, 0, 2);
+        assert_eq!(line_texts(&lines), vec!["a ", "b", "c"]);
+    }
+}
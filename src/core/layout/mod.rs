@@ -0,0 +1,704 @@
+use super::style::{Display, StyledDocument, Viewport, CELL_HEIGHT_PX, CELL_WIDTH_PX};
+use super::{
+    css::{CSSValue, CalcExpression, Unit},
+    dom::node::NodeType,
+    style::{PropertyMap, StyledNode},
+};
+use inline::InlineItem;
+use std::borrow::Cow;
+
+mod inline;
+
+pub struct LayoutDocument<'a> {
+    pub top_box: LayoutBox<'a>,
+}
+
+#[derive(Debug)]
+pub struct LayoutBox<'a> {
+    pub box_type: BoxType,
+    pub box_props: Option<BoxProps<'a>>,
+    pub dimensions: Dimensions,
+    // 行内格式化上下文的根排列好的行
+    pub lines: Vec<LineBox>,
+    pub children: Vec<LayoutBox<'a>>,
+}
+
+#[derive(Debug)]
+pub enum BoxType {
+    BlockBox,
+    InlineBox,
+    NoneBox,
+    AnonymousBox,
+}
+
+#[derive(Debug)]
+pub struct BoxProps<'a> {
+    // ::before和::after为生成的文本
+    pub node_type: Cow<'a, NodeType>,
+    // 用于找回获得焦点的元素
+    pub path: Vec<usize>,
+    pub properties: PropertyMap,
+}
+
+// 以字符格为单位，x向右，y向下
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// margin可以为负数
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EdgeSizes {
+    pub left: i32,
+    pub right: i32,
+    pub top: i32,
+    pub bottom: i32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Dimensions {
+    // 内容区域
+    pub content: Rect,
+    pub padding: EdgeSizes,
+    pub border: EdgeSizes,
+    pub margin: EdgeSizes,
+}
+
+impl Rect {
+    pub fn expanded_by(self, edge: EdgeSizes) -> Rect {
+        Rect {
+            x: self.x - edge.left,
+            y: self.y - edge.top,
+            width: self.width + edge.left + edge.right,
+            height: self.height + edge.top + edge.bottom,
+        }
+    }
+}
+
+impl Dimensions {
+    pub fn padding_box(self) -> Rect {
+        self.content.expanded_by(self.padding)
+    }
+
+    pub fn border_box(self) -> Rect {
+        self.padding_box().expanded_by(self.border)
+    }
+
+    pub fn margin_box(self) -> Rect {
+        self.border_box().expanded_by(self.margin)
+    }
+}
+
+// 一行，文本和行内元素的行高总是1个字符格
+// 块级元素单独占一行，高度为它的margin box的高度
+#[derive(Debug, PartialEq)]
+pub struct LineBox {
+    pub rect: Rect,
+    pub fragments: Vec<Fragment>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Fragment {
+    pub kind: FragmentKind,
+    pub rect: Rect,
+    // 从行内格式化上下文的根到所属盒子的children下标
+    pub boxes: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FragmentKind {
+    // 折行后同一个文本节点在一行中的部分
+    Text(String),
+    // 不能折行的行内元素，如<input>
+    Atomic,
+    // 行内元素中的块级元素
+    Block,
+}
+
+// 一个字符格的宽和高不同，换算长度时需要区分方向
+#[derive(Debug, Clone, Copy)]
+enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl<'a> LayoutBox<'a> {
+    fn new(box_type: BoxType, box_props: Option<BoxProps<'a>>) -> Self {
+        LayoutBox {
+            box_type,
+            box_props,
+            dimensions: Dimensions::default(),
+            lines: vec![],
+            children: vec![],
+        }
+    }
+
+    pub fn inline_container(&mut self) -> &mut LayoutBox<'a> {
+        match self.box_type {
+            BoxType::InlineBox | BoxType::NoneBox | BoxType::AnonymousBox => self,
+            // 父元素内即包含块级元素又包含行级元素会生成匿名盒子，将行级元素升级为块级元素
+            BoxType::BlockBox => {
+                match self.children.last() {
+                    Some(&LayoutBox {
+                        box_type: BoxType::AnonymousBox,
+                        ..
+                    }) => {}
+                    _ => self
+                        .children
+                        .push(LayoutBox::new(BoxType::AnonymousBox, None)),
+                }
+                self.children.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub fn inner_text(&self) -> String {
+        self.children
+            .iter()
+            .clone()
+            .into_iter()
+            .map(
+                |node| match node.box_props.as_ref().map(|p| p.node_type.as_ref()) {
+                    Some(NodeType::Text(t)) => t.data.clone(),
+                    _ => node.inner_text(),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join("")
+    }
+
+    // containing_block的content.height为已经排列的兄弟元素的高度
+    fn layout(&mut self, containing_block: Dimensions, viewport: &Viewport) {
+        match self.box_type {
+            BoxType::BlockBox => self.layout_block(containing_block, viewport),
+            BoxType::InlineBox | BoxType::AnonymousBox => {
+                self.layout_inline(containing_block, viewport)
+            }
+            BoxType::NoneBox => {}
+        }
+    }
+
+    fn layout_block(&mut self, containing_block: Dimensions, viewport: &Viewport) {
+        self.calculate_block_width(containing_block, viewport);
+        self.calculate_block_position(containing_block, viewport);
+        self.layout_block_children(viewport);
+        self.calculate_block_height(containing_block, viewport);
+    }
+
+    // https://www.w3.org/TR/CSS2/visudet.html#blockwidth
+    fn calculate_block_width(&mut self, containing_block: Dimensions, viewport: &Viewport) {
+        let props = match self.box_props {
+            Some(ref p) => &p.properties,
+            None => return,
+        };
+        let containing_width = containing_block.content.width;
+        let length = |name: &str| {
+            props
+                .get(name)
+                .and_then(|v| to_cells(v, containing_width, Axis::Horizontal, viewport))
+        };
+        let d = &mut self.dimensions;
+        d.padding.left = length("padding-left").unwrap_or(0);
+        d.padding.right = length("padding-right").unwrap_or(0);
+        d.border.left = border_width(props, "left");
+        d.border.right = border_width(props, "right");
+        // border-box时width包括padding和border
+        let edges = d.padding.left + d.padding.right + d.border.left + d.border.right;
+        let content_width = |width: i32| match props.get("box-sizing") {
+            Some(CSSValue::Keyword(k)) if k == "border-box" => (width - edges).max(0),
+            _ => width,
+        };
+        let width = length("width").map(content_width);
+        let max_width = length("max-width").map(content_width);
+
+        // margin为auto时为None
+        let margin_left = length("margin-left");
+        let margin_right = length("margin-right");
+        let (mut width, mut left, mut right) =
+            resolve_width(width, margin_left, margin_right, edges, containing_width);
+        // 超过max-width时按width: max-width重新计算
+        if let Some(max_width) = max_width {
+            if width > max_width {
+                (width, left, right) = resolve_width(
+                    Some(max_width),
+                    margin_left,
+                    margin_right,
+                    edges,
+                    containing_width,
+                );
+            }
+        }
+        d.content.width = width;
+        d.margin.left = left;
+        d.margin.right = right;
+    }
+
+    fn calculate_block_position(&mut self, containing_block: Dimensions, viewport: &Viewport) {
+        let props = match self.box_props {
+            Some(ref p) => &p.properties,
+            None => return,
+        };
+        // 百分比也相对于包含块的宽度
+        let length = |name: &str| {
+            props
+                .get(name)
+                .and_then(|v| to_cells(v, containing_block.content.width, Axis::Vertical, viewport))
+                .unwrap_or(0)
+        };
+        let d = &mut self.dimensions;
+        d.margin.top = length("margin-top");
+        d.margin.bottom = length("margin-bottom");
+        d.padding.top = length("padding-top");
+        d.padding.bottom = length("padding-bottom");
+        d.border.top = border_width(props, "top");
+        d.border.bottom = border_width(props, "bottom");
+
+        d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
+        // 放在已有的兄弟元素下面，不合并margin
+        d.content.y = containing_block.content.y
+            + containing_block.content.height
+            + d.margin.top
+            + d.border.top
+            + d.padding.top;
+    }
+
+    fn layout_block_children(&mut self, viewport: &Viewport) {
+        let d = &mut self.dimensions;
+        for child in &mut self.children {
+            child.layout(*d, viewport);
+            d.content.height += child.dimensions.margin_box().height;
+        }
+    }
+
+    // 没有指定height时为子元素的高度之和
+    // 包含块的高度还不确定，百分比当作auto
+    fn calculate_block_height(&mut self, containing_block: Dimensions, viewport: &Viewport) {
+        let props = match self.box_props {
+            Some(ref p) => &p.properties,
+            None => return,
+        };
+        let height = match props.get("height") {
+            Some(CSSValue::Length((_, Unit::Percent))) | None => return,
+            Some(v) => to_cells(v, containing_block.content.width, Axis::Vertical, viewport),
+        };
+        let d = &mut self.dimensions;
+        if let Some(height) = height {
+            d.content.height = match props.get("box-sizing") {
+                Some(CSSValue::Keyword(k)) if k == "border-box" => {
+                    let edges = d.padding.top + d.padding.bottom + d.border.top + d.border.bottom;
+                    (height - edges).max(0)
+                }
+                _ => height,
+            };
+        }
+    }
+
+    // 匿名盒子等行内格式化上下文的根，把行内内容排成行
+    // 行内元素中的块级元素单独占一行
+    fn layout_inline(&mut self, containing_block: Dimensions, viewport: &Viewport) {
+        let d = &mut self.dimensions;
+        d.content = Rect {
+            x: containing_block.content.x,
+            y: containing_block.content.y + containing_block.content.height,
+            width: containing_block.content.width.max(0),
+            height: 0,
+        };
+        let content = d.content;
+
+        let mut items = vec![];
+        inline::collect_items(self, &mut vec![], &mut items, &mut true);
+        let mut lines = vec![];
+        let mut y = content.y;
+        for run in items.split_inclusive(|item| matches!(item, InlineItem::Block { .. })) {
+            let (inline_items, block) = match run.split_last() {
+                Some((InlineItem::Block { boxes }, rest)) => (rest, Some(boxes)),
+                _ => (run, None),
+            };
+            let run_lines = inline::break_lines(inline_items, content.x, y, content.width);
+            y += run_lines.iter().map(|l| l.rect.height).sum::<i32>();
+            lines.extend(run_lines);
+
+            if let Some(boxes) = block {
+                let containing_block = Dimensions {
+                    content: Rect { y, ..content },
+                    ..Dimensions::default()
+                };
+                let block_box = self.descendant_mut(boxes);
+                block_box.layout(containing_block, viewport);
+                let rect = block_box.dimensions.margin_box();
+                lines.push(LineBox {
+                    rect: Rect {
+                        height: rect.height,
+                        ..containing_block.content
+                    },
+                    fragments: vec![Fragment {
+                        kind: FragmentKind::Block,
+                        rect,
+                        boxes: boxes.clone(),
+                    }],
+                });
+                y += rect.height;
+            }
+        }
+        self.dimensions.content.height = y - content.y;
+        self.lines = lines;
+    }
+
+    // 从self开始按children下标找到的各个盒子，不包括self
+    pub fn descendants(&self, indices: &[usize]) -> Vec<&LayoutBox<'a>> {
+        let mut boxes = vec![];
+        let mut current = self;
+        for &i in indices {
+            current = &current.children[i];
+            boxes.push(current);
+        }
+        boxes
+    }
+
+    fn descendant_mut(&mut self, indices: &[usize]) -> &mut LayoutBox<'a> {
+        indices.iter().fold(self, |b, &i| &mut b.children[i])
+    }
+}
+
+// (width, margin-left, margin-right)，参数为None时表示auto
+// 过度约束时调整margin-right
+fn resolve_width(
+    width: Option<i32>,
+    margin_left: Option<i32>,
+    margin_right: Option<i32>,
+    edges: i32,
+    containing_width: i32,
+) -> (i32, i32, i32) {
+    let total = width.unwrap_or(0) + margin_left.unwrap_or(0) + margin_right.unwrap_or(0) + edges;
+    // 宽度超出时auto的margin为0
+    let (margin_left, margin_right) = match width {
+        Some(_) if total > containing_width => (
+            Some(margin_left.unwrap_or(0)),
+            Some(margin_right.unwrap_or(0)),
+        ),
+        _ => (margin_left, margin_right),
+    };
+    let underflow = containing_width - total;
+    match (width, margin_left, margin_right) {
+        (Some(w), Some(l), Some(r)) => (w, l, r + underflow),
+        (Some(w), Some(l), None) => (w, l, underflow),
+        (Some(w), None, Some(r)) => (w, underflow, r),
+        // 左右都是auto时居中
+        (Some(w), None, None) => (w, underflow / 2, underflow - underflow / 2),
+        (None, l, r) => {
+            let (l, r) = (l.unwrap_or(0), r.unwrap_or(0));
+            if underflow >= 0 {
+                (underflow, l, r)
+            } else {
+                (0, l, r + underflow)
+            }
+        }
+    }
+}
+
+// 长度 -> 字符格，百分比相对于包含块的宽度，auto等返回None
+fn to_cells(
+    value: &CSSValue,
+    containing_width: i32,
+    axis: Axis,
+    viewport: &Viewport,
+) -> Option<i32> {
+    let px = to_px(value, containing_width as f64 * CELL_WIDTH_PX, viewport)?;
+    let cell = match axis {
+        Axis::Horizontal => CELL_WIDTH_PX,
+        Axis::Vertical => CELL_HEIGHT_PX,
+    };
+    Some((px / cell).round() as i32)
+}
+
+fn to_px(value: &CSSValue, percent_base: f64, viewport: &Viewport) -> Option<f64> {
+    match value {
+        CSSValue::Length((n, Unit::Percent)) => Some(n * percent_base / 100.0),
+        CSSValue::Length((n, unit)) => viewport.to_px(*n, *unit),
+        CSSValue::Number(0.0) => Some(0.0),
+        // 计算样式中剩下的calc(p% + npx)
+        CSSValue::Calc(e) => match e.as_ref() {
+            CalcExpression::Add(l, r) => match (l.as_ref(), r.as_ref()) {
+                (CalcExpression::Value(l), CalcExpression::Value(r)) => {
+                    Some(to_px(l, percent_base, viewport)? + to_px(r, percent_base, viewport)?)
+                }
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// 终端中边框只能画一个字符格宽，样式为none或hidden时为0
+fn border_width(props: &PropertyMap, side: &str) -> i32 {
+    match props.get(&format!("border-{}-style", side)) {
+        Some(CSSValue::Keyword(k)) if k != "none" && k != "hidden" => {}
+        _ => return 0,
+    }
+    match props.get(&format!("border-{}-width", side)) {
+        Some(CSSValue::Length((0.0, _))) | Some(CSSValue::Number(_)) => 0,
+        _ => 1,
+    }
+}
+
+pub fn to_layout_document<'a>(
+    document: StyledDocument<'a>,
+    viewport: &Viewport,
+) -> LayoutDocument<'a> {
+    let mut layout_box = to_layout_box(document.document_element);
+    // 初始包含块为视口，高度从0开始累加
+    let mut initial = Dimensions::default();
+    initial.content.width = viewport.columns as i32;
+    layout_box.layout(initial, viewport);
+    LayoutDocument {
+        top_box: layout_box,
+    }
+}
+
+// 有样式的Node -> box
+fn to_layout_box<'a>(snode: StyledNode<'a>) -> LayoutBox<'a> {
+    let box_type = match snode.display() {
+        Display::Block => BoxType::BlockBox,
+        Display::Inline => BoxType::InlineBox,
+        Display::None => BoxType::NoneBox,
+    };
+
+    let box_props = BoxProps {
+        node_type: snode.node_type,
+        path: snode.path,
+        properties: snode.properties,
+    };
+
+    let mut layout = LayoutBox::new(box_type, Some(box_props));
+
+    for child in snode.children {
+        match child.display() {
+            Display::Block => {
+                layout.children.push(to_layout_box(child));
+            }
+            Display::Inline => {
+                layout
+                    .inline_container()
+                    .children
+                    .push(to_layout_box(child));
+            }
+            Display::None => {}
+        }
+    }
+
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        css::SelectorContext,
+        dom::{
+            document::Document,
+            element::{AttrMap, Element},
+        },
+        style::to_styled_document,
+    };
+
+    // <html><body><div style=...></div><div></div></body></html>
+    fn layout_div(style: &str, columns: usize) -> Dimensions {
+        let mut attributes = AttrMap::new();
+        attributes.insert("style".to_string(), style.to_string());
+        let div = Element::new("div".to_string(), attributes, vec![]);
+        let sibling = Element::new("div".to_string(), AttrMap::new(), vec![]);
+        let body = Element::new("body".to_string(), AttrMap::new(), vec![div, sibling]);
+        let html = Element::new("html".to_string(), AttrMap::new(), vec![body]);
+        let document = Document::new("".to_string(), "".to_string(), html);
+        let viewport = Viewport {
+            columns,
+            ..Viewport::default()
+        };
+        let styled = to_styled_document(&document, &viewport, &SelectorContext::default());
+        let layout = to_layout_document(styled, &viewport);
+        let body = &layout.top_box.children[0];
+        // 兄弟元素排在下面
+        let div = body.children[0].dimensions;
+        assert_eq!(
+            body.children[1].dimensions.content.y,
+            div.margin_box().y + div.margin_box().height
+        );
+        div
+    }
+
+    #[test]
+    fn test_block_width() {
+        let d = layout_div("", 80);
+        assert_eq!(
+            d.content,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 80,
+                height: 0
+            }
+        );
+
+        // 1em = 2个字符格宽，1个字符格高
+        let d = layout_div(
+            "padding: 1em 8px; border: 1px solid; margin: 2em 10%; height: 3em",
+            80,
+        );
+        assert_eq!(
+            d.margin,
+            EdgeSizes {
+                left: 8,
+                right: 8,
+                top: 2,
+                bottom: 2
+            }
+        );
+        assert_eq!(
+            d.padding,
+            EdgeSizes {
+                left: 1,
+                right: 1,
+                top: 1,
+                bottom: 1
+            }
+        );
+        assert_eq!(
+            d.border,
+            EdgeSizes {
+                left: 1,
+                right: 1,
+                top: 1,
+                bottom: 1
+            }
+        );
+        assert_eq!(
+            d.content,
+            Rect {
+                x: 10,
+                y: 4,
+                width: 60,
+                height: 3
+            }
+        );
+        assert_eq!(d.margin_box().width, 80);
+    }
+
+    #[test]
+    fn test_auto_margins_and_max_width() {
+        // 320px = 40个字符格
+        let d = layout_div("width: 320px; margin: 0 auto", 100);
+        assert_eq!(
+            (d.margin.left, d.content.width, d.margin.right),
+            (30, 40, 30)
+        );
+
+        let d = layout_div("max-width: 50%; margin-left: auto", 100);
+        assert_eq!(
+            (d.margin.left, d.content.width, d.margin.right),
+            (50, 50, 0)
+        );
+
+        // 宽度超出时margin-right为负
+        let d = layout_div("width: 120ch; margin: auto", 100);
+        assert_eq!(
+            (d.margin.left, d.content.width, d.margin.right),
+            (0, 120, -20)
+        );
+
+        let d = layout_div("width: calc(50% + 2ch); margin-right: auto", 100);
+        assert_eq!(
+            (d.margin.left, d.content.width, d.margin.right),
+            (0, 52, 48)
+        );
+    }
+
+    #[test]
+    fn test_box_sizing() {
+        let d = layout_div(
+            "box-sizing: border-box; width: 20ch; padding: 0 2ch; border: thin dotted",
+            80,
+        );
+        assert_eq!(d.content.width, 14);
+        assert_eq!(d.border_box().width, 20);
+
+        let d = layout_div(
+            "box-sizing: border-box; max-width: 20ch; padding: 0 2ch; margin: auto",
+            80,
+        );
+        assert_eq!(d.border_box().width, 20);
+        assert_eq!(d.margin.left, 30);
+    }
+
+    #[test]
+    fn test_inline_layout() {
+        // <div style="padding-left: 2ch">Hello <span>wide world</span><div>x</div> again</div>
+        let text = |s: &str| crate::core::dom::text::Text::new(s.to_string());
+        let block = Element::new("div".to_string(), AttrMap::new(), vec![text("x")]);
+        let span = Element::new(
+            "span".to_string(),
+            AttrMap::new(),
+            vec![text("wide world"), block],
+        );
+        let mut attributes = AttrMap::new();
+        attributes.insert("style".to_string(), "padding-left: 2ch".to_string());
+        let div = Element::new(
+            "div".to_string(),
+            attributes,
+            vec![text("Hello "), span, text(" again")],
+        );
+        let body = Element::new("body".to_string(), AttrMap::new(), vec![div]);
+        let html = Element::new("html".to_string(), AttrMap::new(), vec![body]);
+        let document = Document::new("".to_string(), "".to_string(), html);
+        let viewport = Viewport {
+            columns: 12,
+            ..Viewport::default()
+        };
+        let styled = to_styled_document(&document, &viewport, &SelectorContext::default());
+        let layout = to_layout_document(styled, &viewport);
+
+        let div = &layout.top_box.children[0].children[0];
+        let anonymous = &div.children[0];
+        assert_eq!(
+            anonymous.dimensions.content,
+            Rect {
+                x: 2,
+                y: 0,
+                width: 10,
+                height: 4
+            }
+        );
+        let lines = &anonymous.lines;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0].fragments[1],
+            Fragment {
+                kind: FragmentKind::Text("wide".to_string()),
+                rect: Rect {
+                    x: 8,
+                    y: 0,
+                    width: 4,
+                    height: 1
+                },
+                boxes: vec![1, 0],
+            }
+        );
+        assert_eq!(
+            lines[1].fragments[0].kind,
+            FragmentKind::Text("world".to_string())
+        );
+        // 块级元素单独占一行
+        assert_eq!(lines[2].fragments[0].kind, FragmentKind::Block);
+        let block = anonymous.descendants(&lines[2].fragments[0].boxes)[1];
+        assert_eq!(block.dimensions.content.y, 2);
+        assert_eq!(block.children[0].lines.len(), 1);
+        assert_eq!(
+            lines[3].fragments[0].kind,
+            FragmentKind::Text("again".to_string())
+        );
+        assert_eq!(div.dimensions.content.height, 4);
+    }
+}
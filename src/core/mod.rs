@@ -3,6 +3,7 @@ pub mod dom;
 pub mod fetch;
 pub mod html;
 pub mod layout;
+pub mod source;
 pub mod style;
 pub mod url;
 pub mod window;
@@ -0,0 +1,112 @@
+use combine::stream::position::Positioner;
+use std::fmt;
+
+// 源码中的位置，line和column从1开始，offset为UTF-8字节偏移
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl Default for SourcePosition {
+    fn default() -> Self {
+        SourcePosition {
+            line: 1,
+            column: 1,
+            offset: 0,
+        }
+    }
+}
+
+impl SourcePosition {
+    // 读入c之后的位置
+    pub fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// css解析时由combine::stream::position::Stream更新位置
+impl Positioner<char> for SourcePosition {
+    type Position = SourcePosition;
+    type Checkpoint = SourcePosition;
+
+    fn position(&self) -> SourcePosition {
+        *self
+    }
+
+    fn update(&mut self, token: &char) {
+        self.advance(*token);
+    }
+
+    fn checkpoint(&self) -> SourcePosition {
+        *self
+    }
+
+    fn reset(&mut self, checkpoint: SourcePosition) {
+        *self = checkpoint;
+    }
+}
+
+// [start, end)
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub struct SourceSpan {
+    pub start: SourcePosition,
+    pub end: SourcePosition,
+}
+
+impl SourceSpan {
+    pub fn new(start: SourcePosition, end: SourcePosition) -> Self {
+        SourceSpan { start, end }
+    }
+}
+
+// 带位置的解析错误
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic<E> {
+    pub error: E,
+    pub span: SourceSpan,
+}
+
+impl<E> Diagnostic<E> {
+    pub fn new(error: E, span: SourceSpan) -> Self {
+        Diagnostic { error, span }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Diagnostic<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span.start, self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let mut position = SourcePosition::default();
+        "a\u{3042}\nb".chars().for_each(|c| position.advance(c));
+        assert_eq!(
+            position,
+            SourcePosition {
+                line: 2,
+                column: 2,
+                offset: 6
+            }
+        );
+    }
+}
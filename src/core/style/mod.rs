@@ -0,0 +1,1441 @@
+use super::{
+    css::{
+        self, CSSParseError, CSSValue, CalcExpression, Declaration, MediaFeature, MediaQuery,
+        MediaQueryList, PseudoElement, SelectorContext, Specificity, Stylesheet, Unit,
+    },
+    dom::{
+        document::{Document, StylesheetSource},
+        node::{Node, NodeRef, NodeType},
+        text::Text,
+    },
+    fetch::{fetch, Request},
+    source::Diagnostic,
+    url::{ParseError, Url},
+};
+use log::warn;
+use once_cell::sync::Lazy;
+use std::{borrow::Cow, collections::HashMap};
+
+mod shorthand;
+
+pub type PropertyMap = HashMap<String, CSSValue>;
+
+#[derive(Debug, PartialEq)]
+pub enum Display {
+    Inline,
+    Block,
+    None,
+}
+
+#[derive(Debug)]
+pub struct StyledDocument<'a> {
+    pub document_element: StyledNode<'a>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StyledNode<'a> {
+    // ::before和::after生成的文本不在文档中
+    pub node_type: Cow<'a, NodeType>,
+    // NodeRef::path()，伪元素为所属元素的路径
+    pub path: Vec<usize>,
+    pub properties: PropertyMap,
+    pub children: Vec<StyledNode<'a>>,
+}
+
+impl<'a> StyledNode<'a> {
+    pub fn display(&self) -> Display {
+        match self.properties.get("display") {
+            Some(CSSValue::Keyword(s)) => match s.as_str() {
+                "block" => Display::Block,
+                "none" => Display::None,
+                _ => Display::Inline,
+            },
+            _ => Display::Inline,
+        }
+    }
+}
+
+// 支持的属性: (名称, 是否继承, 初始值)
+const PROPERTIES: &[(&str, bool, &str)] = &[
+    ("display", false, "inline"),
+    ("color", true, "canvastext"),
+    ("background-color", false, "transparent"),
+    ("font-weight", true, "normal"),
+    ("font-style", true, "normal"),
+    ("text-decoration", false, "none"),
+    ("text-align", true, "start"),
+    ("white-space", true, "normal"),
+    ("visibility", true, "visible"),
+    ("width", false, "auto"),
+    ("height", false, "auto"),
+    ("max-width", false, "none"),
+    ("box-sizing", false, "content-box"),
+    ("content", false, "normal"),
+    ("margin-top", false, "0"),
+    ("margin-right", false, "0"),
+    ("margin-bottom", false, "0"),
+    ("margin-left", false, "0"),
+    ("padding-top", false, "0"),
+    ("padding-right", false, "0"),
+    ("padding-bottom", false, "0"),
+    ("padding-left", false, "0"),
+    ("border-top-width", false, "medium"),
+    ("border-right-width", false, "medium"),
+    ("border-bottom-width", false, "medium"),
+    ("border-left-width", false, "medium"),
+    ("border-top-style", false, "none"),
+    ("border-right-style", false, "none"),
+    ("border-bottom-style", false, "none"),
+    ("border-left-style", false, "none"),
+    ("border-top-color", false, "currentcolor"),
+    ("border-right-color", false, "currentcolor"),
+    ("border-bottom-color", false, "currentcolor"),
+    ("border-left-color", false, "currentcolor"),
+    ("font-variant", true, "normal"),
+    ("font-size", true, "medium"),
+    ("line-height", true, "normal"),
+    ("font-family", true, "monospace"),
+    ("background-image", false, "none"),
+    ("background-repeat", false, "repeat"),
+    ("background-attachment", false, "scroll"),
+    ("background-position", false, "0% 0%"),
+    ("background-size", false, "auto"),
+];
+
+// 解析后的初始值
+static INITIAL_VALUES: Lazy<HashMap<&str, CSSValue>> = Lazy::new(|| {
+    PROPERTIES
+        .iter()
+        .filter_map(|(name, _, initial)| Some((*name, css::parse_value(initial).ok()?)))
+        .collect()
+});
+
+fn initial_value(name: &str) -> Option<CSSValue> {
+    INITIAL_VALUES.get(name).cloned()
+}
+
+const DEFAULT_STYLESHEET: &str = r#"
+head, script, style {
+    display: none;
+}
+html, body, p, div {
+    display: block;
+}
+"#;
+
+// 样式表的来源
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
+impl Origin {
+    // 层叠时的优先级，!important的声明顺序相反
+    fn precedence(self, important: bool) -> u8 {
+        match (self, important) {
+            (Origin::UserAgent, false) => 0,
+            (Origin::User, false) => 1,
+            (Origin::Author, false) => 2,
+            (Origin::Author, true) => 3,
+            (Origin::User, true) => 4,
+            (Origin::UserAgent, true) => 5,
+        }
+    }
+}
+
+// 终端的一个字符格相当于多少css px
+pub const CELL_WIDTH_PX: f64 = 8.0;
+pub const CELL_HEIGHT_PX: f64 = 16.0;
+// font-size: medium，也是1rem
+const FONT_SIZE_PX: f64 = 16.0;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+// @media判断用的显示区域，大小以字符格为单位
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Viewport {
+    pub columns: usize,
+    pub rows: usize,
+    pub color_scheme: ColorScheme,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            columns: 80,
+            rows: 24,
+            color_scheme: ColorScheme::Light,
+        }
+    }
+}
+
+impl Viewport {
+    pub fn width_px(&self) -> f64 {
+        self.columns as f64 * CELL_WIDTH_PX
+    }
+
+    pub fn height_px(&self) -> f64 {
+        self.rows as f64 * CELL_HEIGHT_PX
+    }
+
+    // 百分比取决于包含块，这里无法换算
+    // 计算样式中的em已经按font-size换算，这里的em只用于@media，相对于初始的font-size
+    pub fn to_px(&self, n: f64, unit: Unit) -> Option<f64> {
+        match unit {
+            Unit::Px => Some(n),
+            Unit::Em | Unit::Rem => Some(n * FONT_SIZE_PX),
+            Unit::Ch => Some(n * CELL_WIDTH_PX),
+            Unit::Vw => Some(n * self.width_px() / 100.0),
+            Unit::Vh => Some(n * self.height_px() / 100.0),
+            Unit::Percent => None,
+        }
+    }
+
+    // 逗号分隔的query中任意一个满足即可，空的列表总是满足
+    pub fn matches_media(&self, queries: &MediaQueryList) -> bool {
+        queries.is_empty() || queries.iter().any(|q| self.matches_query(q))
+    }
+
+    fn matches_query(&self, query: &MediaQuery) -> bool {
+        let media_type = matches!(query.media_type.as_deref(), None | Some("all" | "screen"));
+        let matched = media_type && query.features.iter().all(|f| self.matches_feature(f));
+        matched != query.negated
+    }
+
+    // 不支持的feature不满足
+    fn matches_feature(&self, feature: &MediaFeature) -> bool {
+        let length = |value: &Option<CSSValue>| match value {
+            Some(CSSValue::Length((n, unit))) => self.to_px(*n, *unit),
+            Some(CSSValue::Number(n)) if *n == 0.0 => Some(0.0),
+            _ => None,
+        };
+        let keyword = |value: &Option<CSSValue>| match value {
+            Some(CSSValue::Keyword(k)) => Some(k.clone()),
+            _ => None,
+        };
+        let (width, height) = (self.width_px(), self.height_px());
+        let value = &feature.value;
+        match feature.name.as_str() {
+            "width" => length(value).is_some_and(|v| width == v),
+            "min-width" => length(value).is_some_and(|v| width >= v),
+            "max-width" => length(value).is_some_and(|v| width <= v),
+            "height" => length(value).is_some_and(|v| height == v),
+            "min-height" => length(value).is_some_and(|v| height >= v),
+            "max-height" => length(value).is_some_and(|v| height <= v),
+            "orientation" => match keyword(value).as_deref() {
+                Some("portrait") => height >= width,
+                Some("landscape") => width > height,
+                _ => false,
+            },
+            "prefers-color-scheme" => match keyword(value).as_deref() {
+                Some("light") => self.color_scheme == ColorScheme::Light,
+                Some("dark") => self.color_scheme == ColorScheme::Dark,
+                _ => false,
+            },
+            // 终端可以显示颜色
+            "color" => value.is_none(),
+            _ => false,
+        }
+    }
+}
+
+pub fn to_styled_document<'a>(
+    document: &'a Document,
+    viewport: &Viewport,
+    context: &SelectorContext,
+) -> StyledDocument<'a> {
+    to_styled_document_with_user_stylesheet(document, viewport, context, "")
+}
+
+pub fn to_styled_document_with_user_stylesheet<'a>(
+    document: &'a Document,
+    viewport: &Viewport,
+    context: &SelectorContext,
+    user_stylesheet: &str,
+) -> StyledDocument<'a> {
+    let mut stylesheets = vec![
+        (Origin::UserAgent, parse_stylesheet(DEFAULT_STYLESHEET)),
+        (Origin::User, parse_stylesheet(user_stylesheet)),
+    ];
+    stylesheets.extend(
+        author_stylesheets(document)
+            .into_iter()
+            .map(|s| (Origin::Author, s)),
+    );
+    for (_, stylesheet) in &mut stylesheets {
+        filter_media(stylesheet, viewport);
+    }
+    let document_element = to_styled_node(
+        &document.document_element,
+        &stylesheets,
+        context,
+        viewport,
+        &mut vec![],
+        None,
+    );
+
+    StyledDocument {
+        document_element: document_element,
+    }
+}
+
+// 不满足@media条件的规则不参与层叠
+fn filter_media(stylesheet: &mut Stylesheet, viewport: &Viewport) {
+    stylesheet
+        .rules
+        .retain(|r| r.media.iter().all(|m| viewport.matches_media(m)));
+}
+
+fn parse_stylesheet(raw: &str) -> Stylesheet {
+    css::parse(raw.to_string()).unwrap_or_else(|e| {
+        warn!("css parse error; {}", e);
+        Stylesheet::new(vec![])
+    })
+}
+
+// 防止@import循环
+const MAX_IMPORT_DEPTH: usize = 8;
+
+// 读取<link>和@import引用的外部样式表，已经读取过的（包括失败的）不再读取
+// 在解析到<link>之后和解析结束时调用，计算样式时只使用读取的结果
+pub fn load_stylesheets(document: &mut Document) {
+    let base = Url::parse(&document.url).ok();
+    let mut loaded = std::mem::take(&mut document.external_stylesheets);
+    for source in document.get_stylesheet_sources() {
+        match source {
+            StylesheetSource::Inline(raw) => {
+                load_imports(&parse_stylesheet(&raw), base.as_ref(), 0, &mut loaded)
+            }
+            StylesheetSource::External(href) => load_external(&href, base.as_ref(), 0, &mut loaded),
+        }
+    }
+    document.external_stylesheets = loaded;
+}
+
+fn load_imports(
+    stylesheet: &Stylesheet,
+    base: Option<&Url>,
+    depth: usize,
+    loaded: &mut HashMap<String, Option<String>>,
+) {
+    if depth < MAX_IMPORT_DEPTH {
+        for href in &stylesheet.imports {
+            load_external(href, base, depth + 1, loaded);
+        }
+    }
+}
+
+fn load_external(
+    href: &str,
+    base: Option<&Url>,
+    depth: usize,
+    loaded: &mut HashMap<String, Option<String>>,
+) {
+    let url = match stylesheet_url(href, base) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("invalid stylesheet url {}; {}", href, e);
+            return;
+        }
+    };
+    if loaded.contains_key(url.as_str()) {
+        return;
+    }
+    let raw = fetch_stylesheet(&url);
+    loaded.insert(url.to_string(), raw.clone());
+    if let Some(raw) = raw {
+        load_imports(&parse_stylesheet(&raw), Some(&url), depth, loaded);
+    }
+}
+
+fn fetch_stylesheet(url: &Url) -> Option<String> {
+    match fetch(Request::new(url.to_string())) {
+        Ok(response) => Some(String::from_utf8_lossy(&response.data).into_owned()),
+        Err(e) => {
+            warn!("failed to load stylesheet {}; {}", url, e);
+            None
+        }
+    }
+}
+
+// href相对于base解析
+fn stylesheet_url(href: &str, base: Option<&Url>) -> Result<Url, ParseError> {
+    base.map_or_else(|| Url::parse(href), |b| b.join(href))
+}
+
+// <style>、<link>及其@import展开后的样式表，按层叠顺序
+// 外部样式表只使用load_stylesheets()读取的结果
+fn author_stylesheets(document: &Document) -> Vec<Stylesheet> {
+    let base = Url::parse(&document.url).ok();
+    let loaded = &document.external_stylesheets;
+    let mut stylesheets = vec![];
+    for source in document.get_stylesheet_sources() {
+        match source {
+            StylesheetSource::Inline(raw) => push_with_imports(
+                parse_stylesheet(&raw),
+                base.as_ref(),
+                0,
+                loaded,
+                &mut stylesheets,
+            ),
+            StylesheetSource::External(href) => {
+                push_external(&href, base.as_ref(), 0, loaded, &mut stylesheets)
+            }
+        }
+    }
+    stylesheets
+}
+
+// 作者样式表中被跳过的规则和声明
+pub fn author_stylesheet_errors(document: &Document) -> Vec<Diagnostic<CSSParseError>> {
+    author_stylesheets(document)
+        .into_iter()
+        .flat_map(|stylesheet| stylesheet.errors)
+        .collect()
+}
+
+// @import的样式表排在导入它的样式表之前
+fn push_with_imports(
+    stylesheet: Stylesheet,
+    base: Option<&Url>,
+    depth: usize,
+    loaded: &HashMap<String, Option<String>>,
+    stylesheets: &mut Vec<Stylesheet>,
+) {
+    if depth < MAX_IMPORT_DEPTH {
+        for href in &stylesheet.imports {
+            push_external(href, base, depth + 1, loaded, stylesheets);
+        }
+    }
+    stylesheets.push(stylesheet);
+}
+
+// 还没有读取或者读取失败的样式表被跳过
+fn push_external(
+    href: &str,
+    base: Option<&Url>,
+    depth: usize,
+    loaded: &HashMap<String, Option<String>>,
+    stylesheets: &mut Vec<Stylesheet>,
+) {
+    let url = match stylesheet_url(href, base) {
+        Ok(url) => url,
+        Err(_) => return,
+    };
+    if let Some(Some(raw)) = loaded.get(url.as_str()) {
+        push_with_imports(
+            parse_stylesheet(raw),
+            Some(&url),
+            depth,
+            loaded,
+            stylesheets,
+        );
+    }
+}
+
+// ancestors: 从根节点到父节点，combinator的匹配需要
+fn to_styled_node<'a>(
+    node: &'a Box<Node>,
+    stylesheets: &[(Origin, Stylesheet)],
+    context: &SelectorContext,
+    viewport: &Viewport,
+    ancestors: &mut Vec<&'a Node>,
+    parent: Option<&PropertyMap>,
+) -> StyledNode<'a> {
+    let node_ref = NodeRef::new(node, ancestors);
+    let path = node_ref.path();
+    // 文本节点没有自己的声明，只继承父元素
+    let specified = match node.node_type {
+        NodeType::Element(_) => cascade(node_ref, None, stylesheets, context),
+        _ => PropertyMap::new(),
+    };
+    let props = compute(specified, parent, viewport);
+
+    ancestors.push(node);
+    let mut children = to_styled_nodes(
+        &node.children,
+        stylesheets,
+        context,
+        viewport,
+        ancestors,
+        &props,
+    );
+    ancestors.pop();
+
+    if let NodeType::Element(_) = node.node_type {
+        let node_ref = NodeRef::new(node, ancestors);
+        let pseudo = |pseudo_element| {
+            to_styled_pseudo_element(
+                node_ref,
+                pseudo_element,
+                stylesheets,
+                context,
+                viewport,
+                &props,
+            )
+        };
+        if let Some(before) = pseudo(PseudoElement::Before) {
+            children.insert(0, before);
+        }
+        if let Some(after) = pseudo(PseudoElement::After) {
+            children.push(after);
+        }
+    }
+
+    StyledNode {
+        node_type: Cow::Borrowed(&node.node_type),
+        path,
+        properties: props,
+        children: children,
+    }
+}
+
+// ::before和::after，content为none或normal时不生成
+fn to_styled_pseudo_element<'a>(
+    node: NodeRef,
+    pseudo_element: PseudoElement,
+    stylesheets: &[(Origin, Stylesheet)],
+    context: &SelectorContext,
+    viewport: &Viewport,
+    parent: &PropertyMap,
+) -> Option<StyledNode<'a>> {
+    let specified = cascade(node, Some(pseudo_element), stylesheets, context);
+    let props = compute(specified, Some(parent), viewport);
+    // content: "a" 或 content: "a" "b"
+    let content = match props.get("content")? {
+        CSSValue::String(s) => s.clone(),
+        CSSValue::List(values) => values
+            .iter()
+            .map(|v| match v {
+                CSSValue::String(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?
+            .concat(),
+        _ => return None,
+    };
+    Some(StyledNode {
+        node_type: Cow::Owned(NodeType::Text(Text { data: content })),
+        path: node.path(),
+        properties: props,
+        children: vec![],
+    })
+}
+
+// 注释和doctype不参与样式计算和布局
+fn to_styled_nodes<'a>(
+    nodes: &'a Vec<Box<Node>>,
+    stylesheets: &[(Origin, Stylesheet)],
+    context: &SelectorContext,
+    viewport: &Viewport,
+    ancestors: &mut Vec<&'a Node>,
+    parent: &PropertyMap,
+) -> Vec<StyledNode<'a>> {
+    nodes
+        .iter()
+        .filter(|x| matches!(x.node_type, NodeType::Element(_) | NodeType::Text(_)))
+        .map(|x| to_styled_node(x, stylesheets, context, viewport, ancestors, Some(parent)))
+        .collect()
+}
+
+// style属性中的声明
+fn inline_style(node: &Node) -> Vec<Declaration> {
+    let style = match node.node_type {
+        NodeType::Element(ref e) => match e.attributes.get("style") {
+            Some(style) => style,
+            None => return vec![],
+        },
+        _ => return vec![],
+    };
+    css::parse_declarations(style).unwrap_or_else(|e| {
+        warn!("inline style parse error; {}", e);
+        vec![]
+    })
+}
+
+// 层叠后的值 -> 计算值
+// 没有声明的属性，继承属性取父元素的值，其他取初始值
+// var()替换后无效的声明当作unset
+fn compute(
+    specified: PropertyMap,
+    parent: Option<&PropertyMap>,
+    viewport: &Viewport,
+) -> PropertyMap {
+    let inherit = |name: &str| {
+        parent
+            .and_then(|p| p.get(name).cloned())
+            .or_else(|| initial_value(name))
+    };
+    let is_inherited = |name: &str| {
+        is_custom_property(name) || PROPERTIES.iter().any(|(n, i, _)| *n == name && *i)
+    };
+    let mut props: PropertyMap = PROPERTIES
+        .iter()
+        .filter_map(|(name, inherited, _)| {
+            let value = if *inherited {
+                inherit(name)
+            } else {
+                initial_value(name)
+            };
+            value.map(|v| (name.to_string(), v))
+        })
+        .collect();
+    // 自定义属性都是继承属性，父元素的值中已经没有var()
+    if let Some(parent) = parent {
+        props.extend(
+            parent
+                .iter()
+                .filter(|(name, _)| is_custom_property(name))
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+    }
+
+    let (customs, specified): (PropertyMap, PropertyMap) = specified
+        .into_iter()
+        .partition(|(name, _)| is_custom_property(name));
+    let mut unresolved = props.clone();
+    for (name, value) in customs {
+        match value {
+            CSSValue::Keyword(ref k) if k == "inherit" => match inherit(&name) {
+                Some(v) => unresolved.insert(name, v),
+                None => unresolved.remove(&name),
+            },
+            CSSValue::Keyword(ref k) if k == "initial" => unresolved.remove(&name),
+            v => unresolved.insert(name, v),
+        };
+    }
+    // 循环引用的自定义属性无效
+    let cyclic: Vec<String> = unresolved
+        .keys()
+        .filter(|name| is_cyclic(name, &unresolved))
+        .cloned()
+        .collect();
+    for name in cyclic {
+        unresolved.remove(&name);
+    }
+    props.retain(|name, _| !is_custom_property(name));
+    for (name, value) in &unresolved {
+        if let Some(v) = substitute(value, &unresolved) {
+            props.insert(name.clone(), v);
+        }
+    }
+
+    // font-size先计算，其他属性的em相对于它，font-size自己的em相对于父元素的
+    let parent_font_size = font_size_px(parent.and_then(|p| p.get("font-size")));
+    let mut specified: Vec<_> = specified.into_iter().collect();
+    specified.sort_by_key(|(name, _)| name != "font-size");
+    for (name, value) in specified {
+        let em = match name.as_str() {
+            "font-size" => parent_font_size,
+            _ => font_size_px(props.get("font-size")),
+        };
+        let value = match value {
+            // 替换后展开简写属性，取其中name的值
+            CSSValue::PendingSubstitution(shorthand, value) => substitute(&value, &props)
+                .and_then(|v| shorthand::expand(&shorthand, &v))
+                .and_then(|longhands| longhands.into_iter().find(|(n, _)| *n == name))
+                .map(|(_, v)| v),
+            v => substitute(&v, &props),
+        };
+        let value = match value.and_then(|v| evaluate(v, viewport, em)) {
+            Some(CSSValue::Keyword(ref k)) if k == "inherit" => inherit(&name),
+            Some(CSSValue::Keyword(ref k)) if k == "initial" => initial_value(&name),
+            Some(v) => Some(v),
+            None if is_inherited(&name) => inherit(&name),
+            None => initial_value(&name),
+        };
+        let value = match value {
+            Some(v) if name == "font-size" => {
+                Some(compute_font_size(&v, parent_font_size, viewport))
+            }
+            v => v,
+        };
+        match value {
+            Some(v) => props.insert(name, v),
+            None => props.remove(&name),
+        };
+    }
+    props
+}
+
+// 计算后的font-size都是px，没有声明font-size的根元素为medium
+fn font_size_px(value: Option<&CSSValue>) -> f64 {
+    match value {
+        Some(CSSValue::Length((n, Unit::Px))) => *n,
+        _ => FONT_SIZE_PX,
+    }
+}
+
+// 关键字和百分比换算为px，无法换算时和父元素相同
+fn compute_font_size(value: &CSSValue, parent: f64, viewport: &Viewport) -> CSSValue {
+    let px = match value {
+        CSSValue::Length((n, Unit::Percent)) => Some(n * parent / 100.0),
+        CSSValue::Length((n, unit)) => viewport.to_px(*n, *unit),
+        CSSValue::Number(n) if *n == 0.0 => Some(0.0),
+        CSSValue::Keyword(k) => match k.as_str() {
+            "xx-small" => Some(9.0),
+            "x-small" => Some(10.0),
+            "small" => Some(13.0),
+            "medium" => Some(FONT_SIZE_PX),
+            "large" => Some(18.0),
+            "x-large" => Some(24.0),
+            "xx-large" => Some(32.0),
+            "xxx-large" => Some(48.0),
+            "larger" => Some(parent * 1.2),
+            "smaller" => Some(parent / 1.2),
+            _ => None,
+        },
+        _ => None,
+    };
+    CSSValue::Length((px.unwrap_or(parent), Unit::Px))
+}
+
+fn is_custom_property(name: &str) -> bool {
+    name.starts_with("--")
+}
+
+// value中var()引用的自定义属性，包括fallback中的
+fn var_references(value: &CSSValue, references: &mut Vec<String>) {
+    match value {
+        CSSValue::Var(name, fallback) => {
+            references.push(name.clone());
+            if let Some(fallback) = fallback {
+                var_references(fallback, references);
+            }
+        }
+        CSSValue::List(values) | CSSValue::CommaList(values) => {
+            for v in values {
+                var_references(v, references);
+            }
+        }
+        CSSValue::Calc(e) => calc_var_references(e, references),
+        _ => {}
+    }
+}
+
+fn calc_var_references(e: &CalcExpression, references: &mut Vec<String>) {
+    match e {
+        CalcExpression::Value(v) => var_references(v, references),
+        CalcExpression::Add(l, r)
+        | CalcExpression::Sub(l, r)
+        | CalcExpression::Mul(l, r)
+        | CalcExpression::Div(l, r) => {
+            calc_var_references(l, references);
+            calc_var_references(r, references);
+        }
+    }
+}
+
+// 从name出发能否回到name
+fn is_cyclic(name: &str, customs: &PropertyMap) -> bool {
+    let mut stack = vec![];
+    var_references(&customs[name], &mut stack);
+    let mut visited = vec![];
+    while let Some(n) = stack.pop() {
+        if n == name {
+            return true;
+        }
+        if visited.contains(&n) {
+            continue;
+        }
+        if let Some(v) = customs.get(&n) {
+            var_references(v, &mut stack);
+        }
+        visited.push(n);
+    }
+    false
+}
+
+// 把var()替换为自定义属性的值，没有定义且没有fallback时返回None
+// customs中不能有循环引用
+fn substitute(value: &CSSValue, customs: &PropertyMap) -> Option<CSSValue> {
+    match value {
+        CSSValue::Var(name, fallback) => match (customs.get(name), fallback) {
+            (Some(v), _) => substitute(v, customs),
+            (None, Some(fallback)) => substitute(fallback, customs),
+            (None, None) => None,
+        },
+        // 替换结果为空白分隔的多个值时展开
+        CSSValue::List(values) => {
+            let mut list = vec![];
+            for v in values {
+                match substitute(v, customs)? {
+                    CSSValue::List(values) => list.extend(values),
+                    v => list.push(v),
+                }
+            }
+            Some(CSSValue::List(list))
+        }
+        CSSValue::CommaList(values) => values
+            .iter()
+            .map(|v| substitute(v, customs))
+            .collect::<Option<_>>()
+            .map(CSSValue::CommaList),
+        CSSValue::Calc(e) => substitute_calc(e, customs).map(|e| CSSValue::Calc(Box::new(e))),
+        v => Some(v.clone()),
+    }
+}
+
+fn substitute_calc(e: &CalcExpression, customs: &PropertyMap) -> Option<CalcExpression> {
+    let binary = |l, r| {
+        Some((
+            Box::new(substitute_calc(l, customs)?),
+            Box::new(substitute_calc(r, customs)?),
+        ))
+    };
+    Some(match e {
+        CalcExpression::Value(v) => CalcExpression::Value(substitute(v, customs)?),
+        CalcExpression::Add(l, r) => {
+            let (l, r) = binary(l, r)?;
+            CalcExpression::Add(l, r)
+        }
+        CalcExpression::Sub(l, r) => {
+            let (l, r) = binary(l, r)?;
+            CalcExpression::Sub(l, r)
+        }
+        CalcExpression::Mul(l, r) => {
+            let (l, r) = binary(l, r)?;
+            CalcExpression::Mul(l, r)
+        }
+        CalcExpression::Div(l, r) => {
+            let (l, r) = binary(l, r)?;
+            CalcExpression::Div(l, r)
+        }
+    })
+}
+
+// calc()的中间结果，长度为px + %
+#[derive(Debug, Clone, Copy)]
+enum CalcValue {
+    Number(f64),
+    Length(f64, f64),
+}
+
+// 对calc()求值，em按font-size换算为px，无效时返回None
+// 百分比需要包含块，和px混合时保留为calc(p% + npx)
+fn evaluate(value: CSSValue, viewport: &Viewport, em: f64) -> Option<CSSValue> {
+    match value {
+        CSSValue::Length((n, Unit::Em)) => Some(CSSValue::Length((n * em, Unit::Px))),
+        CSSValue::Calc(e) => Some(match evaluate_calc(&e, viewport, em)? {
+            CalcValue::Number(n) => CSSValue::Number(n),
+            CalcValue::Length(px, 0.0) => CSSValue::Length((px, Unit::Px)),
+            CalcValue::Length(0.0, percent) => CSSValue::Length((percent, Unit::Percent)),
+            CalcValue::Length(px, percent) => CSSValue::Calc(Box::new(CalcExpression::Add(
+                Box::new(CalcExpression::Value(CSSValue::Length((
+                    percent,
+                    Unit::Percent,
+                )))),
+                Box::new(CalcExpression::Value(CSSValue::Length((px, Unit::Px)))),
+            ))),
+        }),
+        CSSValue::List(values) => values
+            .into_iter()
+            .map(|v| evaluate(v, viewport, em))
+            .collect::<Option<_>>()
+            .map(CSSValue::List),
+        CSSValue::CommaList(values) => values
+            .into_iter()
+            .map(|v| evaluate(v, viewport, em))
+            .collect::<Option<_>>()
+            .map(CSSValue::CommaList),
+        v => Some(v),
+    }
+}
+
+fn evaluate_calc(e: &CalcExpression, viewport: &Viewport, em: f64) -> Option<CalcValue> {
+    use CalcValue::{Length, Number};
+    let binary = |l, r| {
+        Some((
+            evaluate_calc(l, viewport, em)?,
+            evaluate_calc(r, viewport, em)?,
+        ))
+    };
+    match e {
+        CalcExpression::Value(CSSValue::Number(n)) => Some(Number(*n)),
+        CalcExpression::Value(CSSValue::Length((n, Unit::Percent))) => Some(Length(0.0, *n)),
+        CalcExpression::Value(CSSValue::Length((n, Unit::Em))) => Some(Length(n * em, 0.0)),
+        CalcExpression::Value(CSSValue::Length((n, unit))) => {
+            viewport.to_px(*n, *unit).map(|px| Length(px, 0.0))
+        }
+        CalcExpression::Value(_) => None,
+        // 只能同类相加减
+        CalcExpression::Add(l, r) => match binary(l, r)? {
+            (Number(a), Number(b)) => Some(Number(a + b)),
+            (Length(a, p), Length(b, q)) => Some(Length(a + b, p + q)),
+            _ => None,
+        },
+        CalcExpression::Sub(l, r) => match binary(l, r)? {
+            (Number(a), Number(b)) => Some(Number(a - b)),
+            (Length(a, p), Length(b, q)) => Some(Length(a - b, p - q)),
+            _ => None,
+        },
+        // 至少一边是数字
+        CalcExpression::Mul(l, r) => match binary(l, r)? {
+            (Number(a), Number(b)) => Some(Number(a * b)),
+            (Number(n), Length(px, p)) | (Length(px, p), Number(n)) => Some(Length(px * n, p * n)),
+            _ => None,
+        },
+        // 除数必须是非0的数字
+        CalcExpression::Div(l, r) => match binary(l, r)? {
+            (_, Number(0.0)) => None,
+            (Number(a), Number(b)) => Some(Number(a / b)),
+            (Length(px, p), Number(n)) => Some(Length(px / n, p / n)),
+            _ => None,
+        },
+    }
+}
+
+// style属性比任何选择器都优先
+const INLINE_SPECIFICITY: Specificity = (usize::MAX, 0, 0);
+
+// 按(来源和!important, specificity, 出现顺序)从低到高依次覆盖
+// pseudo_element为Some时计算::before等的样式，不包括style属性
+fn cascade(
+    node: NodeRef,
+    pseudo_element: Option<PseudoElement>,
+    stylesheets: &[(Origin, Stylesheet)],
+    context: &SelectorContext,
+) -> PropertyMap {
+    let mut declarations = vec![];
+    for (origin, stylesheet) in stylesheets {
+        for rule in &stylesheet.rules {
+            if let Some(specificity) = rule.specificity(node, pseudo_element, context) {
+                declarations.extend(
+                    rule.declarations
+                        .iter()
+                        .map(|d| ((origin.precedence(d.important), specificity), d)),
+                );
+            }
+        }
+    }
+    let inline_declarations = match pseudo_element {
+        Some(_) => vec![],
+        None => inline_style(node.node),
+    };
+    declarations.extend(inline_declarations.iter().map(|d| {
+        (
+            (Origin::Author.precedence(d.important), INLINE_SPECIFICITY),
+            d,
+        )
+    }));
+    // 稳定排序，相同时保持出现顺序
+    declarations.sort_by_key(|(key, _)| *key);
+    // 简写属性展开后覆盖，之后只有普通属性，无效的简写属性忽略
+    declarations
+        .into_iter()
+        .filter_map(|(_, d)| shorthand::expand(&d.name, &d.value))
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::dom::element::{AttrMap, Element};
+
+    fn cascade_str(node: &Node, stylesheets: &[(Origin, &str)]) -> PropertyMap {
+        let stylesheets: Vec<_> = stylesheets
+            .iter()
+            .map(|(origin, raw)| (*origin, css::parse(raw.to_string()).unwrap()))
+            .collect();
+        cascade(
+            NodeRef::new(node, &[]),
+            None,
+            &stylesheets,
+            &SelectorContext::default(),
+        )
+    }
+
+    fn keyword(s: &str) -> CSSValue {
+        CSSValue::Keyword(s.to_string())
+    }
+
+    #[test]
+    fn test_cascade_specificity() {
+        let mut attributes = AttrMap::new();
+        attributes.insert("class".to_string(), "inline".to_string());
+        attributes.insert("id".to_string(), "main".to_string());
+        let node = Element::new("div".to_string(), attributes, vec![]);
+
+        let props = cascade_str(
+            &node,
+            &[(
+                Origin::Author,
+                "#main { color: red } .inline { display: inline; color: blue } div { display: block; width: auto } div { width: none }",
+            )],
+        );
+        assert_eq!(props.get("display"), Some(&keyword("inline")));
+        assert_eq!(props.get("color"), Some(&keyword("red")));
+        // specificity相同时后出现的优先
+        assert_eq!(props.get("width"), Some(&keyword("none")));
+    }
+
+    #[test]
+    fn test_cascade_origin_and_important() {
+        let node = Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let props = cascade_str(
+            &node,
+            &[
+                (
+                    Origin::UserAgent,
+                    "p { display: block; color: black; width: auto !important }",
+                ),
+                (
+                    Origin::User,
+                    "p { color: green !important; display: inline }",
+                ),
+                (
+                    Origin::Author,
+                    "#x, p { color: red; display: none } p { width: none !important }",
+                ),
+            ],
+        );
+        assert_eq!(props.get("display"), Some(&keyword("none")));
+        assert_eq!(props.get("color"), Some(&keyword("green")));
+        assert_eq!(props.get("width"), Some(&keyword("auto")));
+    }
+
+    #[test]
+    fn test_inline_style() {
+        let mut attributes = AttrMap::new();
+        attributes.insert("id".to_string(), "main".to_string());
+        attributes.insert(
+            "style".to_string(),
+            "color: blue; display: inline; width: none".to_string(),
+        );
+        let node = Element::new("div".to_string(), attributes, vec![]);
+        let props = cascade_str(
+            &node,
+            &[
+                (Origin::UserAgent, "div { width: auto !important }"),
+                (
+                    Origin::Author,
+                    "#main { color: red } div { display: block !important }",
+                ),
+            ],
+        );
+        assert_eq!(props.get("color"), Some(&keyword("blue")));
+        // !important的声明优先于style属性
+        assert_eq!(props.get("display"), Some(&keyword("block")));
+        assert_eq!(props.get("width"), Some(&keyword("auto")));
+
+        // 解析失败时忽略
+        let mut attributes = AttrMap::new();
+        attributes.insert("style".to_string(), "color: }".to_string());
+        let node = Element::new("div".to_string(), attributes, vec![]);
+        assert_eq!(
+            cascade_str(&node, &[(Origin::Author, "div { color: red }")]).get("color"),
+            Some(&keyword("red"))
+        );
+    }
+
+    #[test]
+    fn test_computed_values() {
+        let mut attributes = AttrMap::new();
+        attributes.insert("class".to_string(), "note".to_string());
+        let text = crate::core::dom::text::Text::new("a".to_string());
+        let child = Element::new("span".to_string(), AttrMap::new(), vec![text]);
+        let node = Element::new("div".to_string(), attributes, vec![child]);
+        let stylesheets = vec![(
+            Origin::Author,
+            css::parse(
+                ".note { color: red; width: none; display: block } span { visibility: inherit; display: initial }"
+                    .to_string(),
+            )
+            .unwrap(),
+        )];
+        let styled = to_styled_node(
+            &node,
+            &stylesheets,
+            &SelectorContext::default(),
+            &Viewport::default(),
+            &mut vec![],
+            None,
+        );
+        assert_eq!(styled.properties.get("color"), Some(&keyword("red")));
+        assert_eq!(
+            styled.properties.get("white-space"),
+            Some(&keyword("normal"))
+        );
+        assert_eq!(styled.display(), Display::Block);
+
+        // 继承属性取父元素的值，非继承属性取初始值
+        let span = &styled.children[0];
+        assert_eq!(span.properties.get("color"), Some(&keyword("red")));
+        assert_eq!(span.properties.get("width"), Some(&keyword("auto")));
+        assert_eq!(span.properties.get("visibility"), Some(&keyword("visible")));
+        assert_eq!(span.display(), Display::Inline);
+
+        let text = &span.children[0];
+        assert_eq!(text.properties.get("color"), Some(&keyword("red")));
+        assert_eq!(text.properties.get("display"), Some(&keyword("inline")));
+    }
+
+    #[test]
+    fn test_pseudo_elements() {
+        let text = crate::core::dom::text::Text::new("b".to_string());
+        let node = Element::new("p".to_string(), AttrMap::new(), vec![text]);
+        let stylesheets = vec![(
+            Origin::Author,
+            css::parse(
+                r#"p { color: red } p::before { content: "a" } p::before { display: block }
+                p::after { content: "c" "d"; color: blue } p:hover::after { content: none }"#
+                    .to_string(),
+            )
+            .unwrap(),
+        )];
+        let styled = to_styled_node(
+            &node,
+            &stylesheets,
+            &SelectorContext::default(),
+            &Viewport::default(),
+            &mut vec![],
+            None,
+        );
+        let texts: Vec<_> = styled
+            .children
+            .iter()
+            .map(|c| match c.node_type.as_ref() {
+                NodeType::Text(t) => t.data.clone(),
+                _ => panic!("not a text node"),
+            })
+            .collect();
+        assert_eq!(texts, ["a", "b", "cd"]);
+        let (before, after) = (&styled.children[0], &styled.children[2]);
+        assert_eq!(before.display(), Display::Block);
+        assert_eq!(before.properties.get("color"), Some(&keyword("red")));
+        assert_eq!(after.properties.get("color"), Some(&keyword("blue")));
+
+        // content: none时不生成
+        let context = SelectorContext {
+            focused: Some(vec![]),
+            ..SelectorContext::default()
+        };
+        let styled = to_styled_node(
+            &node,
+            &stylesheets,
+            &context,
+            &Viewport::default(),
+            &mut vec![],
+            None,
+        );
+        assert_eq!(styled.children.len(), 2);
+    }
+
+    #[test]
+    fn test_custom_properties() {
+        let text = crate::core::dom::text::Text::new("a".to_string());
+        let child = Element::new("span".to_string(), AttrMap::new(), vec![text]);
+        let node = Element::new("div".to_string(), AttrMap::new(), vec![child]);
+        let stylesheets = vec![(
+            Origin::Author,
+            css::parse(
+                r#"
+                div { --fg: red; --border: 1px solid; --a: var(--b); --b: var(--a, blue) }
+                div { --c: var(--a, green); background-color: var(--missing) }
+                span {
+                    --span-fg: var(--missing, var(--fg));
+                    color: var(--span-fg);
+                    text-decoration: var(--border) var(--fg);
+                    visibility: var(--b);
+                    width: var(--b, 1px);
+                    height: var(--c);
+                }
+                "#
+                .to_string(),
+            )
+            .unwrap(),
+        )];
+        let styled = to_styled_node(
+            &node,
+            &stylesheets,
+            &SelectorContext::default(),
+            &Viewport::default(),
+            &mut vec![],
+            None,
+        );
+        assert_eq!(styled.properties.get("--fg"), Some(&keyword("red")));
+        // 循环引用的属性无效，引用它们的var()使用fallback
+        assert_eq!(styled.properties.get("--a"), None);
+        assert_eq!(styled.properties.get("--b"), None);
+        assert_eq!(styled.properties.get("--c"), Some(&keyword("green")));
+        assert_eq!(
+            styled.properties.get("background-color"),
+            Some(&keyword("transparent"))
+        );
+
+        let span = &styled.children[0];
+        assert_eq!(span.properties.get("color"), Some(&keyword("red")));
+        assert_eq!(
+            span.properties.get("text-decoration"),
+            Some(&CSSValue::List(vec![
+                CSSValue::Length((1.0, Unit::Px)),
+                keyword("solid"),
+                keyword("red"),
+            ]))
+        );
+        // 无效时继承属性取父元素的值，其他取初始值
+        assert_eq!(span.properties.get("visibility"), Some(&keyword("visible")));
+        assert_eq!(
+            span.properties.get("width"),
+            Some(&CSSValue::Length((1.0, Unit::Px)))
+        );
+        assert_eq!(span.properties.get("height"), Some(&keyword("green")));
+        // 文本节点也继承自定义属性
+        assert_eq!(
+            span.children[0].properties.get("--fg"),
+            Some(&keyword("red"))
+        );
+    }
+
+    #[test]
+    fn test_calc() {
+        let props = |declarations: &str| {
+            let specified = css::parse_declarations(declarations)
+                .unwrap()
+                .into_iter()
+                .map(|d| (d.name, d.value))
+                .collect();
+            compute(specified, None, &Viewport::default())
+        };
+        let length = |n, unit| CSSValue::Length((n, unit));
+
+        let p = props("--w: 10px; width: calc(2em + var(--w) * 2); height: calc(50vw / 4 - 1ch)");
+        assert_eq!(p.get("width"), Some(&length(52.0, Unit::Px)));
+        assert_eq!(p.get("height"), Some(&length(72.0, Unit::Px)));
+
+        // 百分比和px混合时保留
+        let p = props("width: calc(100% - 2em); height: calc((50% + 10%) * 2)");
+        assert_eq!(
+            p.get("width"),
+            Some(&CSSValue::Calc(Box::new(CalcExpression::Add(
+                Box::new(CalcExpression::Value(length(100.0, Unit::Percent))),
+                Box::new(CalcExpression::Value(length(-32.0, Unit::Px))),
+            ))))
+        );
+        assert_eq!(p.get("height"), Some(&length(120.0, Unit::Percent)));
+
+        // 类型不匹配或除以0时无效
+        let p = props("width: calc(1px + 2); height: calc(1px / 0); --x: calc(1px * 2)");
+        assert_eq!(p.get("width"), Some(&keyword("auto")));
+        assert_eq!(p.get("height"), Some(&keyword("auto")));
+        // 自定义属性中的calc()在使用时求值
+        assert_eq!(
+            p.get("--x"),
+            Some(&CSSValue::Calc(Box::new(CalcExpression::Mul(
+                Box::new(CalcExpression::Value(length(1.0, Unit::Px))),
+                Box::new(CalcExpression::Value(CSSValue::Number(2.0))),
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_font_relative_units() {
+        let props = |declarations: &str, parent: Option<&PropertyMap>| {
+            let specified = css::parse_declarations(declarations)
+                .unwrap()
+                .into_iter()
+                .map(|d| (d.name, d.value))
+                .collect();
+            compute(specified, parent, &Viewport::default())
+        };
+        let length = |n, unit| CSSValue::Length((n, unit));
+
+        let root = props("font-size: 20px", None);
+        // font-size的em相对于父元素，其他属性的em相对于自己的font-size，rem不变
+        let div = props(
+            "width: 1em; font-size: 2em; height: 1rem; padding-top: calc(1em + 50%)",
+            Some(&root),
+        );
+        assert_eq!(div.get("font-size"), Some(&length(40.0, Unit::Px)));
+        assert_eq!(div.get("width"), Some(&length(40.0, Unit::Px)));
+        assert_eq!(div.get("height"), Some(&length(1.0, Unit::Rem)));
+        assert_eq!(
+            div.get("padding-top"),
+            Some(&CSSValue::Calc(Box::new(CalcExpression::Add(
+                Box::new(CalcExpression::Value(length(50.0, Unit::Percent))),
+                Box::new(CalcExpression::Value(length(40.0, Unit::Px))),
+            ))))
+        );
+
+        // 继承的是换算后的px
+        let span = props("width: 0.5em; margin-top: 50vh", Some(&div));
+        assert_eq!(span.get("width"), Some(&length(20.0, Unit::Px)));
+        assert_eq!(span.get("margin-top"), Some(&length(50.0, Unit::Vh)));
+        assert_eq!(Viewport::default().to_px(50.0, Unit::Vh), Some(192.0));
+
+        let p = props("font-size: 150%", Some(&root));
+        assert_eq!(p.get("font-size"), Some(&length(30.0, Unit::Px)));
+        let p = props("font-size: small", Some(&root));
+        assert_eq!(p.get("font-size"), Some(&length(13.0, Unit::Px)));
+        let p = props("font-size: larger", Some(&root));
+        assert_eq!(p.get("font-size"), Some(&length(24.0, Unit::Px)));
+    }
+
+    #[test]
+    fn test_shorthands() {
+        let node = Element::new("div".to_string(), AttrMap::new(), vec![]);
+        let px = |n| CSSValue::Length((n, Unit::Px));
+        // 按出现顺序覆盖，无效的简写属性忽略
+        let props = cascade_str(
+            &node,
+            &[(
+                Origin::Author,
+                "div { margin: 1px 2px; margin-left: 3px; border-top-style: dotted; border: 1px solid } div { margin: auto auto auto auto auto }",
+            )],
+        );
+        assert_eq!(props.get("margin"), None);
+        assert_eq!(props.get("margin-top"), Some(&px(1.0)));
+        assert_eq!(props.get("margin-right"), Some(&px(2.0)));
+        assert_eq!(props.get("margin-left"), Some(&px(3.0)));
+        assert_eq!(props.get("border-top-style"), Some(&keyword("solid")));
+        assert_eq!(
+            props.get("border-bottom-color"),
+            Some(&keyword("currentcolor"))
+        );
+
+        // var()替换后再展开
+        let specified = cascade_str(
+            &node,
+            &[(
+                Origin::Author,
+                "div { --m: 4px 5px; margin: var(--m) 6px; padding: var(--m) var(--m) var(--m) }",
+            )],
+        );
+        let props = compute(specified, None, &Viewport::default());
+        assert_eq!(props.get("margin-top"), Some(&px(4.0)));
+        assert_eq!(props.get("margin-right"), Some(&px(5.0)));
+        assert_eq!(props.get("margin-bottom"), Some(&px(6.0)));
+        assert_eq!(props.get("margin-left"), Some(&px(5.0)));
+        // 6个值无效，取初始值
+        assert_eq!(props.get("padding-top"), Some(&CSSValue::Number(0.0)));
+    }
+
+    #[test]
+    fn test_external_stylesheets() {
+        let dir = std::env::temp_dir().join(format!("style-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("css")).unwrap();
+        std::fs::write(
+            dir.join("css/main.css"),
+            "@import 'base.css'; p { color: green }",
+        )
+        .unwrap();
+        std::fs::write(dir.join("css/base.css"), "p { color: red; display: block }").unwrap();
+
+        let mut link_attributes = AttrMap::new();
+        link_attributes.insert("rel".to_string(), "Stylesheet".to_string());
+        link_attributes.insert("href".to_string(), "css/main.css".to_string());
+        let head = Element::new(
+            "head".to_string(),
+            AttrMap::new(),
+            vec![
+                Element::new("link".to_string(), link_attributes, vec![]),
+                Element::new(
+                    "style".to_string(),
+                    AttrMap::new(),
+                    vec![crate::core::dom::text::Text::new(
+                        "p { display: inline }".to_string(),
+                    )],
+                ),
+            ],
+        );
+        let p = Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let html = Element::new("html".to_string(), AttrMap::new(), vec![head, p]);
+        let url = Url::from_file_path(dir.join("index.html"))
+            .unwrap()
+            .to_string();
+        let mut document = Document::new(url.clone(), url, html);
+        load_stylesheets(&mut document);
+
+        let styled =
+            to_styled_document(&document, &Viewport::default(), &SelectorContext::default());
+        let p = &styled.document_element.children[1];
+        // 顺序: base.css, main.css, <style>
+        assert_eq!(p.properties.get("color"), Some(&keyword("green")));
+        assert_eq!(p.display(), Display::Inline);
+
+        // 计算样式时只使用已经读取的样式表
+        std::fs::remove_dir_all(&dir).unwrap();
+        let styled =
+            to_styled_document(&document, &Viewport::default(), &SelectorContext::default());
+        let p = &styled.document_element.children[1];
+        assert_eq!(p.properties.get("color"), Some(&keyword("green")));
+    }
+
+    #[test]
+    fn test_failed_stylesheet_is_not_refetched() {
+        let dir = std::env::temp_dir().join(format!("style-fail-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut link_attributes = AttrMap::new();
+        link_attributes.insert("rel".to_string(), "stylesheet".to_string());
+        link_attributes.insert("href".to_string(), "missing.css".to_string());
+        let link = Element::new("link".to_string(), link_attributes, vec![]);
+        let p = Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let html = Element::new("html".to_string(), AttrMap::new(), vec![link, p]);
+        let url = Url::from_file_path(dir.join("index.html")).unwrap();
+        let mut document = Document::new(url.to_string(), url.to_string(), html);
+
+        load_stylesheets(&mut document);
+        let missing = url.join("missing.css").unwrap().to_string();
+        assert_eq!(document.external_stylesheets.get(&missing), Some(&None));
+
+        // 失败的结果也被记录，之后出现的文件不会被读取
+        std::fs::write(dir.join("missing.css"), "p { color: red }").unwrap();
+        load_stylesheets(&mut document);
+        let styled =
+            to_styled_document(&document, &Viewport::default(), &SelectorContext::default());
+        assert_eq!(
+            styled.document_element.children[1].properties.get("color"),
+            Some(&keyword("canvastext"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_media_queries() {
+        let props = |viewport: Viewport| {
+            let node = Element::new("p".to_string(), AttrMap::new(), vec![]);
+            let mut stylesheet = css::parse(
+                r#"
+                @media (max-width: 600px) { p { width: narrow } }
+                @media screen and (min-width: 601px), print { p { width: wide } }
+                @media (prefers-color-scheme: dark) { p { color: white } }
+                @media not screen { p { color: red } }
+                @media (min-width: 20em) and (orientation: landscape) {
+                    @media (max-height: 60vw) { p { height: short } }
+                }
+                "#
+                .to_string(),
+            )
+            .unwrap();
+            filter_media(&mut stylesheet, &viewport);
+            cascade(
+                NodeRef::new(&node, &[]),
+                None,
+                &[(Origin::Author, stylesheet)],
+                &SelectorContext::default(),
+            )
+        };
+
+        // 80列 = 640px
+        let wide = props(Viewport::default());
+        assert_eq!(wide.get("width"), Some(&keyword("wide")));
+        assert_eq!(wide.get("color"), None);
+        assert_eq!(wide.get("height"), Some(&keyword("short")));
+
+        let narrow = props(Viewport {
+            columns: 40,
+            rows: 50,
+            color_scheme: ColorScheme::Dark,
+        });
+        assert_eq!(narrow.get("width"), Some(&keyword("narrow")));
+        assert_eq!(narrow.get("color"), Some(&keyword("white")));
+        assert_eq!(narrow.get("height"), None);
+    }
+}
@@ -0,0 +1,547 @@
+use super::{initial_value, var_references};
+use crate::core::css::{CSSValue, Unit};
+
+// 四个方向，margin: 1px 2px 3px 4px 的顺序
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+const CSS_WIDE_KEYWORDS: &[&str] = &["inherit", "initial", "unset"];
+const BORDER_WIDTHS: &[&str] = &["thin", "medium", "thick"];
+const BORDER_STYLES: &[&str] = &[
+    "none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset",
+];
+const FONT_STYLES: &[&str] = &["italic", "oblique"];
+const FONT_VARIANTS: &[&str] = &["small-caps"];
+const FONT_WEIGHTS: &[&str] = &["bold", "bolder", "lighter"];
+const FONT_SIZES: &[&str] = &[
+    "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large", "larger", "smaller",
+];
+const BACKGROUND_REPEATS: &[&str] = &[
+    "repeat",
+    "repeat-x",
+    "repeat-y",
+    "no-repeat",
+    "space",
+    "round",
+];
+const BACKGROUND_ATTACHMENTS: &[&str] = &["scroll", "fixed", "local"];
+const BACKGROUND_POSITIONS: &[&str] = &["left", "right", "top", "bottom", "center"];
+const BACKGROUND_SIZES: &[&str] = &["auto", "cover", "contain"];
+
+// 简写属性包含的普通属性，不是简写属性时返回None
+fn longhands(name: &str) -> Option<Vec<String>> {
+    let border_side = |side: &str| {
+        ["width", "style", "color"]
+            .iter()
+            .map(|part| format!("border-{}-{}", side, part))
+            .collect::<Vec<_>>()
+    };
+    let strings = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+    match name {
+        "margin" | "padding" => Some(
+            SIDES
+                .iter()
+                .map(|side| format!("{}-{}", name, side))
+                .collect(),
+        ),
+        "border-width" | "border-style" | "border-color" => Some(
+            SIDES
+                .iter()
+                .map(|side| format!("border-{}-{}", side, &name["border-".len()..]))
+                .collect(),
+        ),
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            Some(border_side(&name["border-".len()..]))
+        }
+        "border" => Some(SIDES.iter().flat_map(|side| border_side(side)).collect()),
+        "font" => Some(strings(&[
+            "font-style",
+            "font-variant",
+            "font-weight",
+            "font-size",
+            "line-height",
+            "font-family",
+        ])),
+        "background" => Some(strings(&[
+            "background-color",
+            "background-image",
+            "background-repeat",
+            "background-attachment",
+            "background-position",
+            "background-size",
+        ])),
+        _ => None,
+    }
+}
+
+// 简写属性 -> [(普通属性, 值)]，省略的部分取初始值
+// 不是简写属性时原样返回，值无效时返回None
+// 值中有var()时无法判断，先记录下来，计算样式时替换后再展开
+pub fn expand(name: &str, value: &CSSValue) -> Option<Vec<(String, CSSValue)>> {
+    let longhands = match longhands(name) {
+        Some(longhands) => longhands,
+        None => return Some(vec![(name.to_string(), value.clone())]),
+    };
+    let mut references = vec![];
+    var_references(value, &mut references);
+    let values = if !references.is_empty() {
+        let pending = CSSValue::PendingSubstitution(name.to_string(), Box::new(value.clone()));
+        vec![Some(pending); longhands.len()]
+    } else if is_keyword(value, CSS_WIDE_KEYWORDS) {
+        vec![Some(value.clone()); longhands.len()]
+    } else {
+        expand_values(name, value)?
+    };
+    Some(
+        longhands
+            .into_iter()
+            .zip(values)
+            .filter_map(|(name, value)| {
+                let value = value.or_else(|| initial_value(&name))?;
+                Some((name, value))
+            })
+            .collect(),
+    )
+}
+
+// 按longhands()的顺序，None表示省略
+fn expand_values(name: &str, value: &CSSValue) -> Option<Vec<Option<CSSValue>>> {
+    match name {
+        "margin" => sides(value, |v| {
+            is_length_percentage(v) || is_keyword(v, &["auto"])
+        }),
+        "padding" => sides(value, is_length_percentage),
+        "border-width" => sides(value, is_border_width),
+        "border-style" => sides(value, |v| is_keyword(v, BORDER_STYLES)),
+        "border-color" => sides(value, is_color),
+        "border" => {
+            let side = border_side(value)?;
+            Some((0..SIDES.len()).flat_map(|_| side.clone()).collect())
+        }
+        "font" => font(value),
+        "background" => background(value),
+        "border-top" | "border-right" | "border-bottom" | "border-left" => border_side(value),
+        _ => None,
+    }
+}
+
+// 1到4个值: 上 右 下 左，省略的取对边的值
+fn sides(value: &CSSValue, valid: impl Fn(&CSSValue) -> bool) -> Option<Vec<Option<CSSValue>>> {
+    let values = components(value);
+    if !values.iter().all(|v| valid(v)) {
+        return None;
+    }
+    let (top, right, bottom, left) = match values[..] {
+        [a] => (a, a, a, a),
+        [a, b] => (a, b, a, b),
+        [a, b, c] => (a, b, c, b),
+        [a, b, c, d] => (a, b, c, d),
+        _ => return None,
+    };
+    Some(
+        [top, right, bottom, left]
+            .into_iter()
+            .map(|v| Some(v.clone()))
+            .collect(),
+    )
+}
+
+// 宽度、样式、颜色，顺序任意，各最多一个
+fn border_side(value: &CSSValue) -> Option<Vec<Option<CSSValue>>> {
+    let (mut width, mut style, mut color) = (None, None, None);
+    for v in components(value) {
+        let slot = if is_border_width(v) {
+            &mut width
+        } else if is_keyword(v, BORDER_STYLES) {
+            &mut style
+        } else if is_color(v) {
+            &mut color
+        } else {
+            return None;
+        };
+        if slot.replace(v.clone()).is_some() {
+            return None;
+        }
+    }
+    Some(vec![width, style, color])
+}
+
+// [style || variant || weight]? size[/line-height]? family[, family]*
+fn font(value: &CSSValue) -> Option<Vec<Option<CSSValue>>> {
+    let (first, other_families) = match value {
+        CSSValue::CommaList(values) => (&values[0], &values[1..]),
+        v => (v, &[][..]),
+    };
+    let values = components(first);
+    let (mut style, mut variant, mut weight) = (None, None, None);
+    let mut i = 0;
+    // normal可以是前三个中的任意一个，不用记录
+    while i < 3 && i < values.len() {
+        let v = values[i];
+        let slot = if is_keyword(v, &["normal"]) {
+            i += 1;
+            continue;
+        } else if is_keyword(v, FONT_STYLES) {
+            &mut style
+        } else if is_keyword(v, FONT_VARIANTS) {
+            &mut variant
+        } else if is_font_weight(v) {
+            &mut weight
+        } else {
+            break;
+        };
+        if slot.replace(v.clone()).is_some() {
+            return None;
+        }
+        i += 1;
+    }
+
+    let size = values
+        .get(i)
+        .filter(|v| is_length_percentage(v) || is_keyword(v, FONT_SIZES))?;
+    i += 1;
+    let line_height = match values.get(i) {
+        Some(CSSValue::Slash) => {
+            let line_height = values.get(i + 1).filter(|v| {
+                is_length_percentage(v)
+                    || matches!(v, CSSValue::Number(_))
+                    || is_keyword(v, &["normal"])
+            })?;
+            i += 2;
+            Some((*line_height).clone())
+        }
+        _ => None,
+    };
+
+    let family = &values[i..];
+    if family.is_empty() || !family.iter().all(|v| is_family_name(v)) {
+        return None;
+    }
+    let family = list(family);
+    let family = if other_families.is_empty() {
+        family
+    } else {
+        let valid = |v: &CSSValue| components(v).iter().all(|v| is_family_name(v));
+        if !other_families.iter().all(valid) {
+            return None;
+        }
+        CSSValue::CommaList(
+            std::iter::once(family)
+                .chain(other_families.iter().cloned())
+                .collect(),
+        )
+    };
+    Some(vec![
+        style,
+        variant,
+        weight,
+        Some((*size).clone()),
+        line_height,
+        Some(family),
+    ])
+}
+
+// color, image, repeat, attachment, position[/size]，顺序任意，不支持多层背景
+fn background(value: &CSSValue) -> Option<Vec<Option<CSSValue>>> {
+    let values = components(value);
+    let (mut color, mut image, mut repeat, mut attachment) = (None, None, None, None);
+    let (mut position, mut size) = (None, None);
+    // 从i开始最多max个连续的有效值
+    let run = |i: usize, max: usize, valid: fn(&CSSValue) -> bool| {
+        values[i..]
+            .iter()
+            .take(max)
+            .take_while(|v| valid(v))
+            .count()
+    };
+    let mut i = 0;
+    while i < values.len() {
+        let v = values[i];
+        let (slot, n) = if is_background_position(v) {
+            (&mut position, run(i, 4, is_background_position))
+        } else if is_keyword(v, BACKGROUND_REPEATS) {
+            (
+                &mut repeat,
+                run(i, 2, |v| is_keyword(v, BACKGROUND_REPEATS)),
+            )
+        } else if is_keyword(v, BACKGROUND_ATTACHMENTS) {
+            (&mut attachment, 1)
+        } else if matches!(v, CSSValue::Url(_)) || is_keyword(v, &["none"]) {
+            (&mut image, 1)
+        } else if is_color(v) {
+            (&mut color, 1)
+        } else {
+            return None;
+        };
+        if slot.replace(list(&values[i..i + n])).is_some() {
+            return None;
+        }
+        i += n;
+        // size只能紧跟在position后面
+        if is_background_position(v) && values.get(i) == Some(&&CSSValue::Slash) {
+            let n = run(i + 1, 2, is_background_size);
+            if n == 0 {
+                return None;
+            }
+            size = Some(list(&values[i + 1..i + 1 + n]));
+            i += 1 + n;
+        }
+    }
+    Some(vec![color, image, repeat, attachment, position, size])
+}
+
+// 空白分隔的各个值
+fn components(value: &CSSValue) -> Vec<&CSSValue> {
+    match value {
+        CSSValue::List(values) => values.iter().collect(),
+        v => vec![v],
+    }
+}
+
+// components()的反向操作
+fn list(values: &[&CSSValue]) -> CSSValue {
+    match values {
+        [v] => (*v).clone(),
+        values => CSSValue::List(values.iter().map(|v| (*v).clone()).collect()),
+    }
+}
+
+fn is_keyword(value: &CSSValue, keywords: &[&str]) -> bool {
+    matches!(value, CSSValue::Keyword(k) if keywords.contains(&k.as_str()))
+}
+
+// 没有单位的数字只能是0
+fn is_length_percentage(value: &CSSValue) -> bool {
+    matches!(
+        value,
+        CSSValue::Length(_) | CSSValue::Calc(_) | CSSValue::Number(0.0)
+    )
+}
+
+fn is_border_width(value: &CSSValue) -> bool {
+    match value {
+        CSSValue::Length((_, unit)) => *unit != Unit::Percent,
+        v => is_length_percentage(v) || is_keyword(v, BORDER_WIDTHS),
+    }
+}
+
+// 其他关键字都当作颜色名
+fn is_color(value: &CSSValue) -> bool {
+    match value {
+        CSSValue::Color(_) => true,
+        CSSValue::Keyword(_) => !is_keyword(value, CSS_WIDE_KEYWORDS),
+        _ => false,
+    }
+}
+
+fn is_font_weight(value: &CSSValue) -> bool {
+    match value {
+        CSSValue::Number(n) => (1.0..=1000.0).contains(n),
+        v => is_keyword(v, FONT_WEIGHTS),
+    }
+}
+
+fn is_family_name(value: &CSSValue) -> bool {
+    match value {
+        CSSValue::String(_) => true,
+        CSSValue::Keyword(_) => !is_keyword(value, CSS_WIDE_KEYWORDS),
+        _ => false,
+    }
+}
+
+fn is_background_position(value: &CSSValue) -> bool {
+    is_length_percentage(value) || is_keyword(value, BACKGROUND_POSITIONS)
+}
+
+fn is_background_size(value: &CSSValue) -> bool {
+    is_length_percentage(value) || is_keyword(value, BACKGROUND_SIZES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::css::{parse_value, Color};
+
+    fn expand_str(name: &str, value: &str) -> Option<Vec<(String, CSSValue)>> {
+        expand(name, &parse_value(value).unwrap())
+    }
+
+    fn keyword(s: &str) -> CSSValue {
+        CSSValue::Keyword(s.to_string())
+    }
+
+    fn px(n: f64) -> CSSValue {
+        CSSValue::Length((n, Unit::Px))
+    }
+
+    fn longhand(name: &str, value: CSSValue) -> (String, CSSValue) {
+        (name.to_string(), value)
+    }
+
+    #[test]
+    fn test_expand_sides() {
+        assert_eq!(
+            expand_str("margin", "1px 2px"),
+            Some(vec![
+                longhand("margin-top", px(1.0)),
+                longhand("margin-right", px(2.0)),
+                longhand("margin-bottom", px(1.0)),
+                longhand("margin-left", px(2.0)),
+            ])
+        );
+        assert_eq!(
+            expand_str("padding", "1px 2px 3px"),
+            Some(vec![
+                longhand("padding-top", px(1.0)),
+                longhand("padding-right", px(2.0)),
+                longhand("padding-bottom", px(3.0)),
+                longhand("padding-left", px(2.0)),
+            ])
+        );
+        assert_eq!(
+            expand_str("border-style", "solid"),
+            Some(
+                SIDES
+                    .iter()
+                    .map(|side| longhand(&format!("border-{}-style", side), keyword("solid")))
+                    .collect()
+            )
+        );
+        // 不是简写属性时原样返回
+        assert_eq!(
+            expand_str("margin-top", "1px"),
+            Some(vec![longhand("margin-top", px(1.0))])
+        );
+        assert_eq!(expand_str("margin", "1px 2px 3px 4px 5px"), None);
+        assert_eq!(expand_str("padding", "auto"), None);
+        assert_eq!(expand_str("border-width", "10%"), None);
+    }
+
+    #[test]
+    fn test_expand_border() {
+        let red = CSSValue::Color(Color::rgb(255, 0, 0));
+        assert_eq!(
+            expand_str("border-left", "#f00 2px"),
+            Some(vec![
+                longhand("border-left-width", px(2.0)),
+                longhand("border-left-style", keyword("none")),
+                longhand("border-left-color", red),
+            ])
+        );
+        let border = expand_str("border", "thick dashed").unwrap();
+        assert_eq!(border.len(), 12);
+        assert_eq!(border[3], longhand("border-right-width", keyword("thick")));
+        assert_eq!(border[4], longhand("border-right-style", keyword("dashed")));
+        assert_eq!(
+            border[5],
+            longhand("border-right-color", keyword("currentcolor"))
+        );
+        assert_eq!(expand_str("border", "1px 2px solid"), None);
+        assert_eq!(
+            expand_str("border", "inherit").unwrap()[11],
+            longhand("border-left-color", keyword("inherit"))
+        );
+    }
+
+    #[test]
+    fn test_expand_font() {
+        assert_eq!(
+            expand_str("font", "bold italic 12px/1.5 \"Noto Sans\", serif"),
+            Some(vec![
+                longhand("font-style", keyword("italic")),
+                longhand("font-variant", keyword("normal")),
+                longhand("font-weight", keyword("bold")),
+                longhand("font-size", px(12.0)),
+                longhand("line-height", CSSValue::Number(1.5)),
+                longhand(
+                    "font-family",
+                    CSSValue::CommaList(vec![
+                        CSSValue::String("Noto Sans".to_string()),
+                        keyword("serif"),
+                    ])
+                ),
+            ])
+        );
+        assert_eq!(
+            expand_str("font", "normal 700 large Times New Roman"),
+            Some(vec![
+                longhand("font-style", keyword("normal")),
+                longhand("font-variant", keyword("normal")),
+                longhand("font-weight", CSSValue::Number(700.0)),
+                longhand("font-size", keyword("large")),
+                longhand("line-height", keyword("normal")),
+                longhand(
+                    "font-family",
+                    CSSValue::List(vec![keyword("times"), keyword("new"), keyword("roman")])
+                ),
+            ])
+        );
+        // size和family不能省略
+        assert_eq!(expand_str("font", "bold 12px"), None);
+        assert_eq!(expand_str("font", "bold serif"), None);
+        assert_eq!(expand_str("font", "bold bold 12px serif"), None);
+    }
+
+    #[test]
+    fn test_expand_background() {
+        assert_eq!(
+            expand_str("background", "#fff"),
+            Some(vec![
+                longhand(
+                    "background-color",
+                    CSSValue::Color(Color::rgb(255, 255, 255))
+                ),
+                longhand("background-image", keyword("none")),
+                longhand("background-repeat", keyword("repeat")),
+                longhand("background-attachment", keyword("scroll")),
+                longhand(
+                    "background-position",
+                    CSSValue::List(vec![
+                        CSSValue::Length((0.0, Unit::Percent)),
+                        CSSValue::Length((0.0, Unit::Percent)),
+                    ])
+                ),
+                longhand("background-size", keyword("auto")),
+            ])
+        );
+        let background = expand_str(
+            "background",
+            "url(a.png) no-repeat center / cover fixed blue",
+        )
+        .unwrap();
+        assert_eq!(background[0], longhand("background-color", keyword("blue")));
+        assert_eq!(
+            background[1],
+            longhand("background-image", CSSValue::Url("a.png".to_string()))
+        );
+        assert_eq!(
+            background[2],
+            longhand("background-repeat", keyword("no-repeat"))
+        );
+        assert_eq!(
+            background[3],
+            longhand("background-attachment", keyword("fixed"))
+        );
+        assert_eq!(
+            background[4],
+            longhand("background-position", keyword("center"))
+        );
+        assert_eq!(background[5], longhand("background-size", keyword("cover")));
+        assert_eq!(expand_str("background", "red blue"), None);
+        assert_eq!(expand_str("background", "red / cover"), None);
+    }
+
+    #[test]
+    fn test_expand_pending_substitution() {
+        let value = parse_value("var(--m) 2px").unwrap();
+        let pending = CSSValue::PendingSubstitution("margin".to_string(), Box::new(value.clone()));
+        assert_eq!(
+            expand("margin", &value),
+            Some(
+                SIDES
+                    .iter()
+                    .map(|side| longhand(&format!("margin-{}", side), pending.clone()))
+                    .collect()
+            )
+        );
+    }
+}
@@ -1,10 +1,7 @@
 use cursive::{theme::Effect, utils::markup::StyledString, views::TextView, View};
 
-use crate::core::{dom::element::Element, layout::LayoutBox};
+use crate::core::dom::element::Element;
 
-pub fn render(lbox: &LayoutBox, _element: &Element) -> Box<dyn View> {
-    Box::new(TextView::new(StyledString::styled(
-        lbox.inner_text(),
-        Effect::Italic,
-    )))
+pub fn render(text: String, _element: &Element) -> Box<dyn View> {
+    Box::new(TextView::new(StyledString::styled(text, Effect::Italic)))
 }
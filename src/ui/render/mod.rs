@@ -2,59 +2,121 @@ mod a;
 mod i;
 mod input;
 
-use crate::core::{
-    dom::element::Element,
-    dom::node::NodeType,
-    layout::{BoxProps, BoxType, LayoutBox},
+use crate::{
+    core::{
+        dom::{element::Element, node::NodeType},
+        layout::{BoxType, EdgeSizes, Fragment, FragmentKind, LayoutBox},
+    },
+    ui::views::with_current_page_view,
 };
 use cursive::{
-    views::{LinearLayout, TextView},
+    event::EventResult,
+    view::{Margins, Nameable},
+    views::{DummyView, FocusTracker, LinearLayout, PaddedView, Panel, ResizedView, TextView},
     View,
 };
+use std::collections::HashMap;
 
 pub type ElementContainer = LinearLayout;
 
+// 可以获得焦点的元素的view名，重新渲染后用来找回焦点
+// 换行的<a>分成多个片段，用片段的序号区分
+pub fn element_view_name(path: &[usize], fragment: usize) -> String {
+    let path: Vec<String> = path.iter().map(|i| i.to_string()).collect();
+    format!("element-{}:{}", path.join("-"), fragment)
+}
+
+// 焦点变化时通知PageView，:focus和:hover需要重新计算样式
+fn track_focus(view: Box<dyn View>, path: &[usize], fragment: usize) -> Box<dyn View> {
+    let focused = path.to_vec();
+    let lost = path.to_vec();
+    Box::new(
+        FocusTracker::new(view)
+            .on_focus(move |_| {
+                let focused = focused.clone();
+                EventResult::with_cb(move |s| {
+                    with_current_page_view(s, |v| v.set_focus(focused.clone(), fragment));
+                })
+            })
+            .on_focus_lost(move |_| {
+                let lost = lost.clone();
+                EventResult::with_cb(move |s| {
+                    with_current_page_view(s, |v| v.remove_focus(&lost, fragment));
+                })
+            })
+            .with_name(element_view_name(path, fragment)),
+    )
+}
+
+// 同一个元素的片段从0开始编号
+fn next_fragment(fragments: &mut HashMap<Vec<usize>, usize>, path: &[usize]) -> usize {
+    let count = fragments.entry(path.to_vec()).or_insert(0);
+    *count += 1;
+    *count - 1
+}
+
+fn to_margins(edges: EdgeSizes) -> Margins {
+    // 负的margin无法表示
+    let cells = |n: i32| n.max(0) as usize;
+    Margins::lrtb(
+        cells(edges.left),
+        cells(edges.right),
+        cells(edges.top),
+        cells(edges.bottom),
+    )
+}
+
+// 块级元素按layout的结果加上padding, border, margin并固定宽度
+// 四边都有边框时用Panel画出，否则边框只占位置
+fn to_box_view(layout: &LayoutBox) -> Box<dyn View> {
+    let view = to_element_container(layout);
+    if !matches!(layout.box_type, BoxType::BlockBox) {
+        return Box::new(view);
+    }
+    let d = layout.dimensions;
+    let b = d.border;
+    let view: Box<dyn View> = if b.left > 0 && b.right > 0 && b.top > 0 && b.bottom > 0 {
+        Box::new(Panel::new(PaddedView::new(to_margins(d.padding), view)))
+    } else {
+        let padding = EdgeSizes {
+            left: d.padding.left + b.left,
+            right: d.padding.right + b.right,
+            top: d.padding.top + b.top,
+            bottom: d.padding.bottom + b.bottom,
+        };
+        Box::new(PaddedView::new(to_margins(padding), view))
+    };
+    let width = d.border_box().width.max(0) as usize;
+    Box::new(PaddedView::new(
+        to_margins(d.margin),
+        ResizedView::with_fixed_width(width, view),
+    ))
+}
+
 pub fn to_element_container<'a>(layout: &LayoutBox<'a>) -> ElementContainer {
     let mut container = match layout.box_type {
         BoxType::NoneBox => {
             return LinearLayout::horizontal();
         }
         BoxType::BlockBox => LinearLayout::vertical(),
-        BoxType::InlineBox | BoxType::AnonymousBox => LinearLayout::horizontal(),
+        // 行内格式化上下文，按layout排好的行显示
+        BoxType::InlineBox | BoxType::AnonymousBox => return to_lines_container(layout),
     };
 
-    let elements = match layout.box_props {
-        Some(BoxProps {
-            node_type: NodeType::Element(ref element),
-            ..
-        }) => match element.tag_name.as_str() {
-            "a" => vec![a::render(layout, element)],
-            "i" => vec![i::render(layout, element)],
-            "input" => vec![input::render(layout, element)],
-            _ => layout
-                .children
-                .iter()
-                .map(|child| Box::new(to_element_container(child)) as Box<dyn View>)
-                .collect(),
+    let path = path_of(layout);
+    let elements = match element_of(layout) {
+        Some(element) => match element.tag_name.as_str() {
+            // 块级元素只有一个片段
+            "a" => vec![track_focus(
+                a::render(layout.inner_text(), element),
+                path,
+                0,
+            )],
+            "i" => vec![i::render(layout.inner_text(), element)],
+            "input" => vec![track_focus(input::render(layout, element), path, 0)],
+            _ => layout.children.iter().map(to_box_view).collect(),
         },
-        Some(BoxProps {
-            node_type: NodeType::Text(ref t),
-            ..
-        }) => {
-            let text_to_display = t.data.clone();
-            let text_to_display = text_to_display.replace("\n", "");
-            let text_to_display = text_to_display.trim();
-            if text_to_display != "" {
-                vec![Box::new(TextView::new(text_to_display)) as Box<dyn View>]
-            } else {
-                vec![]
-            }
-        }
-        _ => layout
-            .children
-            .iter()
-            .map(|child| Box::new(to_element_container(child)) as Box<dyn View>)
-            .collect(),
+        None => layout.children.iter().map(to_box_view).collect(),
     };
     for child in elements {
         container.add_child(child);
@@ -62,3 +124,107 @@ pub fn to_element_container<'a>(layout: &LayoutBox<'a>) -> ElementContainer {
 
     container
 }
+
+// 每行一个水平的LinearLayout
+fn to_lines_container(root: &LayoutBox) -> ElementContainer {
+    let mut container = LinearLayout::vertical();
+    let mut fragments = HashMap::new();
+    for line in &root.lines {
+        let mut row = LinearLayout::horizontal();
+        for fragment in &line.fragments {
+            row.add_child(to_fragment_view(root, fragment, &mut fragments));
+        }
+        // pre中的空行也要占一行
+        if line.fragments.is_empty() {
+            row.add_child(ResizedView::with_fixed_height(1, DummyView));
+        }
+        container.add_child(row);
+    }
+    container
+}
+
+// 文本按所在的<a>或<i>显示
+// fragments: 每个元素已经生成的片段数
+fn to_fragment_view(
+    root: &LayoutBox,
+    fragment: &Fragment,
+    fragments: &mut HashMap<Vec<usize>, usize>,
+) -> Box<dyn View> {
+    let boxes = root.descendants(&fragment.boxes);
+    let leaf = boxes.last().copied().unwrap_or(root);
+    match fragment.kind {
+        FragmentKind::Block => to_box_view(leaf),
+        FragmentKind::Atomic => match element_of(leaf) {
+            Some(element) => {
+                let index = next_fragment(fragments, path_of(leaf));
+                track_focus(input::render(leaf, element), path_of(leaf), index)
+            }
+            None => Box::new(DummyView),
+        },
+        FragmentKind::Text(ref text) => {
+            for lbox in boxes.iter().rev() {
+                match element_of(lbox).map(|e| (e, e.tag_name.as_str())) {
+                    Some((element, "a")) => {
+                        let index = next_fragment(fragments, path_of(lbox));
+                        return track_focus(a::render(text.clone(), element), path_of(lbox), index);
+                    }
+                    Some((element, "i")) => return i::render(text.clone(), element),
+                    _ => {}
+                }
+            }
+            Box::new(TextView::new(text.clone()))
+        }
+    }
+}
+
+fn element_of<'b>(layout: &'b LayoutBox) -> Option<&'b Element> {
+    match layout.box_props.as_ref().map(|p| p.node_type.as_ref()) {
+        Some(NodeType::Element(element)) => Some(element),
+        _ => None,
+    }
+}
+
+fn path_of<'b>(layout: &'b LayoutBox) -> &'b [usize] {
+    layout.box_props.as_ref().map_or(&[][..], |p| &p.path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        css::SelectorContext,
+        dom::{
+            document::Document,
+            element::{AttrMap, Element},
+            text::Text,
+        },
+        layout::to_layout_document,
+        style::{to_styled_document, Viewport},
+    };
+    use cursive::view::Selector;
+
+    #[test]
+    fn test_block_focusable_elements() {
+        let block = |tag_name: &str, children| {
+            let mut attributes = AttrMap::new();
+            attributes.insert("style".to_string(), "display: block".to_string());
+            Element::new(tag_name.to_string(), attributes, children)
+        };
+        let a = block("a", vec![Text::new("link".to_string())]);
+        let input = block("input", vec![]);
+        let body = Element::new("body".to_string(), AttrMap::new(), vec![a, input]);
+        let html = Element::new("html".to_string(), AttrMap::new(), vec![body]);
+        let document = Document::new("".to_string(), "".to_string(), html);
+        let viewport = Viewport::default();
+        let styled = to_styled_document(&document, &viewport, &SelectorContext::default());
+        let layout = to_layout_document(styled, &viewport);
+
+        // 块级的<a>和<input>只有一个片段，PageView按片段0找回焦点
+        let mut container = to_element_container(&layout.top_box);
+        for lbox in &layout.top_box.children[0].children {
+            assert!(matches!(lbox.box_type, BoxType::BlockBox));
+            let name = element_view_name(path_of(lbox), 0);
+            assert!(container.focus_view(&Selector::Name(&name)).is_ok());
+        }
+    }
+}
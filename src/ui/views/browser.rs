@@ -1,15 +1,18 @@
 use cursive::{
+    theme::{BaseColor, Color, PaletteColor, Theme},
     traits::Finder,
     view::{Nameable, Resizable, ViewWrapper},
     views::{LinearLayout, NamedView, Panel, ScrollView},
-    CbSink, Cursive, With,
+    CbSink, Cursive, Vec2, View, With,
 };
-use log::error;
-use std::{error::Error, rc::Rc};
+use log::{error, warn};
+use std::{collections::HashSet, error::Error, rc::Rc};
 
 use crate::core::{
     fetch::{fetch, Request},
-    html, url,
+    html,
+    style::{ColorScheme, Viewport},
+    url,
 };
 
 use super::{NavigationView, PageView};
@@ -19,16 +22,28 @@ pub static NAVBAR_VIEW_NAME: &str = "browser-view-navbar";
 pub static PAGE_VIEW_NAME: &str = "browser-view-page";
 pub static PAGE_VIEW_CONTAINER_NAME: &str = "browser-view-page-container";
 
+// 导航栏的高度
+const NAVBAR_HEIGHT: usize = 3;
+
 pub struct BrowserView {
     view: LinearLayout,
     ui_cb_sink: Rc<CbSink>,
+    // 页面的显示区域，切换页面时交给新的PageView
+    viewport: Viewport,
+    // 访问过的url，用于:visited
+    visited: HashSet<String>,
 }
 
 impl BrowserView {
-    pub fn named(ui_cb_sink: Rc<CbSink>) -> NamedView<Self> {
+    pub fn named(ui_cb_sink: Rc<CbSink>, theme: &Theme) -> NamedView<Self> {
         (BrowserView {
             ui_cb_sink: ui_cb_sink.clone(),
             view: LinearLayout::vertical(),
+            viewport: Viewport {
+                color_scheme: color_scheme(theme),
+                ..Viewport::default()
+            },
+            visited: HashSet::new(),
         })
         .with(|view| {
             view.add_named_navigation_container();
@@ -51,7 +66,7 @@ impl BrowserView {
         self.view.add_child(
             Panel::new(
                 ScrollView::new(
-                    PageView::new(self.ui_cb_sink.clone())
+                    PageView::new(self.ui_cb_sink.clone(), self.viewport, self.visited.clone())
                         .with_name(PAGE_VIEW_NAME)
                         .full_screen(),
                 )
@@ -109,6 +124,10 @@ impl BrowserView {
                 absolute_url
             ))?;
 
+        // :visited比较的是解析后的url
+        self.visited
+            .insert(url::Url::parse(&absolute_url).map_or(absolute_url.clone(), |u| u.to_string()));
+
         self.view.remove_child(1).ok_or(format!(
             "failed to navigate to {}; failed to find page container",
             absolute_url
@@ -117,24 +136,64 @@ impl BrowserView {
         self.add_named_page_container();
 
         let response = fetch(Request::new(absolute_url.clone()))?;
-        let document = html::parse(response)?;
-
-        self.view
-            .call_on_name(PAGE_VIEW_NAME, |view: &mut PageView| {
-                view.init_page(document)
-            })
-            .ok_or(format!(
+        let mut parser = html::HTMLParser::new(response.url.clone(), &response.headers);
+        let no_container = || {
+            format!(
                 "failed to render {}; no element container found",
                 absolute_url
-            ))??;
+            )
+        };
+        self.with_page_view_mut(|view| view.init_page(parser.document()))
+            .ok_or_else(no_container)??;
+
+        // fetch()已经收到了整个响应，渲染只在<script>暂停时和解析结束时进行
+        parser.push(&response.data);
+        parser.end();
+        self.with_page_view_mut(|view| view.parse_page(&mut parser))
+            .ok_or_else(no_container)??;
+
+        for e in parser.errors() {
+            warn!("html parse error; {}", e);
+        }
+        let css_errors = self
+            .with_page_view_mut(|view| view.stylesheet_errors())
+            .ok_or_else(no_container)?;
+        for e in css_errors {
+            warn!("css parse error; {}", e);
+        }
 
         Ok(())
     }
 }
 
+// 背景色较暗时为dark
+fn color_scheme(theme: &Theme) -> ColorScheme {
+    match theme.palette[PaletteColor::View] {
+        Color::Dark(BaseColor::Black) | Color::Light(BaseColor::Black) => ColorScheme::Dark,
+        Color::Rgb(r, g, b) if (r as u32 + g as u32 + b as u32) < 384 => ColorScheme::Dark,
+        Color::RgbLowRes(r, g, b) if r + g + b < 8 => ColorScheme::Dark,
+        _ => ColorScheme::Light,
+    }
+}
+
 impl ViewWrapper for BrowserView {
     type V = LinearLayout;
 
+    // 终端大小变化时重新计算样式
+    fn wrap_layout(&mut self, size: Vec2) {
+        // 去掉导航栏和Panel的边框
+        let viewport = Viewport {
+            columns: size.x.saturating_sub(2),
+            rows: size.y.saturating_sub(NAVBAR_HEIGHT + 2),
+            ..self.viewport
+        };
+        if viewport != self.viewport {
+            self.viewport = viewport;
+            self.with_page_view_mut(|view| view.resize(viewport));
+        }
+        self.view.layout(size);
+    }
+
     fn with_view<F, R>(&self, f: F) -> ::std::option::Option<R>
     where
         F: FnOnce(&Self::V) -> R,
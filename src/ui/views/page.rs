@@ -1,20 +1,32 @@
-use cursive::{traits::Finder, view::ViewWrapper, views::LinearLayout, CbSink, Cursive, With};
-use std::{cell::RefCell, rc::Rc};
+use cursive::{
+    traits::Finder,
+    view::{Selector, ViewWrapper},
+    views::LinearLayout,
+    CbSink, Cursive, Vec2, View, With,
+};
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
 
 use crate::{
     core::{
+        css::{CSSParseError, SelectorContext},
         dom::document::Document,
+        html::{HTMLParser, ParseStatus},
         layout::{to_layout_document, LayoutDocument},
-        style::{to_styled_document, StyledDocument},
+        source::Diagnostic,
+        style::{
+            author_stylesheet_errors, load_stylesheets, to_styled_document, StyledDocument,
+            Viewport,
+        },
+        url::Url,
         window::Window,
     },
     javascript::{JsRuntime, JsRuntimeState},
     ui::{
         api::PageViewAPIHandler,
-        render::{to_element_container, ElementContainer},
+        render::{element_view_name, to_element_container, ElementContainer},
     },
 };
-use log::{error, info};
+use log::{error, info, warn};
 use thiserror::Error;
 
 use super::PAGE_VIEW_NAME;
@@ -31,6 +43,12 @@ pub enum PageError {
 pub struct PageView {
     window: Option<Rc<RefCell<Window>>>,
     document: Option<Rc<RefCell<Document>>>,
+    viewport: Viewport,
+    // 焦点和访问历史，用于:focus, :visited等
+    context: SelectorContext,
+    // 获得焦点的片段，和当前的view渲染时的焦点
+    focused_fragment: usize,
+    rendered_focus: Option<Vec<usize>>,
 
     view: ElementContainer,
 
@@ -38,10 +56,17 @@ pub struct PageView {
 }
 
 impl PageView {
-    pub fn new(ui_cb_sink: Rc<CbSink>) -> Self {
+    pub fn new(ui_cb_sink: Rc<CbSink>, viewport: Viewport, visited: HashSet<String>) -> Self {
         (Self {
             window: None,
             document: None,
+            viewport,
+            context: SelectorContext {
+                visited,
+                ..SelectorContext::default()
+            },
+            focused_fragment: 0,
+            rendered_focus: None,
 
             view: ElementContainer::vertical(),
 
@@ -71,50 +96,137 @@ impl PageView {
 
         self.render_document()?;
 
-        self.execute_inline_scripts()?;
-
         Ok(())
     }
 
+    // 解析已经收到的内容，<script>在解析到时执行
+    // document()会复制整棵树，只在脚本暂停时和解析结束时取出并渲染
+    pub fn parse_page(&mut self, parser: &mut HTMLParser) -> Result<(), PageError> {
+        loop {
+            match parser.run() {
+                ParseStatus::Script(script) => {
+                    self.replace_document(parser.document())?;
+                    // 脚本出错时保留出错前的修改，继续解析后面的内容
+                    if let Err(e) = self.execute_script(script.as_str()) {
+                        warn!("{}", e);
+                    }
+                    let document = match &self.document {
+                        Some(d) => d,
+                        None => return Err(PageError::NoDocumentError),
+                    };
+                    parser.update_document(&document.borrow());
+                    self.render_document()?;
+                }
+                ParseStatus::NeedsInput => return Ok(()),
+                ParseStatus::Finished => {
+                    self.replace_document(parser.document())?;
+                    return self.render_document();
+                }
+            }
+        }
+    }
+
+    // 作者样式表中被跳过的规则和声明
+    pub fn stylesheet_errors(&self) -> Vec<Diagnostic<CSSParseError>> {
+        self.document
+            .as_ref()
+            .map_or(vec![], |d| author_stylesheet_errors(&d.borrow()))
+    }
+
+    // 保持同一个Rc，JsRuntimeState中的引用不变
+    // 已经读取的外部样式表交给新的文档，只读取新出现的<link>和@import
+    fn replace_document(&mut self, mut document: Document) -> Result<(), PageError> {
+        match &self.document {
+            Some(d) => {
+                let mut d = d.borrow_mut();
+                document.external_stylesheets = std::mem::take(&mut d.external_stylesheets);
+                load_stylesheets(&mut document);
+                *d = document;
+                Ok(())
+            }
+            None => Err(PageError::NoDocumentError),
+        }
+    }
+
+    // @media的结果可能改变，需要重新渲染
+    pub fn resize(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+        if self.document.is_none() {
+            return;
+        }
+        if let Err(e) = self.render_document() {
+            error!("failed to restyle on resize; {}", e);
+        }
+    }
+
+    // 在同一个元素的片段之间移动时会失去再获得焦点
+    // 重新渲染等到layout时，焦点所在的元素变化了才进行
+    pub fn set_focus(&mut self, path: Vec<usize>, fragment: usize) {
+        self.context.focused = Some(path);
+        self.focused_fragment = fragment;
+    }
+
+    // 焦点已经移到别的片段时忽略，和on_focus的先后顺序无关
+    pub fn remove_focus(&mut self, path: &[usize], fragment: usize) {
+        if self.context.focused.as_deref() == Some(path) && self.focused_fragment == fragment {
+            self.context.focused = None;
+        }
+    }
+
+    // 获得焦点的元素变化时重新计算样式
+    fn refresh_focus(&mut self) {
+        if self.document.is_none() || self.context.focused == self.rendered_focus {
+            return;
+        }
+        if let Err(e) = self.render_document() {
+            error!("failed to restyle on focus change; {}", e);
+        }
+    }
+
     pub fn render_document(&mut self) -> Result<(), PageError> {
         let document = match &self.document {
             Some(w) => w,
             None => return Err(PageError::NoDocumentError),
         };
         let document = &*document.borrow_mut();
-        let styled: StyledDocument = to_styled_document(document);
-        let layout: LayoutDocument = to_layout_document(styled);
+        self.context.base_url = Url::parse(&document.url).ok();
+        let styled: StyledDocument = to_styled_document(document, &self.viewport, &self.context);
+        let layout: LayoutDocument = to_layout_document(styled, &self.viewport);
 
         self.view = to_element_container(&layout.top_box);
 
+        // 新的view没有焦点，找回之前获得焦点的片段
+        if let Some(ref focused) = self.context.focused {
+            let name = element_view_name(focused, self.focused_fragment);
+            if self.view.focus_view(&Selector::Name(&name)).is_err() {
+                self.context.focused = None;
+            }
+        }
+        self.rendered_focus = self.context.focused.clone();
+
         Ok(())
     }
 
-    fn execute_inline_scripts(&mut self) -> Result<(), PageError> {
-        let scripts = {
-            let document = match &self.document {
-                Some(w) => w,
-                None => return Err(PageError::NoDocumentError),
-            };
-            let document = document.borrow_mut();
-            document.get_script_inners()
-        };
-
-        for script in scripts {
-            match self.js_runtime.execute_script(script.as_str()) {
-                Ok(s) => {
-                    info!("javascript execution succeeded; {}", s);
-                }
-                Err(e) => return Err(PageError::JavaScriptError(e)),
-            };
+    fn execute_script(&mut self, script: &str) -> Result<(), PageError> {
+        match self.js_runtime.execute_script(script) {
+            Ok(s) => {
+                info!("javascript execution succeeded; {}", s);
+                Ok(())
+            }
+            Err(e) => Err(PageError::JavaScriptError(e)),
         }
-        Ok(())
     }
 }
 
 impl ViewWrapper for PageView {
     type V = LinearLayout;
 
+    // set_focus()之后的重新计算样式
+    fn wrap_layout(&mut self, size: Vec2) {
+        self.refresh_focus();
+        self.view.layout(size);
+    }
+
     fn with_view<F, R>(&self, f: F) -> ::std::option::Option<R>
     where
         F: FnOnce(&Self::V) -> R,